@@ -1,5 +1,10 @@
 extern crate winres;
 fn main() {
+    // The resource script only means something to the Win32 front end
+    if std::env::var("CARGO_CFG_TARGET_OS").unwrap() != "windows" {
+        return;
+    }
+
     let mut res = winres::WindowsResource::new();
     res.set_resource_file("resources.rc");
     res.add_toolkit_include(true);
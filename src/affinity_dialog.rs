@@ -0,0 +1,240 @@
+// Set affinity on the task context menu: a checkbox for each logical CPU,
+// ticked for the ones the process may run on.
+
+use std::ffi::c_void;
+
+use widestring::U16CString;
+use windows::{
+    core::{w, PCWSTR},
+    Win32::{
+        Foundation::{HINSTANCE, HWND, LPARAM, POINT, RECT, WPARAM},
+        Graphics::Gdi::ScreenToClient,
+        System::LibraryLoader::GetModuleHandleW,
+        UI::{
+            Controls::{CheckDlgButton, IsDlgButtonChecked, BST_CHECKED},
+            WindowsAndMessaging::*,
+        },
+    },
+};
+
+use crate::{
+    process::Process,
+    resources::{to_pcwstr, FALSE, IDC_AFFINITY_TEXT, IDC_FIRST_CPU, IDD_AFFINITY, TRUE},
+    state, task_list,
+};
+
+const IDOK: usize = windows::Win32::UI::WindowsAndMessaging::IDOK.0 as usize;
+const IDCANCEL: usize = windows::Win32::UI::WindowsAndMessaging::IDCANCEL.0 as usize;
+
+// Where the checkboxes go, in dialog units. resources.rc lays the dialog out
+// for one row of them.
+const CPUS_PER_ROW: i32 = 8;
+const CHECKBOX_LEFT: i32 = 7;
+const CHECKBOX_TOP: i32 = 22;
+const CHECKBOX_WIDTH: i32 = 36;
+const CHECKBOX_HEIGHT: i32 = 10;
+const ROW_HEIGHT: i32 = 12;
+
+// Handed to the dialog through its lParam, and kept in GWLP_USERDATA
+struct AffinityParams {
+    main_window: HWND,
+    pid: u32,
+    image_name: String,
+    num_cpus: u32,
+    // What the process may run on now
+    cpus: Vec<u32>,
+}
+
+pub fn show(main_window: HWND, process: &Process) {
+    let state = unsafe { state::get(main_window) };
+    let result = state.source.borrow_mut().affinity(process.pid);
+    let cpus = match result {
+        Ok(cpus) => cpus,
+        Err(e) => {
+            task_list::show_error(
+                main_window,
+                &format!(
+                    "Couldn't get the CPU affinity of {} ({}): {}",
+                    process.image_name, process.pid, e
+                ),
+            );
+            return;
+        }
+    };
+    let mut params = AffinityParams {
+        main_window,
+        pid: process.pid,
+        image_name: process.image_name.clone(),
+        num_cpus: state.num_cpus,
+        cpus,
+    };
+    unsafe {
+        let instance = HINSTANCE(GetModuleHandleW(None).expect("shouldn't fail").0);
+        let _ = DialogBoxParamW(
+            Some(instance),
+            to_pcwstr(IDD_AFFINITY),
+            Some(main_window),
+            Some(dialog_proc),
+            LPARAM(&raw mut params as isize),
+        );
+    }
+}
+
+unsafe extern "system" fn dialog_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> isize {
+    match msg {
+        WM_INITDIALOG => {
+            SetWindowLongPtrW(hwnd, GWLP_USERDATA, lparam.0);
+            add_checkboxes(hwnd);
+            TRUE
+        }
+        WM_COMMAND if wparam.0 == IDOK => {
+            if apply(hwnd) {
+                let _ = EndDialog(hwnd, IDOK as isize);
+            }
+            TRUE
+        }
+        WM_COMMAND if wparam.0 == IDCANCEL => {
+            let _ = EndDialog(hwnd, IDCANCEL as isize);
+            TRUE
+        }
+        _ => FALSE,
+    }
+}
+
+// Lives on show's stack for as long as the dialog is up
+unsafe fn params<'a>(hwnd: HWND) -> &'a AffinityParams {
+    &*(GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const AffinityParams)
+}
+
+// Dialog units to pixels, going by the dialog's font
+unsafe fn to_pixels(hwnd: HWND, left: i32, top: i32, width: i32, height: i32) -> RECT {
+    let mut rect = RECT {
+        left,
+        top,
+        right: left + width,
+        bottom: top + height,
+    };
+    let _ = MapDialogRect(hwnd, &mut rect);
+    rect
+}
+
+unsafe fn move_down(hwnd: HWND, control: HWND, by: i32) {
+    let mut rect = RECT::default();
+    let _ = GetWindowRect(control, &mut rect);
+    let mut top_left = POINT {
+        x: rect.left,
+        y: rect.top,
+    };
+    let _ = ScreenToClient(hwnd, &mut top_left);
+    let _ = SetWindowPos(
+        control,
+        None,
+        top_left.x,
+        top_left.y + by,
+        0,
+        0,
+        SWP_NOSIZE | SWP_NOZORDER,
+    );
+}
+
+unsafe fn add_checkboxes(hwnd: HWND) {
+    let params = params(hwnd);
+    let text = U16CString::from_str_truncate(format!(
+        "Processors {} ({}) may run on:",
+        params.image_name, params.pid
+    ));
+    let _ = SetDlgItemTextW(hwnd, IDC_AFFINITY_TEXT, PCWSTR(text.as_ptr()));
+
+    let instance = HINSTANCE(GetModuleHandleW(None).expect("shouldn't fail").0);
+    let font = SendMessageW(hwnd, WM_GETFONT, None, None);
+    let style = WS_CHILD | WS_VISIBLE | WS_TABSTOP | WINDOW_STYLE(BS_AUTOCHECKBOX as u32);
+    for cpu in 0..params.num_cpus as i32 {
+        let rect = to_pixels(
+            hwnd,
+            CHECKBOX_LEFT + cpu % CPUS_PER_ROW * CHECKBOX_WIDTH,
+            CHECKBOX_TOP + cpu / CPUS_PER_ROW * ROW_HEIGHT,
+            CHECKBOX_WIDTH,
+            CHECKBOX_HEIGHT,
+        );
+        let label = U16CString::from_str_truncate(format!("CPU {}", cpu));
+        let Ok(checkbox) = CreateWindowExW(
+            WINDOW_EX_STYLE(0),
+            w!("BUTTON"),
+            PCWSTR(label.as_ptr()),
+            style,
+            rect.left,
+            rect.top,
+            rect.right - rect.left,
+            rect.bottom - rect.top,
+            Some(hwnd),
+            Some(HMENU((IDC_FIRST_CPU + cpu) as *mut c_void)),
+            Some(instance),
+            None,
+        ) else {
+            continue;
+        };
+        SendMessageW(
+            checkbox,
+            WM_SETFONT,
+            Some(WPARAM(font.0 as usize)),
+            Some(LPARAM(0)),
+        );
+        if params.cpus.contains(&(cpu as u32)) {
+            let _ = CheckDlgButton(hwnd, IDC_FIRST_CPU + cpu, BST_CHECKED);
+        }
+    }
+
+    // Makes room below the first row for the others
+    let rows = params.num_cpus.div_ceil(CPUS_PER_ROW as u32).max(1) as i32;
+    let extra = to_pixels(hwnd, 0, 0, 0, (rows - 1) * ROW_HEIGHT).bottom;
+    for id in [IDOK, IDCANCEL] {
+        if let Ok(button) = GetDlgItem(Some(hwnd), id as i32) {
+            move_down(hwnd, button, extra);
+        }
+    }
+    let mut window = RECT::default();
+    let _ = GetWindowRect(hwnd, &mut window);
+    let _ = SetWindowPos(
+        hwnd,
+        None,
+        0,
+        0,
+        window.right - window.left,
+        window.bottom - window.top + extra,
+        SWP_NOMOVE | SWP_NOZORDER,
+    );
+}
+
+// Returns false to keep the dialog open, when no CPU is ticked or the
+// change failed
+unsafe fn apply(hwnd: HWND) -> bool {
+    let params = params(hwnd);
+    let cpus: Vec<u32> = (0..params.num_cpus)
+        .filter(|&cpu| IsDlgButtonChecked(hwnd, IDC_FIRST_CPU + cpu as i32) == BST_CHECKED.0)
+        .collect();
+    if cpus.is_empty() {
+        task_list::show_error(hwnd, "Pick at least one processor.");
+        return false;
+    }
+
+    let state = state::get(params.main_window);
+    let result = state.source.borrow_mut().set_affinity(params.pid, &cpus);
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            task_list::show_error(
+                hwnd,
+                &format!(
+                    "Couldn't set the CPU affinity of {} ({}): {}",
+                    params.image_name, params.pid, e
+                ),
+            );
+            false
+        }
+    }
+}
@@ -0,0 +1,530 @@
+// `taskmanager serve` samples this machine every refresh tick and streams the
+// samples to any viewer that connects with --connect. A connection carries
+// newline-terminated records in the snapshot.rs encoding:
+//
+//     agent:  taskmanager-agent version=2 num_cpus=<count>
+//     agent:  tick ...                           every refresh interval
+//     viewer: kill pid=<pid>                     End Task on the agent's machine
+//     agent:  kill-result pid=<pid> [error=<message>]
+//     viewer: memory-mode mode=<mode>            what the memory figures count
+//     agent:  memory-mode mode=<mode>            every later tick counts that
+//
+// Each viewer gets its own sampling thread. Kill results arrive between
+// ticks, never inside one. Either side skips record types it doesn't know.
+// A viewer that hears nothing for STALL_TIMEOUT counts the agent as gone.
+//
+// There is no authentication, so anyone who can reach the listening address
+// can end processes as the user the agent runs as.
+
+use std::{
+    io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Result, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cli::ServeOptions,
+    connection::Connection,
+    environment::Variable,
+    process::{MemoryMode, NativeProcessSource, Priority, Process, ProcessSource},
+    snapshot::{self, Record, Snapshot},
+    system::{self, CpuSampler, SystemStatus},
+    REFRESH_INTERVAL_MS,
+};
+
+const MAGIC: &str = "taskmanager-agent";
+const VERSION: u32 = 2;
+// How long End Task waits for the agent before giving up
+const KILL_TIMEOUT: Duration = Duration::from_secs(5);
+// How long a refresh waits for the next tick, so a slow agent can't hold up
+// the window for longer than about a refresh
+const TICK_TIMEOUT: Duration = Duration::from_millis(2 * REFRESH_INTERVAL_MS as u64);
+// Silence for this long means the agent or the network has stalled, which a
+// half-open connection would otherwise never report
+const STALL_TIMEOUT: Duration = Duration::from_millis(5 * REFRESH_INTERVAL_MS as u64);
+
+pub fn run(options: &ServeOptions) -> Result<()> {
+    let listener = TcpListener::bind(&options.listen)?;
+    eprintln!("taskmanager: listening on {}", listener.local_addr()?);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("taskmanager: failed to accept a viewer: {}", e);
+                continue;
+            }
+        };
+        let peer = stream
+            .peer_addr()
+            .map_or_else(|_| "viewer".to_string(), |addr| addr.to_string());
+        thread::spawn(move || match serve_viewer(stream) {
+            Ok(()) => {}
+            // The viewer going away is how a connection normally ends
+            Err(e) if matches!(e.kind(), ErrorKind::BrokenPipe | ErrorKind::ConnectionReset) => {}
+            Err(e) => eprintln!("taskmanager: {}: {}", peer, e),
+        });
+    }
+    Ok(())
+}
+
+fn serve_viewer(stream: TcpStream) -> Result<()> {
+    let mut source = NativeProcessSource::new();
+    let mut cpu_sampler = CpuSampler::new();
+    let mut out = BufWriter::new(stream.try_clone()?);
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    writeln!(
+        out,
+        "{} version={} num_cpus={}",
+        MAGIC,
+        VERSION,
+        system::get_num_cpus()
+    )?;
+
+    let epoch = Instant::now();
+    let refresh_interval = Duration::from_millis(REFRESH_INTERVAL_MS as u64);
+    let mut next_tick = epoch;
+    let mut request = String::new();
+    loop {
+        let now = Instant::now();
+        if now >= next_tick {
+            let processes = source.sample()?;
+            let system_status = system::get_system_status(&mut cpu_sampler);
+            snapshot::write_snapshot(&mut out, epoch, processes.iter(), &system_status)?;
+            out.flush()?;
+            next_tick = now + refresh_interval;
+            continue;
+        }
+
+        // Wait for requests until the next tick is due. A request cut off
+        // by the timeout stays in `request` and is finished on the next pass.
+        stream.set_read_timeout(Some(next_tick - now))?;
+        match reader.read_line(&mut request) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {
+                let request_kind = handle_request(&Record::parse(&request), &mut source, &mut out)?;
+                request.clear();
+                // Samples the new memory mode now rather than at the next tick
+                if request_kind == RequestKind::MemoryMode {
+                    next_tick = Instant::now();
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                ) => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum RequestKind {
+    Kill,
+    MemoryMode,
+    Unknown,
+}
+
+fn handle_request(
+    request: &Record,
+    source: &mut dyn ProcessSource,
+    out: &mut impl Write,
+) -> Result<RequestKind> {
+    match request.kind.as_str() {
+        "kill" => {
+            let Some(pid) = request.get::<u32>("pid") else {
+                return Ok(RequestKind::Unknown);
+            };
+            match source.kill(pid) {
+                Ok(()) => writeln!(out, "kill-result pid={}", pid)?,
+                Err(e) => writeln!(
+                    out,
+                    "kill-result pid={} error={}",
+                    pid,
+                    snapshot::escape(&e.to_string())
+                )?,
+            }
+            out.flush()?;
+            Ok(RequestKind::Kill)
+        }
+        "memory-mode" => {
+            let Some(mode) = request.get::<MemoryMode>("mode") else {
+                return Ok(RequestKind::Unknown);
+            };
+            source.set_memory_mode(mode);
+            writeln!(out, "memory-mode mode={}", mode.key())?;
+            out.flush()?;
+            Ok(RequestKind::MemoryMode)
+        }
+        _ => Ok(RequestKind::Unknown),
+    }
+}
+
+// A read timing out on the connection means STALL_TIMEOUT passed
+fn stalled(error: Error) -> Error {
+    if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) {
+        return Error::new(ErrorKind::TimedOut, "the agent stopped responding");
+    }
+    error
+}
+
+enum Message {
+    Tick(Snapshot),
+    KillResult { pid: u32, error: Option<String> },
+    MemoryMode,
+}
+
+// Reads the agent's records on a thread of its own so the viewer never waits
+// on the network for anything but the next tick
+fn read_messages(mut reader: BufReader<TcpStream>, messages: Sender<Result<Message>>) {
+    let epoch = Instant::now();
+    loop {
+        let message = match snapshot::read_record(&mut reader) {
+            Ok(None) => return,
+            Ok(Some(record)) if record.kind == "tick" => {
+                snapshot::read_tick(&mut reader, &record, epoch).map(Message::Tick)
+            }
+            Ok(Some(record)) if record.kind == "kill-result" => Ok(Message::KillResult {
+                pid: record.get_or_default("pid"),
+                error: record.get("error"),
+            }),
+            Ok(Some(record)) if record.kind == "memory-mode" => Ok(Message::MemoryMode),
+            Ok(Some(_)) => continue,
+            Err(e) => Err(stalled(e)),
+        };
+        let failed = message.is_err();
+        if messages.send(message).is_err() || failed {
+            return;
+        }
+    }
+}
+
+// Samples streamed from a remote `taskmanager serve`
+pub struct RemoteSource {
+    stream: TcpStream,
+    messages: Receiver<Result<Message>>,
+    num_cpus: u32,
+    system_status: Option<SystemStatus>,
+    // A tick that arrived while End Task was waiting for its answer
+    next_tick: Option<Snapshot>,
+    // Set from a memory mode change until the agent confirms it, since the
+    // ticks before then still count the old mode
+    memory_mode_pending: bool,
+    // Why the connection ended, once it has
+    disconnected: Option<String>,
+}
+
+impl RemoteSource {
+    pub fn connect(address: &str) -> Result<RemoteSource> {
+        let stream = TcpStream::connect(address)?;
+        // Shared with the clones, so the reader thread gives up too
+        stream.set_read_timeout(Some(STALL_TIMEOUT))?;
+        stream.set_write_timeout(Some(STALL_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut header = String::new();
+        reader.read_line(&mut header).map_err(stalled)?;
+
+        let header = Record::parse(&header);
+        if header.kind != MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} is not a taskmanager agent", address),
+            ));
+        }
+        let version = header.get_or_default::<u32>("version");
+        if version != VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported agent version {}", version),
+            ));
+        }
+
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || read_messages(reader, sender));
+        Ok(RemoteSource {
+            stream,
+            messages,
+            num_cpus: header.get::<u32>("num_cpus").unwrap_or(1).max(1),
+            system_status: None,
+            next_tick: None,
+            memory_mode_pending: false,
+            disconnected: None,
+        })
+    }
+
+    // None if `timeout` passes first. After the first error, every call
+    // fails straight away with the reason the connection ended.
+    fn receive(&mut self, timeout: Duration) -> Result<Option<Message>> {
+        if let Some(reason) = &self.disconnected {
+            return Err(Error::new(
+                ErrorKind::NotConnected,
+                format!("disconnected: {}", reason),
+            ));
+        }
+        let message = match self.messages.recv_timeout(timeout) {
+            Ok(Ok(message)) => return Ok(Some(message)),
+            Err(RecvTimeoutError::Timeout) => return Ok(None),
+            Ok(Err(e)) => e,
+            Err(RecvTimeoutError::Disconnected) => Error::new(
+                ErrorKind::ConnectionAborted,
+                "the agent closed the connection",
+            ),
+        };
+        self.disconnected = Some(message.to_string());
+        Err(message)
+    }
+
+    // Ticks sampled in the old memory mode are dropped
+    fn take_tick(&mut self, message: Message) -> Option<Snapshot> {
+        match message {
+            Message::Tick(snapshot) if !self.memory_mode_pending => Some(snapshot),
+            Message::MemoryMode => {
+                self.memory_mode_pending = false;
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+impl ProcessSource for RemoteSource {
+    // The most recent tick the agent has sent, waiting up to TICK_TIMEOUT for
+    // the next one if the last was already shown
+    fn sample(&mut self) -> Result<Vec<Process>> {
+        let mut latest = self.next_tick.take();
+        while let Some(message) = self.receive(Duration::ZERO)? {
+            if let Some(snapshot) = self.take_tick(message) {
+                latest = Some(snapshot);
+            }
+        }
+        let deadline = Instant::now() + TICK_TIMEOUT;
+        let snapshot = loop {
+            if let Some(snapshot) = latest {
+                break snapshot;
+            }
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let Some(message) = self.receive(timeout)? else {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "waiting for the agent's next tick",
+                ));
+            };
+            latest = self.take_tick(message);
+        };
+        self.system_status = Some(snapshot.system_status);
+        Ok(snapshot.processes)
+    }
+
+    fn kill(&mut self, pid: u32) -> Result<()> {
+        writeln!(self.stream, "kill pid={}", pid)?;
+
+        let deadline = Instant::now() + KILL_TIMEOUT;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.receive(timeout)? {
+                Some(Message::Tick(snapshot)) if !self.memory_mode_pending => {
+                    self.next_tick = Some(snapshot)
+                }
+                Some(Message::Tick(_)) => {}
+                Some(Message::MemoryMode) => self.memory_mode_pending = false,
+                Some(Message::KillResult {
+                    pid: result_pid,
+                    error,
+                }) if result_pid == pid => {
+                    return match error {
+                        None => Ok(()),
+                        Some(error) => Err(Error::other(error)),
+                    };
+                }
+                Some(Message::KillResult { .. }) => {}
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::TimedOut,
+                        "the agent didn't answer the End Task request",
+                    ))
+                }
+            }
+        }
+    }
+
+    // A write that fails means the connection is gone, which the next sample
+    // reports
+    fn set_memory_mode(&mut self, mode: MemoryMode) {
+        if writeln!(self.stream, "memory-mode mode={}", mode.key()).is_ok() {
+            self.memory_mode_pending = true;
+            self.next_tick = None;
+        }
+    }
+
+    // The files are on the agent's machine
+    fn open_file_location(&mut self, _process: &Process) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "can't open files on the agent's machine",
+        ))
+    }
+
+    fn set_priority(&mut self, _pid: u32, _priority: Priority) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "the agent doesn't take priority changes",
+        ))
+    }
+
+    fn affinity(&mut self, _pid: u32) -> Result<Vec<u32>> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "the agent doesn't send CPU affinity",
+        ))
+    }
+
+    fn set_affinity(&mut self, _pid: u32, _cpus: &[u32]) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "the agent doesn't take CPU affinity changes",
+        ))
+    }
+
+    fn connections(&mut self) -> Result<Vec<Connection>> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "the agent doesn't send its connections",
+        ))
+    }
+
+    fn environment(&mut self, _pid: u32) -> Result<Vec<Variable>> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "the agent doesn't send environment variables",
+        ))
+    }
+
+    fn num_cpus(&self) -> Option<u32> {
+        Some(self.num_cpus)
+    }
+
+    fn system_status(&self) -> Option<SystemStatus> {
+        self.system_status.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+
+    // An agent on a loopback port that serves one viewer
+    fn start_agent() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let _ = serve_viewer(stream);
+        });
+        address
+    }
+
+    // A fake agent that sends a valid header and then `then` with the
+    // connection
+    fn start_fake_agent(then: fn(TcpStream)) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            writeln!(stream, "{} version={} num_cpus=2", MAGIC, VERSION).unwrap();
+            then(stream);
+        });
+        address
+    }
+
+    #[test]
+    fn samples_over_loopback() {
+        let address = start_agent();
+        let mut source = RemoteSource::connect(&address.to_string()).unwrap();
+        assert_eq!(source.num_cpus(), Some(system::get_num_cpus()));
+
+        let processes = source.sample().unwrap();
+        let own = processes.iter().find(|p| p.pid == std::process::id());
+        assert!(own.is_some(), "the agent's sample includes the test");
+        assert!(source.system_status().is_some());
+
+        // The next sample waits for the agent's next tick
+        let started = Instant::now();
+        source.sample().unwrap();
+        assert!(started.elapsed() <= TICK_TIMEOUT);
+    }
+
+    #[test]
+    fn memory_mode_reaches_the_agent() {
+        let address = start_agent();
+        let mut source = RemoteSource::connect(&address.to_string()).unwrap();
+        source.sample().unwrap();
+
+        source.set_memory_mode(MemoryMode::Pss);
+        let processes = source.sample().unwrap();
+        let own = processes.iter().find(|p| p.pid == std::process::id());
+        assert!(own.unwrap().memory_counters.proportional.is_some());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn kill_over_loopback() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let address = start_agent();
+        let mut source = RemoteSource::connect(&address.to_string()).unwrap();
+        source.kill(child.id()).unwrap();
+        assert!(child.wait().unwrap().signal().is_some());
+    }
+
+    #[test]
+    fn wrong_version() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            writeln!(stream, "{} version=99 num_cpus=2", MAGIC).unwrap();
+        });
+        let error = RemoteSource::connect(&address.to_string()).err().unwrap();
+        assert!(error.to_string().contains("unsupported agent version 99"));
+    }
+
+    // A silent agent costs a refresh TICK_TIMEOUT at most, not forever
+    #[test]
+    fn silent_agent() {
+        let address = start_fake_agent(|stream| {
+            thread::sleep(STALL_TIMEOUT * 2);
+            drop(stream);
+        });
+        let mut source = RemoteSource::connect(&address.to_string()).unwrap();
+        let started = Instant::now();
+        let error = source.sample().err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert!(started.elapsed() < TICK_TIMEOUT + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn agent_going_away() {
+        let address = start_fake_agent(drop);
+        let mut source = RemoteSource::connect(&address.to_string()).unwrap();
+        let error = source.sample().err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::ConnectionAborted);
+
+        // Every later call fails straight away
+        let started = Instant::now();
+        let error = source.sample().err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::NotConnected);
+        assert!(error.to_string().starts_with("disconnected: "));
+        assert!(source.kill(1).is_err());
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,740 @@
+// What an executable says about itself, read straight from the file: the
+// version resource of a PE, the headers and notes of an ELF. Only the parts
+// that are needed get read, since images can be hundreds of megabytes. None
+// of it uses the OS, so a Linux machine can read a Windows binary.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{Error, ErrorKind, Read, Result, Seek, SeekFrom},
+    path::Path,
+    time::SystemTime,
+};
+
+// Fields the format doesn't have, or that couldn't be read, are empty
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BinaryInfo {
+    // PE only, from the version resource
+    pub description: String,
+    pub company: String,
+    pub version: String,
+    // Format, word size and machine, like "ELF 64-bit x86-64" or "PE32+ x64"
+    pub image_type: String,
+    // The Architecture column's text for the image, like "x86-64 (64-bit)"
+    pub architecture: String,
+    // ELF only: the GNU build ID in hex, and the dynamic loader it asks for
+    pub build_id: String,
+    pub interpreter: String,
+}
+
+// How far the parsers will read for a single structure, so a corrupt size
+// can't make them allocate gigabytes
+const MAX_READ: usize = 1 << 20;
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_at(file: &mut (impl Read + Seek), offset: u64, len: usize) -> Result<Vec<u8>> {
+    if len > MAX_READ {
+        return Err(invalid_data("structure too large"));
+    }
+    let mut bytes = vec![0; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn u16_at(bytes: &[u8], at: usize, big_endian: bool) -> Option<u16> {
+    let bytes = bytes.get(at..at + 2)?.try_into().ok()?;
+    Some(if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    })
+}
+
+fn u32_at(bytes: &[u8], at: usize, big_endian: bool) -> Option<u32> {
+    let bytes = bytes.get(at..at + 4)?.try_into().ok()?;
+    Some(if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    })
+}
+
+fn u64_at(bytes: &[u8], at: usize, big_endian: bool) -> Option<u64> {
+    let bytes = bytes.get(at..at + 8)?.try_into().ok()?;
+    Some(if big_endian {
+        u64::from_be_bytes(bytes)
+    } else {
+        u64::from_le_bytes(bytes)
+    })
+}
+
+// PE is always little-endian
+fn le16(bytes: &[u8], at: usize) -> Option<u16> {
+    u16_at(bytes, at, false)
+}
+
+fn le32(bytes: &[u8], at: usize) -> Option<u32> {
+    u32_at(bytes, at, false)
+}
+
+fn truncated() -> Error {
+    invalid_data("truncated header")
+}
+
+pub fn read(path: &Path) -> Result<BinaryInfo> {
+    parse(&mut File::open(path)?)
+}
+
+// Anything that's neither PE nor ELF, like a script, gives empty info
+pub fn parse(file: &mut (impl Read + Seek)) -> Result<BinaryInfo> {
+    let mut magic = [0; 4];
+    file.seek(SeekFrom::Start(0))?;
+    if file.read(&mut magic)? < magic.len() {
+        return Ok(BinaryInfo::default());
+    }
+    match magic {
+        [0x7f, b'E', b'L', b'F'] => parse_elf(file),
+        [b'M', b'Z', ..] => parse_pe(file),
+        _ => Ok(BinaryInfo::default()),
+    }
+}
+
+fn elf_machine_name(machine: u16) -> String {
+    match machine {
+        2 => "SPARC",
+        3 => "x86",
+        8 => "MIPS",
+        20 => "PowerPC",
+        21 => "PowerPC64",
+        22 => "S/390",
+        40 => "ARM",
+        43 => "SPARC V9",
+        62 => "x86-64",
+        183 => "AArch64",
+        243 => "RISC-V",
+        258 => "LoongArch",
+        machine => return format!("machine {}", machine),
+    }
+    .to_string()
+}
+
+const PT_INTERP: u32 = 3;
+const PT_NOTE: u32 = 4;
+const NT_GNU_BUILD_ID: u32 = 3;
+
+// A program header's type, file offset, size in the file and alignment
+struct Segment {
+    kind: u32,
+    offset: u64,
+    size: u64,
+    align: u64,
+}
+
+fn parse_elf(file: &mut (impl Read + Seek)) -> Result<BinaryInfo> {
+    let header = read_at(file, 0, 64)?;
+    let is_64 = match header[4] {
+        1 => false,
+        2 => true,
+        _ => return Err(invalid_data("unknown ELF class")),
+    };
+    let big_endian = header[5] == 2;
+    let u16_at = |at| u16_at(&header, at, big_endian).ok_or_else(truncated);
+    let machine = u16_at(18)?;
+    let (phoff, phentsize, phnum) = if is_64 {
+        let phoff = u64_at(&header, 32, big_endian).ok_or_else(truncated)?;
+        (phoff, u16_at(54)?, u16_at(56)?)
+    } else {
+        let phoff = u32_at(&header, 28, big_endian).ok_or_else(truncated)?;
+        (phoff as u64, u16_at(42)?, u16_at(44)?)
+    };
+
+    // From the class and machine, so an x32 program shows as "x86-64 (32-bit)"
+    let bits = if is_64 { 64 } else { 32 };
+    let mut info = BinaryInfo {
+        image_type: format!("ELF {}-bit {}", bits, elf_machine_name(machine)),
+        architecture: format!("{} ({}-bit)", elf_machine_name(machine), bits),
+        ..BinaryInfo::default()
+    };
+
+    let table = read_at(file, phoff, phentsize as usize * phnum as usize)?;
+    let segments = table
+        .chunks_exact(phentsize.max(1) as usize)
+        .filter_map(|entry| {
+            let u32_at = |at| u32_at(entry, at, big_endian);
+            let u64_at = |at| u64_at(entry, at, big_endian);
+            Some(if is_64 {
+                Segment {
+                    kind: u32_at(0)?,
+                    offset: u64_at(8)?,
+                    size: u64_at(32)?,
+                    align: u64_at(48)?,
+                }
+            } else {
+                Segment {
+                    kind: u32_at(0)?,
+                    offset: u32_at(4)? as u64,
+                    size: u32_at(16)? as u64,
+                    align: u32_at(28)? as u64,
+                }
+            })
+        });
+    for segment in segments {
+        match segment.kind {
+            PT_INTERP => {
+                let interpreter = read_at(file, segment.offset, segment.size as usize)?;
+                let end = interpreter.iter().position(|&b| b == 0);
+                let interpreter = &interpreter[..end.unwrap_or(interpreter.len())];
+                info.interpreter = String::from_utf8_lossy(interpreter).into_owned();
+            }
+            PT_NOTE if info.build_id.is_empty() => {
+                let notes = read_at(file, segment.offset, segment.size as usize)?;
+                if let Some(build_id) = find_build_id(&notes, segment.align, big_endian) {
+                    info.build_id = build_id;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(info)
+}
+
+// Each note is a name size, description size and type, then the name and
+// description, each padded to the segment's alignment of 4 or 8
+fn find_build_id(notes: &[u8], align: u64, big_endian: bool) -> Option<String> {
+    let align = if align == 8 { 8 } else { 4 };
+    let pad = |len: usize| len.next_multiple_of(align);
+    let mut at = 0;
+    while at + 12 <= notes.len() {
+        let name_size = u32_at(notes, at, big_endian)? as usize;
+        let desc_size = u32_at(notes, at + 4, big_endian)? as usize;
+        let kind = u32_at(notes, at + 8, big_endian)?;
+        let name_at = at + 12;
+        let desc_at = name_at + pad(name_size);
+        let name = notes.get(name_at..name_at + name_size)?;
+        let desc = notes.get(desc_at..desc_at + desc_size)?;
+        if kind == NT_GNU_BUILD_ID && name == b"GNU\0" {
+            return Some(desc.iter().map(|byte| format!("{:02x}", byte)).collect());
+        }
+        at = desc_at + pad(desc_size);
+    }
+    None
+}
+
+fn pe_machine_name(machine: u16) -> String {
+    match machine {
+        0x014c => "x86",
+        0x01c0 | 0x01c4 => "ARM",
+        0x0200 => "IA-64",
+        0x8664 => "x64",
+        0xaa64 => "ARM64",
+        machine => return format!("machine {:#06x}", machine),
+    }
+    .to_string()
+}
+
+// The Architecture column's text for a PE machine type, like "x86 (32-bit)"
+pub fn pe_architecture(machine: u16) -> String {
+    let name = pe_machine_name(machine);
+    match machine {
+        0x014c | 0x01c0 | 0x01c4 => format!("{} (32-bit)", name),
+        0x0200 | 0x8664 | 0xaa64 => format!("{} (64-bit)", name),
+        _ => name,
+    }
+}
+
+const RT_VERSION: u32 = 16;
+const IMAGE_DIRECTORY_ENTRY_RESOURCE: usize = 2;
+
+// Where a section's data is in memory and in the file
+struct Section {
+    virtual_address: u32,
+    virtual_size: u32,
+    raw_offset: u32,
+    raw_size: u32,
+}
+
+fn rva_to_offset(sections: &[Section], rva: u32) -> Option<u64> {
+    sections.iter().find_map(|section| {
+        let size = section.virtual_size.max(section.raw_size);
+        let offset = rva.checked_sub(section.virtual_address)?;
+        (offset < size).then_some(section.raw_offset as u64 + offset as u64)
+    })
+}
+
+fn parse_pe(file: &mut (impl Read + Seek)) -> Result<BinaryInfo> {
+    let dos_header = read_at(file, 0, 64)?;
+    let pe_offset = le32(&dos_header, 0x3c).ok_or_else(truncated)? as u64;
+    // Signature, file header and as much of the optional header as is read
+    let headers = read_at(file, pe_offset, 4 + 20 + 240)?;
+    if &headers[..4] != b"PE\0\0" {
+        return Err(invalid_data("missing PE signature"));
+    }
+    let machine = le16(&headers, 4).ok_or_else(truncated)?;
+    let num_sections = le16(&headers, 6).ok_or_else(truncated)?;
+    let optional_size = le16(&headers, 20).ok_or_else(truncated)?;
+    let optional = &headers[24..];
+    let (is_pe32_plus, directories_at) = match le16(optional, 0) {
+        Some(0x10b) => (false, 96),
+        Some(0x20b) => (true, 112),
+        _ => return Err(invalid_data("unknown optional header")),
+    };
+
+    let mut info = BinaryInfo {
+        image_type: format!(
+            "{} {}",
+            if is_pe32_plus { "PE32+" } else { "PE32" },
+            pe_machine_name(machine)
+        ),
+        architecture: pe_architecture(machine),
+        ..BinaryInfo::default()
+    };
+
+    let num_directories = le32(optional, directories_at - 4).unwrap_or(0) as usize;
+    if num_directories <= IMAGE_DIRECTORY_ENTRY_RESOURCE {
+        return Ok(info);
+    }
+    let resource_rva = le32(
+        optional,
+        directories_at + IMAGE_DIRECTORY_ENTRY_RESOURCE * 8,
+    )
+    .ok_or_else(truncated)?;
+    if resource_rva == 0 {
+        return Ok(info);
+    }
+
+    let sections_at = pe_offset + 24 + optional_size as u64;
+    let table = read_at(file, sections_at, num_sections as usize * 40)?;
+    let sections: Vec<Section> = table
+        .chunks_exact(40)
+        .filter_map(|entry| {
+            Some(Section {
+                virtual_size: le32(entry, 8)?,
+                virtual_address: le32(entry, 12)?,
+                raw_size: le32(entry, 16)?,
+                raw_offset: le32(entry, 20)?,
+            })
+        })
+        .collect();
+    let resources = rva_to_offset(&sections, resource_rva)
+        .ok_or_else(|| invalid_data("resource directory outside every section"))?;
+
+    if let Some(version_info) = find_version_resource(file, &sections, resources)? {
+        read_version_info(&version_info, &mut info);
+    }
+    Ok(info)
+}
+
+// Resources are a three level tree: type, then name, then language. The
+// version resource is the first name and language under RT_VERSION.
+fn find_version_resource(
+    file: &mut (impl Read + Seek),
+    sections: &[Section],
+    resources: u64,
+) -> Result<Option<Vec<u8>>> {
+    let mut directory = 0;
+    for level in 0..3 {
+        let header = read_at(file, resources + directory as u64, 16)?;
+        let num_entries = le16(&header, 12).ok_or_else(truncated)? as usize
+            + le16(&header, 14).ok_or_else(truncated)? as usize;
+        let entries = read_at(file, resources + directory as u64 + 16, num_entries * 8)?;
+        let entry = entries.chunks_exact(8).find(|entry| {
+            // Named entries have the high bit set, so never match an ID
+            level > 0 || le32(entry, 0) == Some(RT_VERSION)
+        });
+        let Some(entry) = entry else {
+            return Ok(None);
+        };
+        let offset = le32(entry, 4).ok_or_else(truncated)?;
+        // The high bit marks another directory rather than data
+        let is_directory = offset & 0x8000_0000 != 0;
+        if is_directory != (level < 2) {
+            return Ok(None);
+        }
+        directory = offset & 0x7fff_ffff;
+    }
+
+    let data_entry = read_at(file, resources + directory as u64, 8)?;
+    let rva = le32(&data_entry, 0).ok_or_else(truncated)?;
+    let size = le32(&data_entry, 4).ok_or_else(truncated)?;
+    let Some(offset) = rva_to_offset(sections, rva) else {
+        return Ok(None);
+    };
+    read_at(file, offset, size as usize).map(Some)
+}
+
+// One node of a VS_VERSIONINFO tree: a length, value length and type, a
+// UTF-16 key, then the value and the children, each aligned to 4 bytes
+struct VersionNode<'a> {
+    key: String,
+    value: &'a [u8],
+    children: &'a [u8],
+}
+
+fn version_node(bytes: &[u8]) -> Option<(VersionNode<'_>, usize)> {
+    let length = le16(bytes, 0)? as usize;
+    let value_length = le16(bytes, 2)? as usize;
+    let is_text = le16(bytes, 4)? == 1;
+    let node = bytes.get(..length).filter(|node| node.len() >= 6)?;
+
+    let key_words: Vec<u16> = node[6..]
+        .chunks_exact(2)
+        .map(|word| u16::from_le_bytes([word[0], word[1]]))
+        .take_while(|&word| word != 0)
+        .collect();
+    let value_at = (6 + (key_words.len() + 1) * 2).next_multiple_of(4);
+    // Text values are counted in UTF-16 words rather than bytes
+    let value_size = if is_text {
+        value_length * 2
+    } else {
+        value_length
+    };
+    let value = node
+        .get(value_at..value_at + value_size)
+        .unwrap_or_default();
+    let children_at = (value_at + value_size).next_multiple_of(4).min(length);
+    let node = VersionNode {
+        key: String::from_utf16_lossy(&key_words),
+        value,
+        children: &node[children_at..],
+    };
+    Some((node, length.next_multiple_of(4)))
+}
+
+fn version_children(bytes: &[u8]) -> impl Iterator<Item = VersionNode<'_>> {
+    let mut rest = bytes;
+    std::iter::from_fn(move || {
+        let (node, size) = version_node(rest)?;
+        rest = rest.get(size..).unwrap_or_default();
+        (size > 0).then_some(node)
+    })
+}
+
+fn utf16_text(bytes: &[u8]) -> String {
+    let words: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|word| u16::from_le_bytes([word[0], word[1]]))
+        .take_while(|&word| word != 0)
+        .collect();
+    String::from_utf16_lossy(&words).trim().to_string()
+}
+
+const VS_FFI_SIGNATURE: u32 = 0xfeef_04bd;
+
+// The fixed part's file version is always numeric, so it's preferred over
+// the FileVersion string, which can be anything
+fn read_version_info(bytes: &[u8], info: &mut BinaryInfo) {
+    let Some((root, _)) = version_node(bytes) else {
+        return;
+    };
+    if le32(root.value, 0) == Some(VS_FFI_SIGNATURE) {
+        if let (Some(ms), Some(ls)) = (le32(root.value, 8), le32(root.value, 12)) {
+            info.version = format!("{}.{}.{}.{}", ms >> 16, ms & 0xffff, ls >> 16, ls & 0xffff);
+        }
+    }
+
+    // The first string table is the one Explorer shows too
+    let strings = version_children(root.children)
+        .filter(|node| node.key == "StringFileInfo")
+        .find_map(|node| version_children(node.children).next());
+    let Some(strings) = strings else {
+        return;
+    };
+    for string in version_children(strings.children) {
+        let text = utf16_text(string.value);
+        match string.key.as_str() {
+            "FileDescription" => info.description = text,
+            "CompanyName" => info.company = text,
+            "FileVersion" if info.version.is_empty() => info.version = text,
+            _ => {}
+        }
+    }
+}
+
+// Keeps what each path held the last time it was read, so a sample only
+// reads the files that are new or have changed since. The path is normally
+// an image path, or /proc/<pid>/exe for an image that has been deleted.
+#[derive(Default)]
+pub struct BinaryCache {
+    entries: HashMap<String, (Option<SystemTime>, BinaryInfo)>,
+}
+
+impl BinaryCache {
+    pub fn get(&mut self, image_path: &str) -> BinaryInfo {
+        if image_path.is_empty() {
+            return BinaryInfo::default();
+        }
+        let modified = fs::metadata(image_path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if let Some((cached_modified, info)) = self.entries.get(image_path) {
+            if *cached_modified == modified {
+                return info.clone();
+            }
+        }
+        // Unreadable and malformed files are remembered too, so they aren't
+        // tried again every sample
+        let info = read(Path::new(image_path)).unwrap_or_default();
+        self.entries
+            .insert(image_path.to_string(), (modified, info.clone()));
+        info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn put(bytes: &mut Vec<u8>, at: usize, value: &[u8]) {
+        if bytes.len() < at + value.len() {
+            bytes.resize(at + value.len(), 0);
+        }
+        bytes[at..at + value.len()].copy_from_slice(value);
+    }
+
+    const INTERPRETER: &[u8] = b"/lib64/ld-linux-x86-64.so.2\0";
+
+    // A 64-bit little-endian x86-64 executable with an interpreter and a GNU
+    // build ID note, and nothing else
+    fn elf64() -> Vec<u8> {
+        let mut elf = Vec::new();
+        put(&mut elf, 0, &[0x7f, b'E', b'L', b'F', 2, 1, 1]);
+        put(&mut elf, 18, &62u16.to_le_bytes());
+        put(&mut elf, 32, &64u64.to_le_bytes());
+        put(&mut elf, 54, &56u16.to_le_bytes());
+        put(&mut elf, 56, &2u16.to_le_bytes());
+
+        let interp_at = 64 + 2 * 56;
+        let note_at = interp_at + INTERPRETER.len().next_multiple_of(4);
+        let mut note = Vec::new();
+        note.extend(4u32.to_le_bytes());
+        note.extend(4u32.to_le_bytes());
+        note.extend(NT_GNU_BUILD_ID.to_le_bytes());
+        note.extend(b"GNU\0");
+        note.extend([0xde, 0xad, 0xbe, 0xef]);
+
+        for (index, (kind, offset, size)) in [
+            (PT_INTERP, interp_at, INTERPRETER.len()),
+            (PT_NOTE, note_at, note.len()),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let entry = 64 + index * 56;
+            put(&mut elf, entry, &kind.to_le_bytes());
+            put(&mut elf, entry + 8, &(offset as u64).to_le_bytes());
+            put(&mut elf, entry + 32, &(size as u64).to_le_bytes());
+            put(&mut elf, entry + 48, &4u64.to_le_bytes());
+        }
+        put(&mut elf, interp_at, INTERPRETER);
+        put(&mut elf, note_at, &note);
+        elf
+    }
+
+    #[test]
+    fn elf_64_bit() {
+        let info = parse(&mut Cursor::new(elf64())).unwrap();
+        assert_eq!(info.image_type, "ELF 64-bit x86-64");
+        assert_eq!(info.architecture, "x86-64 (64-bit)");
+        assert_eq!(info.interpreter, "/lib64/ld-linux-x86-64.so.2");
+        assert_eq!(info.build_id, "deadbeef");
+        assert_eq!(info.version, "");
+    }
+
+    #[test]
+    fn elf_32_bit_big_endian() {
+        let mut elf = Vec::new();
+        put(&mut elf, 0, &[0x7f, b'E', b'L', b'F', 1, 2, 1]);
+        put(&mut elf, 18, &8u16.to_be_bytes());
+        put(&mut elf, 42, &32u16.to_be_bytes());
+        put(&mut elf, 63, &[0]);
+        let info = parse(&mut Cursor::new(elf)).unwrap();
+        assert_eq!(info.image_type, "ELF 32-bit MIPS");
+        assert_eq!(info.architecture, "MIPS (32-bit)");
+        assert_eq!(info.build_id, "");
+    }
+
+    #[test]
+    fn truncated_elf() {
+        let mut elf = elf64();
+        elf.truncate(100);
+        assert!(parse(&mut Cursor::new(elf)).is_err());
+    }
+
+    #[test]
+    fn neither_elf_nor_pe() {
+        let info = parse(&mut Cursor::new(b"#!/bin/sh\necho hi\n".to_vec())).unwrap();
+        assert_eq!(info, BinaryInfo::default());
+        let info = parse(&mut Cursor::new(b"MZ".to_vec())).unwrap();
+        assert_eq!(info, BinaryInfo::default());
+    }
+
+    fn utf16(text: &str) -> Vec<u8> {
+        text.encode_utf16()
+            .chain([0])
+            .flat_map(u16::to_le_bytes)
+            .collect()
+    }
+
+    // One VS_VERSIONINFO node, laid out the way the resource compiler does
+    fn version_node(key: &str, value: &[u8], is_text: bool, children: &[Vec<u8>]) -> Vec<u8> {
+        let mut node = vec![0; 6];
+        node.extend(utf16(key));
+        node.resize(node.len().next_multiple_of(4), 0);
+        node.extend(value);
+        for child in children {
+            node.resize(node.len().next_multiple_of(4), 0);
+            node.extend(child);
+        }
+        let value_length = if is_text {
+            value.len() / 2
+        } else {
+            value.len()
+        };
+        let length = node.len() as u16;
+        put(&mut node, 0, &length.to_le_bytes());
+        put(&mut node, 2, &(value_length as u16).to_le_bytes());
+        put(&mut node, 4, &(is_text as u16).to_le_bytes());
+        node
+    }
+
+    fn version_info(with_fixed_info: bool) -> Vec<u8> {
+        let mut fixed_info = Vec::new();
+        if with_fixed_info {
+            fixed_info = vec![0; 52];
+            put(&mut fixed_info, 0, &VS_FFI_SIGNATURE.to_le_bytes());
+            put(&mut fixed_info, 8, &((1u32 << 16) | 2).to_le_bytes());
+            put(&mut fixed_info, 12, &((3u32 << 16) | 4).to_le_bytes());
+        }
+        let string = |key, text| version_node(key, &utf16(text), true, &[]);
+        let table = version_node(
+            "040904b0",
+            &[],
+            true,
+            &[
+                string("CompanyName", "Example Corp"),
+                string("FileDescription", " Example Tool "),
+                string("FileVersion", "9.9 (release)"),
+            ],
+        );
+        let strings = version_node("StringFileInfo", &[], true, &[table]);
+        let translations = version_node("VarFileInfo", &[], true, &[]);
+        version_node(
+            "VS_VERSION_INFO",
+            &fixed_info,
+            false,
+            &[translations, strings],
+        )
+    }
+
+    const SECTION_RVA: u32 = 0x1000;
+    const SECTION_OFFSET: usize = 0x200;
+
+    // A PE32+ x64 image whose only section holds a resource tree with one
+    // version resource
+    fn pe(version_info: &[u8]) -> Vec<u8> {
+        let mut pe = Vec::new();
+        put(&mut pe, 0, b"MZ");
+        put(&mut pe, 0x3c, &0x40u32.to_le_bytes());
+        put(&mut pe, 0x40, b"PE\0\0");
+        put(&mut pe, 0x44, &0x8664u16.to_le_bytes());
+        put(&mut pe, 0x46, &1u16.to_le_bytes());
+        put(&mut pe, 0x54, &240u16.to_le_bytes());
+        let optional = 0x58;
+        put(&mut pe, optional, &0x20bu16.to_le_bytes());
+        put(&mut pe, optional + 108, &16u32.to_le_bytes());
+        put(
+            &mut pe,
+            optional + 112 + IMAGE_DIRECTORY_ENTRY_RESOURCE * 8,
+            &SECTION_RVA.to_le_bytes(),
+        );
+
+        // Type, name and language directories, then the data entry
+        let mut resources = Vec::new();
+        for (level, (id, next)) in [
+            (RT_VERSION, 0x8000_0018u32),
+            (1, 0x8000_0030),
+            (0x409, 0x48),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let directory = level * 0x18;
+            put(&mut resources, directory + 14, &1u16.to_le_bytes());
+            put(&mut resources, directory + 16, &id.to_le_bytes());
+            put(&mut resources, directory + 20, &next.to_le_bytes());
+        }
+        let data_at = 0x58;
+        put(&mut resources, 0x48, &(SECTION_RVA + data_at).to_le_bytes());
+        put(
+            &mut resources,
+            0x4c,
+            &(version_info.len() as u32).to_le_bytes(),
+        );
+        put(&mut resources, data_at as usize, version_info);
+
+        let section = optional + 240;
+        put(&mut pe, section, b".rsrc\0\0\0");
+        put(
+            &mut pe,
+            section + 8,
+            &(resources.len() as u32).to_le_bytes(),
+        );
+        put(&mut pe, section + 12, &SECTION_RVA.to_le_bytes());
+        put(
+            &mut pe,
+            section + 16,
+            &(resources.len() as u32).to_le_bytes(),
+        );
+        put(
+            &mut pe,
+            section + 20,
+            &(SECTION_OFFSET as u32).to_le_bytes(),
+        );
+        put(&mut pe, SECTION_OFFSET, &resources);
+        pe
+    }
+
+    #[test]
+    fn pe_version_resource() {
+        let info = parse(&mut Cursor::new(pe(&version_info(true)))).unwrap();
+        assert_eq!(info.image_type, "PE32+ x64");
+        assert_eq!(info.architecture, "x64 (64-bit)");
+        assert_eq!(info.company, "Example Corp");
+        assert_eq!(info.description, "Example Tool");
+        // The fixed part's numeric version wins over the FileVersion string
+        assert_eq!(info.version, "1.2.3.4");
+    }
+
+    #[test]
+    fn pe_without_fixed_file_info() {
+        let info = parse(&mut Cursor::new(pe(&version_info(false)))).unwrap();
+        assert_eq!(info.version, "9.9 (release)");
+        assert_eq!(info.company, "Example Corp");
+    }
+
+    #[test]
+    fn pe_without_resources() {
+        let mut image = pe(&version_info(true));
+        put(
+            &mut image,
+            0x58 + 112 + IMAGE_DIRECTORY_ENTRY_RESOURCE * 8,
+            &0u32.to_le_bytes(),
+        );
+        let info = parse(&mut Cursor::new(image)).unwrap();
+        assert_eq!(info.image_type, "PE32+ x64");
+        assert_eq!(info.version, "");
+    }
+
+    #[test]
+    fn pe_architectures() {
+        assert_eq!(pe_architecture(0x014c), "x86 (32-bit)");
+        assert_eq!(pe_architecture(0xaa64), "ARM64 (64-bit)");
+        assert_eq!(pe_architecture(0x1234), "machine 0x1234");
+    }
+}
@@ -0,0 +1,383 @@
+use std::{
+    io::{Error, ErrorKind, Result},
+    path::PathBuf,
+    slice::Iter,
+};
+
+use crate::{
+    agent::RemoteSource,
+    column::{self, Column, COLUMNS},
+    process::{MemoryMode, NativeProcessSource, ProcessSource},
+    recording::{Recorder, ReplaySource},
+    sort::{SortKey, SortState},
+};
+
+pub const USAGE: &str = "\
+usage: taskmanager [COMMAND] [SOURCE OPTIONS]
+
+Without a command, opens the task manager window.
+
+commands:
+    list [--sort COLUMN] [--desc] [--columns COLUMN,...] [--tree] [--filter TEXT]
+         [--mine] [--elevated] [--users] [--connections] [--memory MODE]
+         [--json | --csv]
+        print the process list once and exit. COLUMN is one of name, pid,
+        cpu, memory, working_set, private_working_set, peak_working_set,
+        commit, virtual_size, page_faults, threads, handles, io_read,
+        io_write, io_total, ports, start_time, running_for, status, priority,
+        io_priority, scheduling, last_cpu, user, container, architecture,
+        security, description, company, version, image_type, build_id,
+        interpreter, path or command.
+        --columns picks the columns to print, in that order, or all of them
+        with --columns all. The table shows name, pid, cpu, memory and user
+        by default, JSON and CSV every column.
+        --tree puts children under their parents, with CPU and memory
+        totals for each subtree. --filter keeps processes whose name or
+        command line contains TEXT, --mine those of the current user, and
+        --elevated those running elevated, or as root or with capabilities
+        on Linux. --users prints CPU and memory totals for each user
+        instead, and --connections the TCP and UDP sockets with the
+        processes that own them. --memory picks what the memory figures
+        count: private (the default), rss, pss or uss.
+    tui
+        full-screen process list for terminals
+    serve --listen ADDRESS:PORT
+        stream this machine's processes to viewers started with --connect.
+        Anyone who can reach the address can end processes, so keep it on
+        localhost or a trusted network.
+    help
+        print this message
+
+source options, accepted with or without a command:
+    --record FILE
+        save every refresh tick to FILE
+    --replay FILE
+        show the ticks saved by --record instead of live processes
+    --connect ADDRESS:PORT
+        show the processes of the machine running 'taskmanager serve'";
+
+pub enum Command {
+    Gui(SourceOptions),
+    Help,
+    List(ListOptions),
+    Serve(ServeOptions),
+    Tui(SourceOptions),
+}
+
+pub enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+pub struct ListOptions {
+    pub sort_state: SortState,
+    pub columns: Vec<&'static Column>,
+    pub tree: bool,
+    pub filter: String,
+    pub mine: bool,
+    pub elevated: bool,
+    pub users: bool,
+    pub connections: bool,
+    pub memory_mode: MemoryMode,
+    pub format: OutputFormat,
+    pub source: SourceOptions,
+}
+
+pub struct ServeOptions {
+    pub listen: String,
+}
+
+// Where samples come from and whether they are recorded
+#[derive(Default)]
+pub struct SourceOptions {
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub connect: Option<String>,
+}
+
+impl SourceOptions {
+    // Returns false if `arg` isn't a source option
+    fn parse_arg(&mut self, arg: &str, args: &mut Iter<String>) -> Result<bool> {
+        match arg {
+            "--record" => self.record = Some(PathBuf::from(option_value(arg, args)?)),
+            "--replay" => self.replay = Some(PathBuf::from(option_value(arg, args)?)),
+            "--connect" => self.connect = Some(option_value(arg, args)?.clone()),
+            _ => return Ok(false),
+        }
+        if self.replay.is_some() && self.connect.is_some() {
+            return Err(invalid_args(
+                "--replay and --connect can't be used together".to_string(),
+            ));
+        }
+        Ok(true)
+    }
+
+    // False if samples arrive at their own pace rather than being taken on
+    // this machine whenever they are asked for
+    pub fn is_local(&self) -> bool {
+        self.replay.is_none() && self.connect.is_none()
+    }
+
+    pub fn open_source(&self) -> Result<Box<dyn ProcessSource>> {
+        match (&self.replay, &self.connect) {
+            (Some(path), _) => Ok(Box::new(ReplaySource::open(path)?)),
+            (None, Some(address)) => Ok(Box::new(RemoteSource::connect(address)?)),
+            (None, None) => Ok(Box::new(NativeProcessSource::new())),
+        }
+    }
+
+    pub fn open_recorder(&self, num_cpus: u32) -> Result<Option<Recorder>> {
+        self.record
+            .as_ref()
+            .map(|path| Recorder::create(path, num_cpus))
+            .transpose()
+    }
+}
+
+fn invalid_args(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+fn unknown_option(option: &str) -> Error {
+    invalid_args(format!("unknown option '{}'", option))
+}
+
+fn option_value<'a>(option: &str, args: &mut Iter<'a, String>) -> Result<&'a String> {
+    args.next()
+        .ok_or_else(|| invalid_args(format!("{} needs a value", option)))
+}
+
+fn parse_sort_key(value: Option<&String>) -> Result<SortKey> {
+    let value = value.ok_or_else(|| invalid_args("--sort needs a value".to_string()))?;
+    column::find(value)
+        .map(|column| column.sort_key)
+        .ok_or_else(|| invalid_args(format!("unknown sort key '{}'", value)))
+}
+
+fn parse_list_options(args: &[String]) -> Result<ListOptions> {
+    let mut sort_key = SortKey::Name;
+    let mut descending = false;
+    let mut columns = None;
+    let mut tree = false;
+    let mut filter = String::new();
+    let mut mine = false;
+    let mut elevated = false;
+    let mut users = false;
+    let mut connections = false;
+    let mut memory_mode = MemoryMode::default();
+    let mut format = OutputFormat::Table;
+    let mut source = SourceOptions::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sort" => sort_key = parse_sort_key(args.next())?,
+            "--desc" => descending = true,
+            "--columns" => {
+                columns =
+                    Some(column::parse_list(option_value(arg, &mut args)?).map_err(invalid_args)?)
+            }
+            "--tree" => tree = true,
+            "--filter" => filter = option_value(arg, &mut args)?.clone(),
+            "--mine" => mine = true,
+            "--elevated" => elevated = true,
+            "--users" => users = true,
+            "--connections" => connections = true,
+            "--memory" => {
+                let value = option_value(arg, &mut args)?;
+                memory_mode = value
+                    .parse()
+                    .map_err(|_| invalid_args(format!("unknown memory mode '{}'", value)))?;
+            }
+            "--json" => format = OutputFormat::Json,
+            "--csv" => format = OutputFormat::Csv,
+            other => {
+                if !source.parse_arg(other, &mut args)? {
+                    return Err(unknown_option(other));
+                }
+            }
+        }
+    }
+
+    let sort_state = if descending {
+        SortState::SortDown(sort_key)
+    } else {
+        SortState::SortUp(sort_key)
+    };
+    let columns = columns.unwrap_or_else(|| match format {
+        OutputFormat::Table => column::default_columns(),
+        OutputFormat::Json | OutputFormat::Csv => COLUMNS.iter().collect(),
+    });
+    Ok(ListOptions {
+        sort_state,
+        columns,
+        tree,
+        filter,
+        mine,
+        elevated,
+        users,
+        connections,
+        memory_mode,
+        format,
+        source,
+    })
+}
+
+fn parse_serve_options(args: &[String]) -> Result<ServeOptions> {
+    let mut listen = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = Some(option_value(arg, &mut args)?.clone()),
+            other => return Err(unknown_option(other)),
+        }
+    }
+    let listen = listen.ok_or_else(|| invalid_args("serve needs --listen".to_string()))?;
+    Ok(ServeOptions { listen })
+}
+
+fn parse_source_options(args: &[String]) -> Result<SourceOptions> {
+    let mut source = SourceOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !source.parse_arg(arg, &mut args)? {
+            return Err(unknown_option(arg));
+        }
+    }
+    Ok(source)
+}
+
+pub fn parse(args: &[String]) -> Result<Command> {
+    match args.first().map(String::as_str) {
+        Some("help" | "--help" | "-h") => Ok(Command::Help),
+        None => Ok(Command::Gui(SourceOptions::default())),
+        Some(option) if option.starts_with("--") => Ok(Command::Gui(parse_source_options(args)?)),
+        Some("list") => Ok(Command::List(parse_list_options(&args[1..])?)),
+        Some("serve") => Ok(Command::Serve(parse_serve_options(&args[1..])?)),
+        Some("tui") => Ok(Command::Tui(parse_source_options(&args[1..])?)),
+        Some(other) => Err(invalid_args(format!("unknown command '{}'", other))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn error(args: &[String]) -> String {
+        match parse(args) {
+            Ok(_) => panic!("{:?} should have been rejected", args),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn commands() {
+        assert!(matches!(parse(&[]), Ok(Command::Gui(_))));
+        for help in ["help", "--help", "-h"] {
+            assert!(matches!(parse(&args(&[help])), Ok(Command::Help)));
+        }
+        assert_eq!(error(&args(&["show"])), "unknown command 'show'");
+    }
+
+    #[test]
+    fn list_options() {
+        let command = parse(&args(&["list", "--sort", "cpu", "--desc", "--csv"]));
+        let Ok(Command::List(options)) = command else {
+            panic!("expected a list command");
+        };
+        assert!(matches!(
+            options.sort_state,
+            SortState::SortDown(SortKey::Cpu)
+        ));
+        assert!(matches!(options.format, OutputFormat::Csv));
+    }
+
+    #[test]
+    fn bad_list_options() {
+        assert_eq!(error(&args(&["list", "--all"])), "unknown option '--all'");
+        assert_eq!(error(&args(&["list", "--sort"])), "--sort needs a value");
+        assert_eq!(
+            error(&args(&["list", "--sort", "size"])),
+            "unknown sort key 'size'"
+        );
+    }
+
+    #[test]
+    fn source_options() {
+        let command = parse(&args(&["tui", "--record", "ticks.txt"]));
+        let Ok(Command::Tui(source)) = command else {
+            panic!("expected a tui command");
+        };
+        assert_eq!(source.record, Some(PathBuf::from("ticks.txt")));
+
+        let command = parse(&args(&["--replay", "ticks.txt"]));
+        let Ok(Command::Gui(source)) = command else {
+            panic!("expected the window");
+        };
+        assert_eq!(source.replay, Some(PathBuf::from("ticks.txt")));
+
+        let command = parse(&args(&["list", "--json", "--replay", "ticks.txt"]));
+        let Ok(Command::List(options)) = command else {
+            panic!("expected a list command");
+        };
+        assert_eq!(options.source.replay, Some(PathBuf::from("ticks.txt")));
+    }
+
+    #[test]
+    fn bad_source_options() {
+        assert_eq!(error(&args(&["--record"])), "--record needs a value");
+        assert_eq!(
+            error(&args(&["list", "--replay"])),
+            "--replay needs a value"
+        );
+        assert_eq!(
+            error(&args(&["tui", "--sort", "cpu"])),
+            "unknown option '--sort'"
+        );
+    }
+
+    #[test]
+    fn connect() {
+        let command = parse(&args(&["list", "--connect", "server:7070"]));
+        let Ok(Command::List(options)) = command else {
+            panic!("expected a list command");
+        };
+        assert_eq!(options.source.connect.as_deref(), Some("server:7070"));
+        assert!(!options.source.is_local());
+
+        assert_eq!(
+            error(&args(&[
+                "--replay",
+                "ticks.txt",
+                "--connect",
+                "server:7070"
+            ])),
+            "--replay and --connect can't be used together"
+        );
+        assert_eq!(
+            error(&args(&[
+                "tui",
+                "--connect",
+                "server:7070",
+                "--replay",
+                "ticks.txt"
+            ])),
+            "--replay and --connect can't be used together"
+        );
+    }
+
+    #[test]
+    fn serve() {
+        let command = parse(&args(&["serve", "--listen", "127.0.0.1:7070"]));
+        let Ok(Command::Serve(options)) = command else {
+            panic!("expected a serve command");
+        };
+        assert_eq!(options.listen, "127.0.0.1:7070");
+        assert_eq!(error(&args(&["serve"])), "serve needs --listen");
+    }
+}
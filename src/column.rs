@@ -0,0 +1,654 @@
+// The process list columns, in display order. The window, the terminal
+// interface and `taskmanager list` all offer the same ones, and show the few
+// in DEFAULT_KEYS until others are picked.
+
+use std::time::UNIX_EPOCH;
+
+use human_bytes::human_bytes;
+
+use crate::{
+    process::{self, MemoryMode},
+    rows::Row,
+    sort::SortKey,
+    system,
+};
+
+// A cell as machine-readable output (JSON, CSV) sees it
+pub enum Value {
+    Number(u64),
+    Text(String),
+    // Not known for this process
+    Empty,
+}
+
+pub struct Column {
+    pub title: &'static str,
+    // --sort and --columns value, and JSON/CSV field name
+    pub key: &'static str,
+    pub sort_key: SortKey,
+    pub align_right: bool,
+    // In pixels in the window
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub window_width: i32,
+    // In characters in the terminal. None takes whatever width the fixed
+    // columns leave over.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub terminal_width: Option<usize>,
+    pub text: fn(&Row) -> String,
+    pub value: fn(&Row) -> Value,
+}
+
+// Enough to tell processes apart and see what they cost. Showing every column
+// leaves each too narrow to read.
+const DEFAULT_KEYS: [&str; 5] = ["name", "pid", "cpu", "memory", "user"];
+
+pub const COLUMNS: [Column; 35] = [
+    Column {
+        title: "Name",
+        key: "name",
+        sort_key: SortKey::Name,
+        align_right: false,
+        window_width: 400,
+        terminal_width: None,
+        text: Row::name,
+        value: |r| Value::Text(r.process.image_name.clone()),
+    },
+    Column {
+        title: "PID",
+        key: "pid",
+        sort_key: SortKey::Pid,
+        align_right: false,
+        window_width: 50,
+        terminal_width: Some(7),
+        text: |r| r.process.pid.to_string(),
+        value: |r| Value::Number(r.process.pid as u64),
+    },
+    Column {
+        title: "CPU",
+        key: "cpu",
+        sort_key: SortKey::Cpu,
+        align_right: true,
+        window_width: 50,
+        terminal_width: Some(5),
+        text: |r| r.cpu_usage.to_string(),
+        value: |r| Value::Number(r.cpu_usage),
+    },
+    Column {
+        title: "Memory",
+        key: "memory",
+        sort_key: SortKey::Memory,
+        align_right: true,
+        window_width: 120,
+        terminal_width: Some(16),
+        text: |r| human_bytes(r.memory as f64),
+        value: |r| Value::Number(r.memory as u64),
+    },
+    Column {
+        title: "Working Set",
+        key: "working_set",
+        sort_key: SortKey::WorkingSet,
+        align_right: true,
+        window_width: 90,
+        terminal_width: Some(11),
+        text: |r| optional_bytes(r.process.memory_counters.working_set),
+        value: |r| optional_number(r.process.memory_counters.working_set),
+    },
+    Column {
+        title: "Private Working Set",
+        key: "private_working_set",
+        sort_key: SortKey::PrivateWorkingSet,
+        align_right: true,
+        window_width: 120,
+        terminal_width: Some(11),
+        text: |r| optional_bytes(r.process.memory_counters.private_working_set),
+        value: |r| optional_number(r.process.memory_counters.private_working_set),
+    },
+    Column {
+        title: "Peak Working Set",
+        key: "peak_working_set",
+        sort_key: SortKey::PeakWorkingSet,
+        align_right: true,
+        window_width: 110,
+        terminal_width: Some(11),
+        text: |r| optional_bytes(r.process.memory_counters.peak_working_set),
+        value: |r| optional_number(r.process.memory_counters.peak_working_set),
+    },
+    Column {
+        title: "Commit Size",
+        key: "commit",
+        sort_key: SortKey::Commit,
+        align_right: true,
+        window_width: 90,
+        terminal_width: Some(11),
+        text: |r| optional_bytes(r.process.memory_counters.commit),
+        value: |r| optional_number(r.process.memory_counters.commit),
+    },
+    Column {
+        title: "Virtual Size",
+        key: "virtual_size",
+        sort_key: SortKey::VirtualSize,
+        align_right: true,
+        window_width: 90,
+        terminal_width: Some(11),
+        text: |r| optional_bytes(r.process.memory_counters.virtual_size),
+        value: |r| optional_number(r.process.memory_counters.virtual_size),
+    },
+    Column {
+        title: "Page Faults",
+        key: "page_faults",
+        sort_key: SortKey::PageFaults,
+        align_right: true,
+        window_width: 80,
+        terminal_width: Some(11),
+        text: |r| {
+            r.process
+                .memory_counters
+                .page_faults
+                .map(|count| count.to_string())
+                .unwrap_or_default()
+        },
+        value: |r| optional_number(r.process.memory_counters.page_faults),
+    },
+    Column {
+        title: "Threads",
+        key: "threads",
+        sort_key: SortKey::Threads,
+        align_right: true,
+        window_width: 60,
+        terminal_width: Some(7),
+        text: |r| r.process.thread_count.to_string(),
+        value: |r| Value::Number(r.process.thread_count as u64),
+    },
+    Column {
+        title: "Handles",
+        key: "handles",
+        sort_key: SortKey::Handles,
+        align_right: true,
+        window_width: 60,
+        terminal_width: Some(7),
+        text: |r| {
+            r.process
+                .handle_count
+                .map(|count| count.to_string())
+                .unwrap_or_default()
+        },
+        value: |r| {
+            r.process
+                .handle_count
+                .map_or(Value::Empty, |count| Value::Number(count as u64))
+        },
+    },
+    Column {
+        title: "I/O Read/s",
+        key: "io_read",
+        sort_key: SortKey::IoRead,
+        align_right: true,
+        window_width: 80,
+        terminal_width: Some(11),
+        text: |r| io_rate(r, r.process.io_read_rate),
+        value: |r| io_value(r, r.process.io_read_rate),
+    },
+    Column {
+        title: "I/O Write/s",
+        key: "io_write",
+        sort_key: SortKey::IoWrite,
+        align_right: true,
+        window_width: 80,
+        terminal_width: Some(11),
+        text: |r| io_rate(r, r.process.io_write_rate),
+        value: |r| io_value(r, r.process.io_write_rate),
+    },
+    Column {
+        title: "I/O Total",
+        key: "io_total",
+        sort_key: SortKey::IoTotal,
+        align_right: true,
+        window_width: 80,
+        terminal_width: Some(11),
+        text: |r| {
+            r.process
+                .io
+                .map(|io| human_bytes((io.read_bytes + io.write_bytes) as f64))
+                .unwrap_or_default()
+        },
+        value: |r| {
+            r.process.io.map_or(Value::Empty, |io| {
+                Value::Number(io.read_bytes + io.write_bytes)
+            })
+        },
+    },
+    Column {
+        title: "Listening Ports",
+        key: "ports",
+        sort_key: SortKey::ListeningPorts,
+        align_right: false,
+        window_width: 100,
+        terminal_width: Some(15),
+        text: listening_ports,
+        value: |r| Value::Text(listening_ports(r)),
+    },
+    Column {
+        title: "Start Time",
+        key: "start_time",
+        sort_key: SortKey::StartTime,
+        align_right: false,
+        window_width: 130,
+        terminal_width: Some(19),
+        text: start_time,
+        value: |r| {
+            r.process
+                .start_time
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(Value::Empty, |since_epoch| {
+                    Value::Number(since_epoch.as_secs())
+                })
+        },
+    },
+    Column {
+        title: "Running For",
+        key: "running_for",
+        sort_key: SortKey::RunningFor,
+        align_right: true,
+        window_width: 90,
+        terminal_width: Some(12),
+        text: |r| running_for(r).map(format_duration).unwrap_or_default(),
+        value: |r| running_for(r).map_or(Value::Empty, Value::Number),
+    },
+    Column {
+        title: "Status",
+        key: "status",
+        sort_key: SortKey::Status,
+        align_right: false,
+        window_width: 150,
+        terminal_width: Some(26),
+        text: status,
+        value: |r| Value::Text(status(r)),
+    },
+    Column {
+        title: "Priority",
+        key: "priority",
+        sort_key: SortKey::Priority,
+        align_right: false,
+        window_width: 130,
+        terminal_width: Some(22),
+        text: priority,
+        value: |r| Value::Text(priority(r)),
+    },
+    Column {
+        title: "I/O Priority",
+        key: "io_priority",
+        sort_key: SortKey::IoPriority,
+        align_right: false,
+        window_width: 90,
+        terminal_width: Some(13),
+        text: |r| r.process.io_priority.clone(),
+        value: |r| Value::Text(r.process.io_priority.clone()),
+    },
+    Column {
+        title: "Scheduling",
+        key: "scheduling",
+        sort_key: SortKey::SchedulingPolicy,
+        align_right: false,
+        window_width: 90,
+        terminal_width: Some(14),
+        text: |r| r.process.scheduling_policy.clone(),
+        value: |r| Value::Text(r.process.scheduling_policy.clone()),
+    },
+    Column {
+        title: "Last CPU",
+        key: "last_cpu",
+        sort_key: SortKey::LastCpu,
+        align_right: true,
+        window_width: 60,
+        terminal_width: Some(8),
+        text: |r| {
+            r.process
+                .last_cpu
+                .map(|cpu| cpu.to_string())
+                .unwrap_or_default()
+        },
+        value: |r| {
+            r.process
+                .last_cpu
+                .map_or(Value::Empty, |cpu| Value::Number(cpu as u64))
+        },
+    },
+    Column {
+        title: "User",
+        key: "user",
+        sort_key: SortKey::User,
+        align_right: false,
+        window_width: 120,
+        terminal_width: Some(12),
+        text: user,
+        value: |r| Value::Text(user(r)),
+    },
+    Column {
+        title: "Container",
+        key: "container",
+        sort_key: SortKey::Container,
+        align_right: false,
+        window_width: 200,
+        terminal_width: Some(24),
+        text: |r| r.process.container.clone(),
+        value: |r| Value::Text(r.process.container.clone()),
+    },
+    Column {
+        title: "Architecture",
+        key: "architecture",
+        sort_key: SortKey::Architecture,
+        align_right: false,
+        window_width: 110,
+        terminal_width: Some(16),
+        text: |r| r.process.architecture.clone(),
+        value: |r| Value::Text(r.process.architecture.clone()),
+    },
+    Column {
+        title: "Security",
+        key: "security",
+        sort_key: SortKey::Security,
+        align_right: false,
+        window_width: 180,
+        terminal_width: Some(28),
+        text: |r| r.process.security.summary(),
+        value: |r| Value::Text(r.process.security.summary()),
+    },
+    Column {
+        title: "Description",
+        key: "description",
+        sort_key: SortKey::Description,
+        align_right: false,
+        window_width: 200,
+        terminal_width: Some(24),
+        text: |r| r.process.binary.description.clone(),
+        value: |r| Value::Text(r.process.binary.description.clone()),
+    },
+    Column {
+        title: "Company",
+        key: "company",
+        sort_key: SortKey::Company,
+        align_right: false,
+        window_width: 150,
+        terminal_width: Some(18),
+        text: |r| r.process.binary.company.clone(),
+        value: |r| Value::Text(r.process.binary.company.clone()),
+    },
+    Column {
+        title: "Version",
+        key: "version",
+        sort_key: SortKey::Version,
+        align_right: false,
+        window_width: 100,
+        terminal_width: Some(15),
+        text: |r| r.process.binary.version.clone(),
+        value: |r| Value::Text(r.process.binary.version.clone()),
+    },
+    Column {
+        title: "Image Type",
+        key: "image_type",
+        sort_key: SortKey::ImageType,
+        align_right: false,
+        window_width: 110,
+        terminal_width: Some(17),
+        text: |r| r.process.binary.image_type.clone(),
+        value: |r| Value::Text(r.process.binary.image_type.clone()),
+    },
+    Column {
+        title: "Build ID",
+        key: "build_id",
+        sort_key: SortKey::BuildId,
+        align_right: false,
+        window_width: 280,
+        terminal_width: Some(40),
+        text: |r| r.process.binary.build_id.clone(),
+        value: |r| Value::Text(r.process.binary.build_id.clone()),
+    },
+    Column {
+        title: "Interpreter",
+        key: "interpreter",
+        sort_key: SortKey::Interpreter,
+        align_right: false,
+        window_width: 200,
+        terminal_width: Some(28),
+        text: |r| r.process.binary.interpreter.clone(),
+        value: |r| Value::Text(r.process.binary.interpreter.clone()),
+    },
+    Column {
+        title: "Image Path",
+        key: "path",
+        sort_key: SortKey::ImagePath,
+        align_right: false,
+        window_width: 300,
+        terminal_width: None,
+        text: image_path,
+        value: |r| Value::Text(image_path(r)),
+    },
+    Column {
+        title: "Command Line",
+        key: "command",
+        sort_key: SortKey::CommandLine,
+        align_right: false,
+        window_width: 400,
+        terminal_width: None,
+        text: |r| r.process.command_line.clone(),
+        value: |r| Value::Text(r.process.command_line.clone()),
+    },
+];
+
+// Blank rather than zero for counters the OS doesn't keep
+fn optional_bytes(bytes: Option<u64>) -> String {
+    bytes
+        .map(|bytes| human_bytes(bytes as f64))
+        .unwrap_or_default()
+}
+
+fn optional_number(number: Option<u64>) -> Value {
+    number.map_or(Value::Empty, Value::Number)
+}
+
+// Blank rather than zero when the counters can't be read
+fn io_rate(row: &Row, rate: u64) -> String {
+    match row.process.io {
+        Some(_) => format!("{}/s", human_bytes(rate as f64)),
+        None => String::new(),
+    }
+}
+
+fn io_value(row: &Row, rate: u64) -> Value {
+    match row.process.io {
+        Some(_) => Value::Number(rate),
+        None => Value::Empty,
+    }
+}
+
+fn listening_ports(row: &Row) -> String {
+    let ports: Vec<String> = row
+        .process
+        .listening_ports
+        .iter()
+        .map(u16::to_string)
+        .collect();
+    ports.join(", ")
+}
+
+fn start_time(row: &Row) -> String {
+    let Some(time) = row.process.start_time.and_then(system::local_time) else {
+        return String::new();
+    };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        time.year, time.month, time.day, time.hour, time.minute, time.second
+    )
+}
+
+// Seconds from the start time until the sample was taken, so a replayed
+// recording shows how long its processes had been running at the time
+fn running_for(row: &Row) -> Option<u64> {
+    let start_time = row.process.start_time?;
+    let running_for = row
+        .process
+        .sample_wall_time
+        .duration_since(start_time)
+        .ok()?;
+    Some(running_for.as_secs())
+}
+
+// "5d 03:04:05", or just "03:04:05" under a day
+fn format_duration(seconds: u64) -> String {
+    let (days, seconds) = (seconds / 86_400, seconds % 86_400);
+    let time = format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    if days > 0 {
+        format!("{}d {}", days, time)
+    } else {
+        time
+    }
+}
+
+// Stuck states say for how long, like "Uninterruptible for 00:01:05"
+fn status(row: &Row) -> String {
+    let Some(status) = row.process.status else {
+        return String::new();
+    };
+    let stuck_for = process::status_duration(&row.process).as_secs();
+    if status.is_stuck() && stuck_for > 0 {
+        format!("{} for {}", status.name(), format_duration(stuck_for))
+    } else {
+        status.name().to_string()
+    }
+}
+
+// Linux's nice values don't map one to one onto priority classes, so the
+// actual value is shown too
+fn priority(row: &Row) -> String {
+    let Some(priority) = row.process.priority else {
+        return String::new();
+    };
+    match row.process.nice {
+        Some(nice) => format!("{} (nice {})", priority.name(), nice),
+        None => priority.name().to_string(),
+    }
+}
+
+// A setuid program shows who started it as well as who it runs as
+fn user(row: &Row) -> String {
+    if row.process.real_user.is_empty() {
+        row.process.user.clone()
+    } else {
+        format!("{} (real: {})", row.process.user, row.process.real_user)
+    }
+}
+
+// Flags an executable that's no longer on disk the way /proc/<pid>/exe does
+fn image_path(row: &Row) -> String {
+    if row.process.image_deleted {
+        format!("{} (deleted)", row.process.image_path)
+    } else {
+        row.process.image_path.clone()
+    }
+}
+
+// The Memory column's title names the figure it shows
+pub fn title(column: &Column, memory_mode: MemoryMode) -> String {
+    if column.sort_key == SortKey::Memory {
+        format!("{} ({})", column.title, memory_mode.name())
+    } else {
+        column.title.to_string()
+    }
+}
+
+pub fn find(key: &str) -> Option<&'static Column> {
+    COLUMNS.iter().find(|column| column.key == key)
+}
+
+pub fn default_columns() -> Vec<&'static Column> {
+    COLUMNS
+        .iter()
+        .filter(|column| DEFAULT_KEYS.contains(&column.key))
+        .collect()
+}
+
+// A comma-separated list of keys, in the order given, or "all"
+pub fn parse_list(text: &str) -> Result<Vec<&'static Column>, String> {
+    if text == "all" {
+        return Ok(COLUMNS.iter().collect());
+    }
+    text.split(',')
+        .map(|key| find(key.trim()).ok_or_else(|| format!("unknown column '{}'", key)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{rc::Rc, time::Duration};
+
+    use super::*;
+    use crate::process::{tests::example_process, Process};
+
+    fn keys(columns: &[&Column]) -> Vec<&'static str> {
+        columns.iter().map(|column| column.key).collect()
+    }
+
+    fn row(process: Process) -> Row {
+        Row {
+            cpu_usage: process.cpu_usage,
+            memory: process.private_working_set,
+            process: Rc::new(process),
+            depth: None,
+            expanded: None,
+        }
+    }
+
+    // Measured against the sample's own clock, so a replay shows the same
+    // thing however long after the recording it runs
+    #[test]
+    fn running_for_is_as_of_the_sample() {
+        let mut process = example_process(1);
+        process.start_time = Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        process.sample_wall_time = UNIX_EPOCH + Duration::from_secs(1_700_000_000 + 90_061);
+        assert_eq!(running_for(&row(process.clone())), Some(90_061));
+
+        process.sample_wall_time = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        assert_eq!(running_for(&row(process.clone())), None);
+
+        process.start_time = None;
+        assert_eq!(running_for(&row(process)), None);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(0), "00:00:00");
+        assert_eq!(format_duration(3723), "01:02:03");
+        assert_eq!(format_duration(90_061), "1d 01:01:01");
+    }
+
+    #[test]
+    fn default_columns_in_display_order() {
+        assert_eq!(
+            keys(&default_columns()),
+            ["name", "pid", "cpu", "memory", "user"]
+        );
+    }
+
+    #[test]
+    fn column_list() {
+        assert_eq!(
+            keys(&parse_list("user,name, cpu").unwrap()),
+            ["user", "name", "cpu"]
+        );
+        assert_eq!(parse_list("all").unwrap().len(), COLUMNS.len());
+    }
+
+    #[test]
+    fn column_list_with_unknown_key() {
+        assert_eq!(
+            parse_list("name,colour").err().unwrap(),
+            "unknown column 'colour'"
+        );
+        assert!(parse_list("").is_err());
+    }
+}
@@ -0,0 +1,154 @@
+// Select columns on the View menu: every column the task list can show, ticked
+// for the ones it does.
+
+use widestring::U16CString;
+use windows::{
+    core::PWSTR,
+    Win32::{
+        Foundation::{HINSTANCE, HWND, LPARAM, WPARAM},
+        System::LibraryLoader::GetModuleHandleW,
+        UI::{
+            Controls::{
+                LIST_VIEW_ITEM_STATE_FLAGS, LVCFMT_LEFT, LVIF_TEXT, LVIS_STATEIMAGEMASK, LVITEMW,
+                LVM_GETITEMSTATE, LVM_INSERTITEMW, LVM_SETEXTENDEDLISTVIEWSTYLE, LVM_SETITEMSTATE,
+                LVS_EX_CHECKBOXES, LVS_EX_FULLROWSELECT,
+            },
+            WindowsAndMessaging::*,
+        },
+    },
+};
+
+use crate::{
+    column::{self, Column, COLUMNS},
+    resources::{to_pcwstr, FALSE, IDC_COLUMN_LIST, IDD_COLUMNS, TRUE},
+    state, task_list,
+};
+
+const IDOK: usize = windows::Win32::UI::WindowsAndMessaging::IDOK.0 as usize;
+const IDCANCEL: usize = windows::Win32::UI::WindowsAndMessaging::IDCANCEL.0 as usize;
+// The checkbox is the item's state image: 1 for unticked, 2 for ticked
+const UNCHECKED: u32 = 1 << 12;
+const CHECKED: u32 = 2 << 12;
+
+// Handed to the dialog through its lParam, and kept in GWLP_USERDATA
+struct ColumnsParams {
+    main_window: HWND,
+}
+
+pub fn show(main_window: HWND) {
+    let mut params = ColumnsParams { main_window };
+    unsafe {
+        let instance = HINSTANCE(GetModuleHandleW(None).expect("shouldn't fail").0);
+        let _ = DialogBoxParamW(
+            Some(instance),
+            to_pcwstr(IDD_COLUMNS),
+            Some(main_window),
+            Some(dialog_proc),
+            LPARAM(&raw mut params as isize),
+        );
+    }
+}
+
+unsafe extern "system" fn dialog_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> isize {
+    match msg {
+        WM_INITDIALOG => {
+            SetWindowLongPtrW(hwnd, GWLP_USERDATA, lparam.0);
+            fill_list(hwnd);
+            TRUE
+        }
+        WM_COMMAND if wparam.0 == IDOK => {
+            if apply(hwnd) {
+                let _ = EndDialog(hwnd, IDOK as isize);
+            }
+            TRUE
+        }
+        WM_COMMAND if wparam.0 == IDCANCEL => {
+            let _ = EndDialog(hwnd, IDCANCEL as isize);
+            TRUE
+        }
+        _ => FALSE,
+    }
+}
+
+// Lives on show's stack for as long as the dialog is up
+unsafe fn params<'a>(hwnd: HWND) -> &'a ColumnsParams {
+    &*(GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const ColumnsParams)
+}
+
+unsafe fn column_list(hwnd: HWND) -> HWND {
+    GetDlgItem(Some(hwnd), IDC_COLUMN_LIST).expect("the dialog has a list")
+}
+
+unsafe fn fill_list(hwnd: HWND) {
+    let state = state::get(params(hwnd).main_window);
+    let list = column_list(hwnd);
+    let style = LVS_EX_CHECKBOXES | LVS_EX_FULLROWSELECT;
+    SendMessageW(
+        list,
+        LVM_SETEXTENDEDLISTVIEWSTYLE,
+        Some(WPARAM(style as usize)),
+        Some(LPARAM(style as isize)),
+    );
+    task_list::add_column(list, "Column", 0, 200, LVCFMT_LEFT);
+
+    for (index, column) in COLUMNS.iter().enumerate() {
+        let mut text = U16CString::from_str_truncate(column::title(column, state.memory_mode));
+        let mut item = LVITEMW {
+            mask: LVIF_TEXT,
+            iItem: index as i32,
+            pszText: PWSTR(text.as_mut_ptr()),
+            ..Default::default()
+        };
+        SendMessageW(
+            list,
+            LVM_INSERTITEMW,
+            None,
+            Some(LPARAM(&raw mut item as isize)),
+        );
+
+        let shown = state.columns.iter().any(|c| c.key == column.key);
+        let mut item = LVITEMW {
+            stateMask: LVIS_STATEIMAGEMASK,
+            state: LIST_VIEW_ITEM_STATE_FLAGS(if shown { CHECKED } else { UNCHECKED }),
+            ..Default::default()
+        };
+        SendMessageW(
+            list,
+            LVM_SETITEMSTATE,
+            Some(WPARAM(index)),
+            Some(LPARAM(&raw mut item as isize)),
+        );
+    }
+}
+
+unsafe fn is_checked(list: HWND, index: usize) -> bool {
+    let state = SendMessageW(
+        list,
+        LVM_GETITEMSTATE,
+        Some(WPARAM(index)),
+        Some(LPARAM(LVIS_STATEIMAGEMASK.0 as isize)),
+    );
+    state.0 as u32 & LVIS_STATEIMAGEMASK.0 == CHECKED
+}
+
+// Returns false to keep the dialog open when no column is ticked
+unsafe fn apply(hwnd: HWND) -> bool {
+    let list = column_list(hwnd);
+    let columns: Vec<&'static Column> = COLUMNS
+        .iter()
+        .enumerate()
+        .filter(|&(index, _)| is_checked(list, index))
+        .map(|(_, column)| column)
+        .collect();
+    if columns.is_empty() {
+        task_list::show_error(hwnd, "Pick at least one column.");
+        return false;
+    }
+    task_list::set_columns(params(hwnd).main_window, columns);
+    true
+}
@@ -0,0 +1,187 @@
+// TCP and UDP sockets and the processes that own them, for answering "what
+// is listening on this port". The window, the terminal interface and
+// `taskmanager list --connections` all show the same columns.
+
+use std::{collections::HashMap, net::SocketAddr, rc::Rc};
+
+use crate::{column::Value, process::Process};
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(windows)]
+mod win32;
+
+#[cfg(target_os = "linux")]
+pub use linux::{get_connections, listening_sockets, socket_inode};
+#[cfg(windows)]
+pub use win32::{get_connections, listening_ports};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    pub fn name(self) -> &'static str {
+        match self {
+            Protocol::Tcp => "TCP",
+            Protocol::Udp => "UDP",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Connection {
+    pub protocol: Protocol,
+    pub local_address: SocketAddr,
+    // None while a TCP socket listens, and for UDP sockets that aren't
+    // connected to anything
+    pub remote_address: Option<SocketAddr>,
+    // TCP only, like LISTEN or ESTABLISHED
+    pub state: &'static str,
+    // None when the owner couldn't be found out, like another user's socket
+    // on Linux
+    pub pid: Option<u32>,
+}
+
+impl Connection {
+    // A TCP socket waiting for connections, or a UDP socket waiting for
+    // datagrams from anywhere
+    pub fn is_listening(&self) -> bool {
+        match self.protocol {
+            Protocol::Tcp => self.state == "LISTEN",
+            Protocol::Udp => self.remote_address.is_none(),
+        }
+    }
+}
+
+// A connection as the connections view shows it, with the owner's image name
+// joined from the last sample
+pub struct ConnectionRow {
+    pub connection: Connection,
+    pub image_name: String,
+}
+
+// Listening sockets first, then by protocol and local port
+pub fn connection_rows(
+    connections: Vec<Connection>,
+    pid_map: &HashMap<u32, Rc<Process>>,
+) -> Vec<ConnectionRow> {
+    let mut rows: Vec<ConnectionRow> = connections
+        .into_iter()
+        .map(|connection| ConnectionRow {
+            image_name: connection
+                .pid
+                .and_then(|pid| pid_map.get(&pid))
+                .map(|process| process.image_name.clone())
+                .unwrap_or_default(),
+            connection,
+        })
+        .collect();
+    rows.sort_by_key(|row| {
+        let c = &row.connection;
+        (
+            !c.is_listening(),
+            c.protocol,
+            c.local_address.port(),
+            c.local_address,
+            c.remote_address,
+        )
+    });
+    rows
+}
+
+pub struct ConnectionColumn {
+    pub title: &'static str,
+    // JSON/CSV field name
+    pub key: &'static str,
+    pub align_right: bool,
+    // In pixels in the window
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub window_width: i32,
+    // In characters in the terminal. None takes whatever width the fixed
+    // columns leave over.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub terminal_width: Option<usize>,
+    pub text: fn(&ConnectionRow) -> String,
+    pub value: fn(&ConnectionRow) -> Value,
+}
+
+pub const CONNECTION_COLUMNS: [ConnectionColumn; 6] = [
+    ConnectionColumn {
+        title: "Protocol",
+        key: "protocol",
+        align_right: false,
+        window_width: 60,
+        terminal_width: Some(8),
+        text: |r| r.connection.protocol.name().to_string(),
+        value: |r| Value::Text(r.connection.protocol.name().to_string()),
+    },
+    ConnectionColumn {
+        title: "Local Address",
+        key: "local",
+        align_right: false,
+        window_width: 200,
+        terminal_width: Some(30),
+        text: |r| r.connection.local_address.to_string(),
+        value: |r| Value::Text(r.connection.local_address.to_string()),
+    },
+    ConnectionColumn {
+        title: "Remote Address",
+        key: "remote",
+        align_right: false,
+        window_width: 200,
+        terminal_width: Some(30),
+        text: |r| {
+            r.connection
+                .remote_address
+                .map(|address| address.to_string())
+                .unwrap_or_default()
+        },
+        value: |r| {
+            r.connection
+                .remote_address
+                .map_or(Value::Empty, |address| Value::Text(address.to_string()))
+        },
+    },
+    ConnectionColumn {
+        title: "State",
+        key: "state",
+        align_right: false,
+        window_width: 100,
+        terminal_width: Some(11),
+        text: |r| r.connection.state.to_string(),
+        value: |r| match r.connection.state {
+            "" => Value::Empty,
+            state => Value::Text(state.to_string()),
+        },
+    },
+    ConnectionColumn {
+        title: "PID",
+        key: "pid",
+        align_right: false,
+        window_width: 50,
+        terminal_width: Some(7),
+        text: |r| {
+            r.connection
+                .pid
+                .map(|pid| pid.to_string())
+                .unwrap_or_default()
+        },
+        value: |r| {
+            r.connection
+                .pid
+                .map_or(Value::Empty, |pid| Value::Number(pid as u64))
+        },
+    },
+    ConnectionColumn {
+        title: "Process",
+        key: "name",
+        align_right: false,
+        window_width: 200,
+        terminal_width: None,
+        text: |r| r.image_name.clone(),
+        value: |r| Value::Text(r.image_name.clone()),
+    },
+];
@@ -0,0 +1,225 @@
+// The kernel's socket tables in /proc/net, which cover the network namespace
+// taskmanager runs in. They name each socket by inode; the owner is whichever
+// process has a file descriptor linked to socket:[<inode>].
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::Result,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+};
+
+use super::{Connection, Protocol};
+
+const SOCKET_TABLES: [(&str, Protocol); 4] = [
+    ("tcp", Protocol::Tcp),
+    ("tcp6", Protocol::Tcp),
+    ("udp", Protocol::Udp),
+    ("udp6", Protocol::Udp),
+];
+
+// The st column of the TCP tables, numbered as in include/net/tcp_states.h
+fn tcp_state(st: u8) -> &'static str {
+    match st {
+        0x01 => "ESTABLISHED",
+        0x02 => "SYN_SENT",
+        0x03 => "SYN_RECV",
+        0x04 => "FIN_WAIT1",
+        0x05 => "FIN_WAIT2",
+        0x06 => "TIME_WAIT",
+        0x07 => "CLOSE",
+        0x08 => "CLOSE_WAIT",
+        0x09 => "LAST_ACK",
+        0x0a => "LISTEN",
+        0x0b => "CLOSING",
+        0x0c => "NEW_SYN_RECV",
+        _ => "UNKNOWN",
+    }
+}
+
+// "<address>:<port>" in hex, where the address is printed as 32-bit words in
+// the machine's own byte order
+fn parse_address(field: &str) -> Option<SocketAddr> {
+    let (address, port) = field.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    if address.len() != 8 && address.len() != 32 {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(16);
+    for word in address.as_bytes().chunks(8) {
+        let word = u32::from_str_radix(std::str::from_utf8(word).ok()?, 16).ok()?;
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+    let ip = match bytes.len() {
+        4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?)),
+        16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?)),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+// One line of a socket table along with the socket's inode:
+//   sl local_address rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout inode ...
+fn parse_socket(line: &str, protocol: Protocol) -> Option<(Connection, u64)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let local_address = parse_address(fields.get(1)?)?;
+    let remote_address = parse_address(fields.get(2)?)?;
+    let st = u8::from_str_radix(fields.get(3)?, 16).ok()?;
+    let inode = fields.get(9)?.parse().ok()?;
+    let state = match protocol {
+        Protocol::Tcp => tcp_state(st),
+        Protocol::Udp => "",
+    };
+    let connection = Connection {
+        protocol,
+        local_address,
+        remote_address: (!remote_address.ip().is_unspecified() || remote_address.port() != 0)
+            .then_some(remote_address),
+        state,
+        pid: None,
+    };
+    Some((connection, inode))
+}
+
+fn read_sockets() -> Vec<(Connection, u64)> {
+    let mut sockets = Vec::new();
+    for (file, protocol) in SOCKET_TABLES {
+        // tcp6 and udp6 are missing when IPv6 is turned off
+        let Ok(table) = fs::read_to_string(Path::new("/proc/net").join(file)) else {
+            continue;
+        };
+        sockets.extend(
+            table
+                .lines()
+                .skip(1)
+                .filter_map(|line| parse_socket(line, protocol)),
+        );
+    }
+    sockets
+}
+
+// The inode of a /proc/<pid>/fd link that points at a socket
+pub fn socket_inode(fd_link: &Path) -> Option<u64> {
+    let target = fs::read_link(fd_link).ok()?;
+    let target = target.to_str()?;
+    target
+        .strip_prefix("socket:[")?
+        .strip_suffix(']')?
+        .parse()
+        .ok()
+}
+
+// Which process holds each socket inode. Only the fds of processes
+// taskmanager may look into (its own user's, or all of them as root) count.
+fn socket_owners() -> Result<HashMap<u64, u32>> {
+    let mut owners = HashMap::new();
+    for entry in fs::read_dir("/proc")?.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse().ok()) else {
+            continue;
+        };
+        let Ok(fds) = fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            if let Some(inode) = socket_inode(&fd.path()) {
+                owners.entry(inode).or_insert(pid);
+            }
+        }
+    }
+    Ok(owners)
+}
+
+pub fn get_connections() -> Result<Vec<Connection>> {
+    let owners = socket_owners()?;
+    Ok(read_sockets()
+        .into_iter()
+        .map(|(mut connection, inode)| {
+            connection.pid = owners.get(&inode).copied();
+            connection
+        })
+        .collect())
+}
+
+// The port of each listening socket, by inode, for matching up with a
+// process's fds while sampling it
+pub fn listening_sockets() -> HashMap<u64, u16> {
+    read_sockets()
+        .into_iter()
+        .filter(|(connection, _)| connection.is_listening())
+        .map(|(connection, inode)| (inode, connection.local_address.port()))
+        .collect()
+}
+
+// The tables print addresses in the machine's byte order, and these are
+// copied from a little-endian one
+#[cfg(all(test, target_endian = "little"))]
+mod tests {
+    use super::*;
+
+    fn address(text: &str) -> SocketAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn addresses() {
+        let parse = |field| parse_address(field).unwrap();
+        assert_eq!(parse("0100007F:0277"), address("127.0.0.1:631"));
+        assert_eq!(parse("00000000:0035"), address("0.0.0.0:53"));
+        assert_eq!(
+            parse("00000000000000000000000001000000:0016"),
+            address("[::1]:22")
+        );
+        assert_eq!(
+            parse("B80D0120000000000000000001000000:01BB"),
+            address("[2001:db8::1]:443")
+        );
+        assert_eq!(
+            parse("0000000000000000FFFF00000100007F:1F90"),
+            address("[::ffff:127.0.0.1]:8080")
+        );
+        assert_eq!(parse_address("0100007F"), None);
+        assert_eq!(parse_address("0100007:0277"), None);
+        assert_eq!(parse_address("0100007F:X"), None);
+    }
+
+    #[test]
+    fn listening_tcp_socket() {
+        let line = "   0: 0100007F:0277 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 23456 1 0000000000000000 100 0 0 10 0";
+        let (connection, inode) = parse_socket(line, Protocol::Tcp).unwrap();
+        assert_eq!(inode, 23456);
+        assert_eq!(connection.local_address, address("127.0.0.1:631"));
+        assert_eq!(connection.remote_address, None);
+        assert_eq!(connection.state, "LISTEN");
+        assert!(connection.is_listening());
+    }
+
+    #[test]
+    fn established_tcp6_socket() {
+        let line = "   3: 0000000000000000FFFF00000100007F:1F90 0000000000000000FFFF00000100007F:C5A2 01 00000000:00000000 00:00000000 00000000  1000        0 34567 1 0000000000000000 20 4 30 10 -1";
+        let (connection, inode) = parse_socket(line, Protocol::Tcp).unwrap();
+        assert_eq!(inode, 34567);
+        assert_eq!(
+            connection.remote_address,
+            Some(address("[::ffff:127.0.0.1]:50594"))
+        );
+        assert_eq!(connection.state, "ESTABLISHED");
+        assert!(!connection.is_listening());
+    }
+
+    #[test]
+    fn udp_socket() {
+        let line = "  785: 3500007F:0035 00000000:0000 07 00000000:00000000 00:00000000 00000000   101        0 12345 2 0000000000000000 0";
+        let (connection, inode) = parse_socket(line, Protocol::Udp).unwrap();
+        assert_eq!(inode, 12345);
+        assert_eq!(connection.local_address, address("127.0.0.53:53"));
+        assert_eq!(connection.state, "");
+        assert!(connection.is_listening());
+    }
+
+    #[test]
+    fn table_header() {
+        let header = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode";
+        assert!(parse_socket(header, Protocol::Tcp).is_none());
+    }
+}
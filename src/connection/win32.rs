@@ -0,0 +1,206 @@
+use std::{
+    collections::HashMap,
+    ffi::c_void,
+    io::{Error, Result},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    slice,
+};
+
+use windows::Win32::{
+    Foundation::{ERROR_INSUFFICIENT_BUFFER, NO_ERROR},
+    NetworkManagement::IpHelper::{
+        GetExtendedTcpTable, GetExtendedUdpTable, MIB_TCP6TABLE_OWNER_PID, MIB_TCPTABLE_OWNER_PID,
+        MIB_UDP6TABLE_OWNER_PID, MIB_UDPTABLE_OWNER_PID, TCP_TABLE_OWNER_PID_ALL,
+        UDP_TABLE_OWNER_PID,
+    },
+    Networking::WinSock::{AF_INET, AF_INET6},
+};
+
+use super::{Connection, Protocol};
+
+// dwState of the TCP tables, numbered as MIB_TCP_STATE
+fn tcp_state(state: u32) -> &'static str {
+    match state {
+        1 => "CLOSED",
+        2 => "LISTEN",
+        3 => "SYN_SENT",
+        4 => "SYN_RECV",
+        5 => "ESTABLISHED",
+        6 => "FIN_WAIT1",
+        7 => "FIN_WAIT2",
+        8 => "CLOSE_WAIT",
+        9 => "CLOSING",
+        10 => "LAST_ACK",
+        11 => "TIME_WAIT",
+        12 => "DELETE_TCB",
+        _ => "UNKNOWN",
+    }
+}
+
+// Ports are stored in network byte order in the low 16 bits
+fn network_port(port: u32) -> u16 {
+    u16::from_be(port as u16)
+}
+
+fn ipv4_address(address: u32, port: u32) -> SocketAddr {
+    SocketAddr::new(
+        IpAddr::V4(Ipv4Addr::from(address.to_ne_bytes())),
+        network_port(port),
+    )
+}
+
+fn ipv6_address(address: [u8; 16], scope_id: u32, port: u32) -> SocketAddr {
+    SocketAddr::V6(SocketAddrV6::new(
+        Ipv6Addr::from(address),
+        network_port(port),
+        0,
+        scope_id,
+    ))
+}
+
+// The remote end of a TCP socket, None while it listens
+fn tcp_remote(state: u32, address: SocketAddr) -> Option<SocketAddr> {
+    (tcp_state(state) != "LISTEN").then_some(address)
+}
+
+// Calls GetExtendedTcpTable or GetExtendedUdpTable until the buffer is big
+// enough for the table, which can grow between calls. The buffer is made of
+// u32s to keep the rows aligned.
+unsafe fn read_table(get_table: impl Fn(Option<*mut c_void>, *mut u32) -> u32) -> Result<Vec<u32>> {
+    let mut size = 0;
+    let mut buffer: Vec<u32> = Vec::new();
+    loop {
+        let table = (!buffer.is_empty()).then_some(buffer.as_mut_ptr() as *mut c_void);
+        match get_table(table, &mut size) {
+            code if code == NO_ERROR.0 => return Ok(buffer),
+            code if code == ERROR_INSUFFICIENT_BUFFER.0 => {
+                buffer.resize((size as usize).div_ceil(size_of::<u32>()), 0);
+            }
+            code => return Err(Error::from_raw_os_error(code as i32)),
+        }
+    }
+}
+
+// The tables are declared with one row, but dwNumEntries of them follow
+unsafe fn table_rows<Row>(rows: &[Row; 1], num_entries: u32) -> &[Row] {
+    slice::from_raw_parts(rows.as_ptr(), num_entries as usize)
+}
+
+unsafe fn get_tcp_connections(connections: &mut Vec<Connection>) -> Result<()> {
+    let buffer = read_table(|table, size| {
+        GetExtendedTcpTable(
+            table,
+            size,
+            false,
+            AF_INET.0 as u32,
+            TCP_TABLE_OWNER_PID_ALL,
+            0,
+        )
+    })?;
+    let table = &*(buffer.as_ptr() as *const MIB_TCPTABLE_OWNER_PID);
+    for row in table_rows(&table.table, table.dwNumEntries) {
+        connections.push(Connection {
+            protocol: Protocol::Tcp,
+            local_address: ipv4_address(row.dwLocalAddr, row.dwLocalPort),
+            remote_address: tcp_remote(
+                row.dwState,
+                ipv4_address(row.dwRemoteAddr, row.dwRemotePort),
+            ),
+            state: tcp_state(row.dwState),
+            pid: Some(row.dwOwningPid),
+        });
+    }
+
+    let buffer = read_table(|table, size| {
+        GetExtendedTcpTable(
+            table,
+            size,
+            false,
+            AF_INET6.0 as u32,
+            TCP_TABLE_OWNER_PID_ALL,
+            0,
+        )
+    })?;
+    let table = &*(buffer.as_ptr() as *const MIB_TCP6TABLE_OWNER_PID);
+    for row in table_rows(&table.table, table.dwNumEntries) {
+        connections.push(Connection {
+            protocol: Protocol::Tcp,
+            local_address: ipv6_address(row.ucLocalAddr, row.dwLocalScopeId, row.dwLocalPort),
+            remote_address: tcp_remote(
+                row.dwState,
+                ipv6_address(row.ucRemoteAddr, row.dwRemoteScopeId, row.dwRemotePort),
+            ),
+            state: tcp_state(row.dwState),
+            pid: Some(row.dwOwningPid),
+        });
+    }
+    Ok(())
+}
+
+// The UDP tables don't say whether a socket is connected, so none are
+unsafe fn get_udp_connections(connections: &mut Vec<Connection>) -> Result<()> {
+    let buffer = read_table(|table, size| {
+        GetExtendedUdpTable(table, size, false, AF_INET.0 as u32, UDP_TABLE_OWNER_PID, 0)
+    })?;
+    let table = &*(buffer.as_ptr() as *const MIB_UDPTABLE_OWNER_PID);
+    for row in table_rows(&table.table, table.dwNumEntries) {
+        connections.push(Connection {
+            protocol: Protocol::Udp,
+            local_address: ipv4_address(row.dwLocalAddr, row.dwLocalPort),
+            remote_address: None,
+            state: "",
+            pid: Some(row.dwOwningPid),
+        });
+    }
+
+    let buffer = read_table(|table, size| {
+        GetExtendedUdpTable(
+            table,
+            size,
+            false,
+            AF_INET6.0 as u32,
+            UDP_TABLE_OWNER_PID,
+            0,
+        )
+    })?;
+    let table = &*(buffer.as_ptr() as *const MIB_UDP6TABLE_OWNER_PID);
+    for row in table_rows(&table.table, table.dwNumEntries) {
+        connections.push(Connection {
+            protocol: Protocol::Udp,
+            local_address: ipv6_address(row.ucLocalAddr, row.dwLocalScopeId, row.dwLocalPort),
+            remote_address: None,
+            state: "",
+            pid: Some(row.dwOwningPid),
+        });
+    }
+    Ok(())
+}
+
+pub fn get_connections() -> Result<Vec<Connection>> {
+    let mut connections = Vec::new();
+    unsafe {
+        get_tcp_connections(&mut connections)?;
+        get_udp_connections(&mut connections)?;
+    }
+    Ok(connections)
+}
+
+// The ports each process listens on, sorted and without repeats. Empty when
+// the tables can't be read.
+pub fn listening_ports() -> HashMap<u32, Vec<u16>> {
+    let mut ports: HashMap<u32, Vec<u16>> = HashMap::new();
+    for connection in get_connections().unwrap_or_default() {
+        let Some(pid) = connection.pid.filter(|_| connection.is_listening()) else {
+            continue;
+        };
+        ports
+            .entry(pid)
+            .or_default()
+            .push(connection.local_address.port());
+    }
+    for process_ports in ports.values_mut() {
+        process_ports.sort_unstable();
+        process_ports.dedup();
+    }
+    ports
+}
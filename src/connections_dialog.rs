@@ -0,0 +1,164 @@
+// View > Connections: every TCP and UDP socket with the process that owns it,
+// as of when the dialog was opened or a process was last ended from it.
+
+use std::ffi::c_void;
+
+use widestring::U16CString;
+use windows::{
+    core::PWSTR,
+    Win32::{
+        Foundation::{HINSTANCE, HWND, LPARAM, WPARAM},
+        System::LibraryLoader::GetModuleHandleW,
+        UI::{
+            Controls::{
+                LVCFMT_LEFT, LVCFMT_RIGHT, LVIF_PARAM, LVIF_TEXT, LVITEMW, LVM_DELETEALLITEMS,
+                LVM_GETITEMW, LVM_INSERTITEMW, LVM_SETEXTENDEDLISTVIEWSTYLE, LVM_SETITEMW,
+                LVS_EX_FULLROWSELECT,
+            },
+            WindowsAndMessaging::*,
+        },
+    },
+};
+
+use crate::{
+    connection::{self, CONNECTION_COLUMNS},
+    resources::{
+        to_pcwstr, FALSE, IDC_CONNECTION_LIST, IDC_END_OWNING_PROCESS, IDD_CONNECTIONS, TRUE,
+    },
+    state, task_list,
+};
+
+const IDOK: usize = windows::Win32::UI::WindowsAndMessaging::IDOK.0 as usize;
+const IDCANCEL: usize = windows::Win32::UI::WindowsAndMessaging::IDCANCEL.0 as usize;
+
+pub fn show(main_window: HWND) {
+    unsafe {
+        let instance = HINSTANCE(GetModuleHandleW(None).expect("shouldn't fail").0);
+        let _ = DialogBoxParamW(
+            Some(instance),
+            to_pcwstr(IDD_CONNECTIONS),
+            Some(main_window),
+            Some(dialog_proc),
+            LPARAM(main_window.0 as isize),
+        );
+    }
+}
+
+unsafe extern "system" fn dialog_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> isize {
+    match msg {
+        WM_INITDIALOG => {
+            // The main window, whose source and processes the list comes from
+            SetWindowLongPtrW(hwnd, GWLP_USERDATA, lparam.0);
+            add_columns(connection_list(hwnd));
+            fill_list(hwnd);
+            TRUE
+        }
+        WM_COMMAND if wparam.0 & 0xffff == IDC_END_OWNING_PROCESS as usize => {
+            end_owning_process(hwnd);
+            TRUE
+        }
+        WM_COMMAND if wparam.0 == IDOK || wparam.0 == IDCANCEL => {
+            let _ = EndDialog(hwnd, IDOK as isize);
+            TRUE
+        }
+        _ => FALSE,
+    }
+}
+
+unsafe fn main_window(hwnd: HWND) -> HWND {
+    HWND(GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut c_void)
+}
+
+unsafe fn connection_list(hwnd: HWND) -> HWND {
+    GetDlgItem(Some(hwnd), IDC_CONNECTION_LIST).expect("the dialog has a list")
+}
+
+unsafe fn add_columns(list: HWND) {
+    SendMessageW(
+        list,
+        LVM_SETEXTENDEDLISTVIEWSTYLE,
+        Some(WPARAM(LVS_EX_FULLROWSELECT as usize)),
+        Some(LPARAM(LVS_EX_FULLROWSELECT as isize)),
+    );
+    for (index, column) in CONNECTION_COLUMNS.iter().enumerate() {
+        let fmt = if column.align_right {
+            LVCFMT_RIGHT
+        } else {
+            LVCFMT_LEFT
+        };
+        task_list::add_column(list, column.title, index as i32, column.window_width, fmt);
+    }
+}
+
+// Each item's lParam is the owner's PID, or -1 if it isn't known
+unsafe fn fill_list(hwnd: HWND) {
+    let state = state::get(main_window(hwnd));
+    let list = connection_list(hwnd);
+    SendMessageW(list, LVM_DELETEALLITEMS, None, None);
+
+    let result = state.source.borrow_mut().connections();
+    let connections = match result {
+        Ok(connections) => connections,
+        Err(e) => {
+            task_list::show_error(hwnd, &format!("Couldn't list connections: {}", e));
+            return;
+        }
+    };
+    for (index, row) in connection::connection_rows(connections, &state.pid_map)
+        .iter()
+        .enumerate()
+    {
+        for (sub_item, column) in CONNECTION_COLUMNS.iter().enumerate() {
+            let mut text = U16CString::from_str_truncate((column.text)(row));
+            let mut item = LVITEMW {
+                mask: LVIF_TEXT,
+                iItem: index as i32,
+                iSubItem: sub_item as i32,
+                pszText: PWSTR(text.as_mut_ptr()),
+                ..Default::default()
+            };
+            let message = if sub_item == 0 {
+                item.mask |= LVIF_PARAM;
+                item.lParam = LPARAM(row.connection.pid.map_or(-1, |pid| pid as isize));
+                LVM_INSERTITEMW
+            } else {
+                LVM_SETITEMW
+            };
+            SendMessageW(list, message, None, Some(LPARAM(&raw mut item as isize)));
+        }
+    }
+}
+
+unsafe fn end_owning_process(hwnd: HWND) {
+    let list = connection_list(hwnd);
+    let selected_item = task_list::get_selected_task(list);
+    if selected_item < 0 {
+        return;
+    }
+    let mut item = LVITEMW {
+        mask: LVIF_PARAM,
+        iItem: selected_item as i32,
+        ..Default::default()
+    };
+    SendMessageW(
+        list,
+        LVM_GETITEMW,
+        None,
+        Some(LPARAM(&raw mut item as isize)),
+    );
+    let Ok(pid) = u32::try_from(item.lParam.0) else {
+        return;
+    };
+
+    let state = state::get(main_window(hwnd));
+    let result = state.source.borrow_mut().kill(pid);
+    if let Err(e) = result {
+        task_list::show_error(hwnd, &format!("Couldn't end process {}: {}", pid, e));
+    }
+    fill_list(hwnd);
+}
@@ -0,0 +1,78 @@
+// A process's environment variables, for checking how a service was started.
+// The window's Environment dialog and the terminal interface's environment
+// view search, copy and export the same list.
+
+use std::io::{Error, ErrorKind};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variable {
+    pub name: String,
+    pub value: String,
+}
+
+impl Variable {
+    // The way `env` prints it
+    pub fn to_line(&self) -> String {
+        format!("{}={}", self.name, self.value)
+    }
+}
+
+// Parses the NAME=value entries of an environment block, sorted by name.
+// Windows keeps each drive's current directory in entries like "=C:=C:\dir",
+// so the name is split off at the first '=' after its first character.
+pub fn parse_entries(entries: impl Iterator<Item = String>) -> Vec<Variable> {
+    let mut variables: Vec<Variable> = entries
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let split = entry
+                .char_indices()
+                .skip(1)
+                .find(|&(_, c)| c == '=')
+                .map(|(index, _)| index);
+            match split {
+                Some(index) => Variable {
+                    name: entry[..index].to_string(),
+                    value: entry[index + 1..].to_string(),
+                },
+                None => Variable {
+                    name: entry,
+                    value: String::new(),
+                },
+            }
+        })
+        .collect();
+    variables.sort_by_key(|variable| variable.name.to_lowercase());
+    variables
+}
+
+// Variables whose name or value contains `text`, ignoring case
+pub fn filter<'a>(variables: &'a [Variable], text: &str) -> Vec<&'a Variable> {
+    let text = text.to_lowercase();
+    variables
+        .iter()
+        .filter(|variable| {
+            variable.name.to_lowercase().contains(&text)
+                || variable.value.to_lowercase().contains(&text)
+        })
+        .collect()
+}
+
+// What copy and export produce: a NAME=value line for each variable
+pub fn to_text(variables: &[&Variable], line_ending: &str) -> String {
+    variables
+        .iter()
+        .map(|variable| variable.to_line() + line_ending)
+        .collect()
+}
+
+// A process's environment is only readable by its owner and by root or an
+// administrator, which a bare "access denied" doesn't say
+pub fn access_error(error: Error) -> Error {
+    if error.kind() != ErrorKind::PermissionDenied {
+        return error;
+    }
+    Error::new(
+        ErrorKind::PermissionDenied,
+        "access denied: only the process's own user or an administrator can read its environment",
+    )
+}
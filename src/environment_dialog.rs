@@ -0,0 +1,277 @@
+// Environment on the task context menu: the variables of a process as of when
+// the dialog was opened, narrowed down by the search box. Copy takes the
+// selected ones, or all that are shown if none are; Export saves what's shown.
+
+use std::fs;
+
+use widestring::U16CString;
+use windows::{
+    core::{w, Error, Result, PCWSTR, PWSTR},
+    Win32::{
+        Foundation::{GlobalFree, HANDLE, HINSTANCE, HWND, LPARAM, MAX_PATH, WPARAM},
+        System::{
+            DataExchange::{CloseClipboard, EmptyClipboard, OpenClipboard, SetClipboardData},
+            LibraryLoader::GetModuleHandleW,
+            Memory::{GlobalAlloc, GlobalLock, GlobalUnlock, GMEM_MOVEABLE},
+        },
+        UI::{
+            Controls::{
+                Dialogs::{
+                    GetSaveFileNameW, OFN_OVERWRITEPROMPT, OFN_PATHMUSTEXIST, OPENFILENAMEW,
+                },
+                EM_SETCUEBANNER, LVCFMT_LEFT, LVIF_TEXT, LVITEMW, LVM_DELETEALLITEMS,
+                LVM_GETNEXTITEM, LVM_INSERTITEMW, LVM_SETEXTENDEDLISTVIEWSTYLE, LVM_SETITEMW,
+                LVNI_SELECTED, LVS_EX_FULLROWSELECT,
+            },
+            WindowsAndMessaging::*,
+        },
+    },
+};
+
+use crate::{
+    environment::{self, Variable},
+    process::Process,
+    resources::{
+        to_pcwstr, FALSE, IDC_COPY_ENVIRONMENT, IDC_ENVIRONMENT_LIST, IDC_ENVIRONMENT_SEARCH,
+        IDC_EXPORT_ENVIRONMENT, IDD_ENVIRONMENT, TRUE,
+    },
+    search_box, state, task_list,
+};
+
+const IDOK: usize = windows::Win32::UI::WindowsAndMessaging::IDOK.0 as usize;
+const IDCANCEL: usize = windows::Win32::UI::WindowsAndMessaging::IDCANCEL.0 as usize;
+// From Win32::System::Ole, which is a lot of feature to pull in for one number
+const CF_UNICODETEXT: u32 = 13;
+
+// Handed to the dialog through its lParam, and kept in GWLP_USERDATA
+struct EnvironmentParams {
+    pid: u32,
+    image_name: String,
+    variables: Vec<Variable>,
+}
+
+pub fn show(main_window: HWND, process: &Process) {
+    let state = unsafe { state::get(main_window) };
+    let result = state.source.borrow_mut().environment(process.pid);
+    let variables = match result {
+        Ok(variables) => variables,
+        Err(e) => {
+            task_list::show_error(
+                main_window,
+                &format!(
+                    "Couldn't read the environment of {} ({}): {}",
+                    process.image_name, process.pid, e
+                ),
+            );
+            return;
+        }
+    };
+    let mut params = EnvironmentParams {
+        pid: process.pid,
+        image_name: process.image_name.clone(),
+        variables,
+    };
+    unsafe {
+        let instance = HINSTANCE(GetModuleHandleW(None).expect("shouldn't fail").0);
+        let _ = DialogBoxParamW(
+            Some(instance),
+            to_pcwstr(IDD_ENVIRONMENT),
+            Some(main_window),
+            Some(dialog_proc),
+            LPARAM(&raw mut params as isize),
+        );
+    }
+}
+
+unsafe extern "system" fn dialog_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> isize {
+    let id = wparam.0 & 0xffff;
+    match msg {
+        WM_INITDIALOG => {
+            SetWindowLongPtrW(hwnd, GWLP_USERDATA, lparam.0);
+            init(hwnd);
+            fill_list(hwnd);
+            TRUE
+        }
+        WM_COMMAND
+            if id == IDC_ENVIRONMENT_SEARCH as usize && (wparam.0 >> 16) as u32 == EN_CHANGE =>
+        {
+            fill_list(hwnd);
+            TRUE
+        }
+        WM_COMMAND if id == IDC_COPY_ENVIRONMENT as usize => {
+            copy(hwnd);
+            TRUE
+        }
+        WM_COMMAND if id == IDC_EXPORT_ENVIRONMENT as usize => {
+            export(hwnd);
+            TRUE
+        }
+        WM_COMMAND if wparam.0 == IDOK || wparam.0 == IDCANCEL => {
+            let _ = EndDialog(hwnd, IDOK as isize);
+            TRUE
+        }
+        _ => FALSE,
+    }
+}
+
+// Lives on show's stack for as long as the dialog is up
+unsafe fn params<'a>(hwnd: HWND) -> &'a EnvironmentParams {
+    &*(GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const EnvironmentParams)
+}
+
+unsafe fn variable_list(hwnd: HWND) -> HWND {
+    GetDlgItem(Some(hwnd), IDC_ENVIRONMENT_LIST).expect("the dialog has a list")
+}
+
+unsafe fn search_text(hwnd: HWND) -> String {
+    GetDlgItem(Some(hwnd), IDC_ENVIRONMENT_SEARCH)
+        .map(search_box::get_text)
+        .unwrap_or_default()
+}
+
+// The variables the list shows, in the same order
+unsafe fn shown<'a>(hwnd: HWND) -> Vec<&'a Variable> {
+    environment::filter(&params(hwnd).variables, &search_text(hwnd))
+}
+
+unsafe fn init(hwnd: HWND) {
+    let params = params(hwnd);
+    let title = U16CString::from_str_truncate(format!(
+        "Environment of {} ({})",
+        params.image_name, params.pid
+    ));
+    let _ = SetWindowTextW(hwnd, PCWSTR(title.as_ptr()));
+
+    if let Ok(search) = GetDlgItem(Some(hwnd), IDC_ENVIRONMENT_SEARCH) {
+        let cue = w!("Search by name or value");
+        SendMessageW(
+            search,
+            EM_SETCUEBANNER,
+            Some(WPARAM(0)),
+            Some(LPARAM(cue.as_ptr() as isize)),
+        );
+    }
+
+    let list = variable_list(hwnd);
+    SendMessageW(
+        list,
+        LVM_SETEXTENDEDLISTVIEWSTYLE,
+        Some(WPARAM(LVS_EX_FULLROWSELECT as usize)),
+        Some(LPARAM(LVS_EX_FULLROWSELECT as isize)),
+    );
+    task_list::add_column(list, "Name", 0, 180, LVCFMT_LEFT);
+    task_list::add_column(list, "Value", 1, 440, LVCFMT_LEFT);
+}
+
+unsafe fn fill_list(hwnd: HWND) {
+    let list = variable_list(hwnd);
+    SendMessageW(list, LVM_DELETEALLITEMS, None, None);
+    for (index, variable) in shown(hwnd).iter().enumerate() {
+        for (sub_item, text) in [&variable.name, &variable.value].into_iter().enumerate() {
+            let mut text = U16CString::from_str_truncate(text);
+            let mut item = LVITEMW {
+                mask: LVIF_TEXT,
+                iItem: index as i32,
+                iSubItem: sub_item as i32,
+                pszText: PWSTR(text.as_mut_ptr()),
+                ..Default::default()
+            };
+            let message = if sub_item == 0 {
+                LVM_INSERTITEMW
+            } else {
+                LVM_SETITEMW
+            };
+            SendMessageW(list, message, None, Some(LPARAM(&raw mut item as isize)));
+        }
+    }
+}
+
+unsafe fn selected_indexes(list: HWND) -> Vec<usize> {
+    let mut indexes = Vec::new();
+    let mut index = -1;
+    loop {
+        index = SendMessageW(
+            list,
+            LVM_GETNEXTITEM,
+            Some(WPARAM(index as usize)),
+            Some(LPARAM(LVNI_SELECTED as isize)),
+        )
+        .0;
+        if index < 0 {
+            return indexes;
+        }
+        indexes.push(index as usize);
+    }
+}
+
+unsafe fn copy(hwnd: HWND) {
+    let shown = shown(hwnd);
+    let selected: Vec<&Variable> = selected_indexes(variable_list(hwnd))
+        .into_iter()
+        .filter_map(|index| shown.get(index).copied())
+        .collect();
+    let variables = if selected.is_empty() { shown } else { selected };
+    if let Err(e) = set_clipboard_text(hwnd, &environment::to_text(&variables, "\r\n")) {
+        task_list::show_error(hwnd, &format!("Couldn't copy to the clipboard: {}", e));
+    }
+}
+
+// Once SetClipboardData succeeds, the clipboard owns the memory
+unsafe fn set_clipboard_text(hwnd: HWND, text: &str) -> Result<()> {
+    let text = U16CString::from_str_truncate(text);
+    let text = text.as_slice_with_nul();
+    let memory = GlobalAlloc(GMEM_MOVEABLE, text.len() * 2)?;
+    let pointer = GlobalLock(memory) as *mut u16;
+    if pointer.is_null() {
+        let _ = GlobalFree(Some(memory));
+        return Err(Error::from_thread());
+    }
+    std::ptr::copy_nonoverlapping(text.as_ptr(), pointer, text.len());
+    let _ = GlobalUnlock(memory);
+
+    let result = OpenClipboard(Some(hwnd)).and_then(|()| {
+        EmptyClipboard()?;
+        SetClipboardData(CF_UNICODETEXT, Some(HANDLE(memory.0)))
+    });
+    let _ = CloseClipboard();
+    if result.is_err() {
+        let _ = GlobalFree(Some(memory));
+    }
+    result.map(|_| ())
+}
+
+unsafe fn export(hwnd: HWND) {
+    let params = params(hwnd);
+    let mut path = [0u16; MAX_PATH as usize];
+    let default_name = format!("{}-{}.env", params.image_name, params.pid);
+    // The last unit stays 0 to terminate the name
+    let len = path.len();
+    for (slot, c) in path[..len - 1].iter_mut().zip(default_name.encode_utf16()) {
+        *slot = c;
+    }
+    let mut save = OPENFILENAMEW {
+        lStructSize: size_of::<OPENFILENAMEW>() as u32,
+        hwndOwner: hwnd,
+        lpstrFilter: w!("Environment files (*.env)\0*.env\0All files (*.*)\0*.*\0"),
+        lpstrFile: PWSTR(path.as_mut_ptr()),
+        nMaxFile: path.len() as u32,
+        lpstrDefExt: w!("env"),
+        Flags: OFN_OVERWRITEPROMPT | OFN_PATHMUSTEXIST,
+        ..Default::default()
+    };
+    // False when cancelled
+    if !GetSaveFileNameW(&mut save).as_bool() {
+        return;
+    }
+
+    let len = path.iter().position(|&c| c == 0).unwrap_or(path.len());
+    let path = String::from_utf16_lossy(&path[..len]);
+    if let Err(e) = fs::write(&path, environment::to_text(&shown(hwnd), "\r\n")) {
+        task_list::show_error(hwnd, &format!("Couldn't export to {}: {}", path, e));
+    }
+}
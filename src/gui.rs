@@ -0,0 +1,257 @@
+use std::{cell::RefCell, ffi::c_void, io, mem::transmute, rc::Rc};
+
+use crate::resources::{FALSE, IDD_ABOUTBOX, ID_UPDATE_TIMER, TRUE};
+use windows::{
+    core::{w, Result},
+    Win32::{
+        Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, WPARAM},
+        System::LibraryLoader::GetModuleHandleW,
+        UI::{
+            Controls::{LVN_COLUMNCLICK, LVN_GETDISPINFO, LVN_KEYDOWN, NMHDR, NM_DBLCLK},
+            WindowsAndMessaging::*,
+        },
+    },
+};
+
+use crate::{
+    cli::SourceOptions,
+    columns_dialog, connections_dialog,
+    process::{MemoryMode, Priority, ProcessSource},
+    recording::Recorder,
+    resources::{self, to_pcwstr, IDC_TASKMANAGER},
+    run_dialog, search_box, state, status_bar, system, task_list, window, REFRESH_INTERVAL_MS,
+};
+
+// Handed to WM_CREATE through CreateWindowExW's lpParam
+struct CreateParams {
+    source: Box<dyn ProcessSource>,
+    recorder: Option<Recorder>,
+    num_cpus: u32,
+}
+
+pub fn run(options: &SourceOptions) -> io::Result<()> {
+    let source = options.open_source()?;
+    let num_cpus = source.num_cpus().unwrap_or_else(system::get_num_cpus);
+    let recorder = options.open_recorder(num_cpus)?;
+    let mut create_params = Some(CreateParams {
+        source,
+        recorder,
+        num_cpus,
+    });
+    Ok(run_message_loop(&mut create_params)?)
+}
+
+fn run_message_loop(create_params: &mut Option<CreateParams>) -> Result<()> {
+    unsafe {
+        let instance = HINSTANCE(GetModuleHandleW(None)?.0);
+
+        let window_class = w!("window");
+        window::init_common_controls();
+        window::register_class(&instance, &window_class, wndproc)?;
+        window::create_window(
+            &instance,
+            &window_class,
+            create_params as *mut Option<CreateParams> as *const c_void,
+        )?;
+
+        let accel = LoadAcceleratorsW(Some(instance), to_pcwstr(IDC_TASKMANAGER))?;
+        let mut message = MSG::default();
+        while GetMessageW(&mut message, None, 0, 0).into() {
+            if TranslateAcceleratorW(message.hwnd, accel, &message) == 0 {
+                let _ = TranslateMessage(&message);
+                DispatchMessageW(&message);
+            }
+        }
+    }
+    Ok(())
+}
+
+unsafe extern "system" fn wndproc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    match msg {
+        WM_CREATE => on_wm_create(hwnd, lparam),
+        WM_COMMAND => on_wm_command(hwnd, msg, wparam, lparam),
+        WM_DESTROY => on_wm_destroy(hwnd),
+        WM_TIMER => on_wm_timer(hwnd),
+        WM_NOTIFY => on_wm_notify(hwnd, wparam, lparam),
+        WM_SIZE => on_wm_size(hwnd),
+        WM_CONTEXTMENU => on_wm_contextmenu(hwnd, lparam),
+        _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+}
+
+const IDOK: usize = windows::Win32::UI::WindowsAndMessaging::IDOK.0 as usize;
+const IDCANCEL: usize = windows::Win32::UI::WindowsAndMessaging::IDCANCEL.0 as usize;
+unsafe extern "system" fn aboutdlgproc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    _lparam: LPARAM,
+) -> isize {
+    match msg {
+        WM_COMMAND if wparam.0 == IDOK || wparam.0 == IDCANCEL => {
+            let _ = EndDialog(hwnd, IDOK as isize);
+            TRUE
+        }
+        WM_INITDIALOG | WM_COMMAND => TRUE,
+        _ => FALSE,
+    }
+}
+
+fn on_wm_create(hwnd: HWND, lparam: LPARAM) -> LRESULT {
+    unsafe {
+        // safety: lpCreateParams is the Option<CreateParams> run_message_loop
+        // passed to CreateWindowExW, which is still on its stack
+        let create_struct = &*(lparam.0 as *const CREATESTRUCTW);
+        let create_params = &mut *(create_struct.lpCreateParams as *mut Option<CreateParams>);
+        let create_params = create_params.take().expect("window created twice");
+
+        let instance = HINSTANCE(GetModuleHandleW(None).expect("shouldn't fail").0);
+        let task_list_hwnd = task_list::create_control(&instance, hwnd).expect("shouldn't fail");
+        let status_bar_hwnd = status_bar::create_control(&instance, hwnd).expect("shouldn't fail");
+        let search_box_hwnd = search_box::create_control(&instance, hwnd).expect("shouldn't fail");
+
+        state::initialize(
+            hwnd,
+            task_list_hwnd,
+            status_bar_hwnd,
+            search_box_hwnd,
+            create_params.num_cpus,
+            Rc::new(RefCell::new(create_params.source)),
+            create_params.recorder.map(|r| Rc::new(RefCell::new(r))),
+        );
+        task_list::check_memory_mode(hwnd, MemoryMode::default());
+
+        let _ = task_list::refresh_process_list(hwnd, false);
+        let _ = on_wm_timer(hwnd);
+        SetTimer(
+            Some(hwnd),
+            ID_UPDATE_TIMER as usize,
+            REFRESH_INTERVAL_MS,
+            None,
+        );
+    }
+    LRESULT(0)
+}
+
+fn on_wm_destroy(hwnd: HWND) -> LRESULT {
+    unsafe {
+        let _ = KillTimer(Some(hwnd), ID_UPDATE_TIMER as usize);
+        state::destroy(hwnd);
+        PostQuitMessage(0);
+        LRESULT(0)
+    }
+}
+
+fn on_wm_timer(hwnd: HWND) -> LRESULT {
+    let state = unsafe { state::get(hwnd) };
+    if let Err(e) = task_list::refresh_process_list(hwnd, true) {
+        let message = if e.kind() == io::ErrorKind::UnexpectedEof {
+            "End of recording".to_string()
+        } else {
+            format!("Couldn't refresh: {}", e)
+        };
+        status_bar::show_message(hwnd, &message);
+        return LRESULT(0);
+    }
+
+    let system_status = status_bar::get_system_status(&state);
+    status_bar::update(hwnd, &system_status);
+
+    if let Some(recorder) = &state.recorder {
+        // Re-read the state to pick up the processes from this refresh
+        let state = unsafe { state::get(hwnd) };
+        let processes = state.pid_map.values().map(Rc::as_ref);
+        if let Err(e) = recorder.borrow_mut().record(processes, &system_status) {
+            // Stopped so the error isn't shown again every tick
+            unsafe { state::stop_recording(hwnd) };
+            task_list::show_error(hwnd, &format!("Recording stopped: {}", e));
+        }
+    }
+    LRESULT(0)
+}
+
+unsafe fn on_wm_command(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    let id = (wparam.0 & 0xffff) as u16;
+    match id {
+        resources::IDM_NEW_TASK => {
+            if let Err(err) = run_dialog::show(hwnd) {
+                task_list::show_error(hwnd, &format!("Couldn't open the Run dialog: {}", err));
+            }
+            LRESULT(0)
+        }
+        resources::IDM_EXIT => {
+            DestroyWindow(hwnd).unwrap();
+            LRESULT(0)
+        }
+        resources::IDM_ABOUT => {
+            let instance = HINSTANCE(GetModuleHandleW(None).expect("shouldn't fail").0);
+            let _ = DialogBoxParamW(
+                Some(instance),
+                to_pcwstr(IDD_ABOUTBOX),
+                Some(hwnd),
+                Some(aboutdlgproc),
+                LPARAM(0),
+            );
+            LRESULT(0)
+        }
+        resources::IDM_END_TASK => task_list::on_end_task_clicked(hwnd),
+        resources::IDM_OPEN_FILE_LOCATION => task_list::on_open_file_location_clicked(hwnd),
+        id @ resources::IDM_PRIORITY_REALTIME..=resources::IDM_PRIORITY_LOW => {
+            let priority = Priority::ALL[(id - resources::IDM_PRIORITY_REALTIME) as usize];
+            task_list::on_set_priority_clicked(hwnd, priority)
+        }
+        resources::IDM_SET_AFFINITY => task_list::on_set_affinity_clicked(hwnd),
+        resources::IDM_SECURITY_DETAILS => task_list::on_security_details_clicked(hwnd),
+        resources::IDM_ENVIRONMENT => task_list::on_environment_clicked(hwnd),
+        id @ resources::IDM_MEMORY_PRIVATE..=resources::IDM_MEMORY_USS => {
+            let memory_mode = MemoryMode::ALL[(id - resources::IDM_MEMORY_PRIVATE) as usize];
+            task_list::on_memory_mode_clicked(hwnd, memory_mode)
+        }
+        resources::IDM_TREE_VIEW => task_list::on_tree_view_clicked(hwnd),
+        resources::IDM_ONLY_MY_PROCESSES => task_list::on_only_my_processes_clicked(hwnd),
+        resources::IDM_ONLY_ELEVATED => task_list::on_only_elevated_clicked(hwnd),
+        resources::IDM_USER_TOTALS => task_list::on_user_totals_clicked(hwnd),
+        resources::IDM_CONNECTIONS => {
+            connections_dialog::show(hwnd);
+            LRESULT(0)
+        }
+        resources::IDM_SELECT_COLUMNS => {
+            columns_dialog::show(hwnd);
+            LRESULT(0)
+        }
+        id if id as i32 == resources::ID_SEARCH_BOX && (wparam.0 >> 16) as u32 == EN_CHANGE => {
+            task_list::on_search_changed(hwnd)
+        }
+        _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+}
+
+unsafe fn on_wm_notify(hwnd: HWND, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    let lpnmh = transmute::<LPARAM, *const NMHDR>(lparam);
+    let code = (*lpnmh).code;
+    match code {
+        LVN_GETDISPINFO => task_list::on_get_display_info(hwnd, lparam),
+        LVN_COLUMNCLICK => task_list::on_column_click(hwnd, lparam),
+        LVN_KEYDOWN => task_list::on_key_down(hwnd, lparam),
+        NM_DBLCLK => task_list::on_double_click(hwnd, lparam),
+        _ => {
+            return DefWindowProcW(hwnd, WM_NOTIFY, wparam, lparam);
+        }
+    }
+    LRESULT(0)
+}
+
+fn on_wm_size(hwnd: HWND) -> LRESULT {
+    // safety: WM_CREATE will ensure the state has been stored in the window first
+    let state = unsafe { state::get(hwnd) };
+    task_list::resize_to_parent(state.task_list, hwnd, state.status_bar, state.search_box);
+    LRESULT(0)
+}
+
+unsafe fn on_wm_contextmenu(hwnd: HWND, lparam: LPARAM) -> LRESULT {
+    let lparam = lparam.0 as i32;
+    let x = lparam & 0xFFFF;
+    let y = (lparam >> 16) & 0xFFFF;
+    task_list::on_show_contextmenu(hwnd, x, y);
+    LRESULT(0)
+}
@@ -0,0 +1,301 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, ErrorKind, Result, Write},
+    rc::Rc,
+    thread,
+    time::Duration,
+};
+
+use human_bytes::human_bytes;
+
+use crate::{
+    cli::{ListOptions, OutputFormat},
+    column::{self, Value},
+    connection::{self, Connection, CONNECTION_COLUMNS},
+    process::{self, Process, ProcessSource},
+    recording::Recorder,
+    rows::{self, Filter},
+    system::{self, CpuSampler},
+    REFRESH_INTERVAL_MS,
+};
+
+pub fn run(options: &ListOptions) -> Result<()> {
+    let mut source = options.source.open_source()?;
+    source.set_memory_mode(options.memory_mode);
+    let num_cpus = source.num_cpus().unwrap_or_else(system::get_num_cpus);
+    let mut recorder = options.source.open_recorder(num_cpus)?;
+    let mut cpu_sampler = CpuSampler::new();
+
+    // CPU usage is measured between two samples, same as a refresh tick in
+    // the window.
+    let first_sample = process::get_processes(source.as_mut(), &HashMap::new(), num_cpus)?;
+    record(
+        &mut recorder,
+        source.as_ref(),
+        &mut cpu_sampler,
+        &first_sample,
+    )?;
+    if options.source.is_local() {
+        thread::sleep(Duration::from_millis(REFRESH_INTERVAL_MS as u64));
+    }
+    let pid_map = process::get_processes(source.as_mut(), &first_sample, num_cpus)?;
+    record(&mut recorder, source.as_ref(), &mut cpu_sampler, &pid_map)?;
+
+    let filter = Filter {
+        text: options.filter.clone(),
+        user: options
+            .mine
+            .then(|| process::sampling_user(source.as_ref())),
+        elevated_only: options.elevated,
+    };
+    let (columns, cells) = if options.users {
+        user_cells(&pid_map, options)
+    } else if options.connections {
+        connection_cells(source.connections()?, &pid_map)
+    } else {
+        process_cells(&pid_map, options, &filter)
+    };
+
+    let mut out = io::stdout().lock();
+    let result = match options.format {
+        OutputFormat::Table => write_table(&mut out, &columns, &cells),
+        OutputFormat::Json => write_json(&mut out, &columns, &cells),
+        OutputFormat::Csv => write_csv(&mut out, &columns, &cells),
+    };
+
+    // Piping into something like `head` closes stdout early, which is fine
+    match result {
+        Err(err) if err.kind() == ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+// A column of output, whether it lists processes, users or connections
+struct OutputColumn {
+    title: String,
+    key: &'static str,
+    align_right: bool,
+}
+
+// A cell as the table shows it, and as JSON and CSV see it
+type Cell = (String, Value);
+
+fn process_cells(
+    pid_map: &HashMap<u32, Rc<Process>>,
+    options: &ListOptions,
+    filter: &Filter,
+) -> (Vec<OutputColumn>, Vec<Vec<Cell>>) {
+    let rows = if options.tree {
+        rows::tree_rows(
+            pid_map,
+            options.sort_state,
+            &HashSet::new(),
+            filter,
+            options.memory_mode,
+        )
+    } else {
+        rows::flat_rows(pid_map, options.sort_state, filter, options.memory_mode)
+    };
+    let columns = options
+        .columns
+        .iter()
+        .map(|c| OutputColumn {
+            title: column::title(c, options.memory_mode),
+            key: c.key,
+            align_right: c.align_right,
+        })
+        .collect();
+    let cells = rows
+        .iter()
+        .map(|row| {
+            options
+                .columns
+                .iter()
+                .map(|c| ((c.text)(row), (c.value)(row)))
+                .collect()
+        })
+        .collect();
+    (columns, cells)
+}
+
+fn user_cells(
+    pid_map: &HashMap<u32, Rc<Process>>,
+    options: &ListOptions,
+) -> (Vec<OutputColumn>, Vec<Vec<Cell>>) {
+    let columns = vec![
+        OutputColumn {
+            title: "User".to_string(),
+            key: "user",
+            align_right: false,
+        },
+        OutputColumn {
+            title: "Processes".to_string(),
+            key: "processes",
+            align_right: true,
+        },
+        OutputColumn {
+            title: "CPU".to_string(),
+            key: "cpu",
+            align_right: true,
+        },
+        OutputColumn {
+            title: format!("Memory ({})", options.memory_mode.name()),
+            key: "memory",
+            align_right: true,
+        },
+    ];
+    let cells = rows::user_totals(pid_map, options.memory_mode)
+        .into_iter()
+        .map(|total| {
+            vec![
+                (total.user.clone(), Value::Text(total.user)),
+                (
+                    total.processes.to_string(),
+                    Value::Number(total.processes as u64),
+                ),
+                (total.cpu_usage.to_string(), Value::Number(total.cpu_usage)),
+                (
+                    human_bytes(total.memory as f64),
+                    Value::Number(total.memory as u64),
+                ),
+            ]
+        })
+        .collect();
+    (columns, cells)
+}
+
+fn connection_cells(
+    connections: Vec<Connection>,
+    pid_map: &HashMap<u32, Rc<Process>>,
+) -> (Vec<OutputColumn>, Vec<Vec<Cell>>) {
+    let columns = CONNECTION_COLUMNS
+        .iter()
+        .map(|c| OutputColumn {
+            title: c.title.to_string(),
+            key: c.key,
+            align_right: c.align_right,
+        })
+        .collect();
+    let cells = connection::connection_rows(connections, pid_map)
+        .iter()
+        .map(|row| {
+            CONNECTION_COLUMNS
+                .iter()
+                .map(|c| ((c.text)(row), (c.value)(row)))
+                .collect()
+        })
+        .collect();
+    (columns, cells)
+}
+
+fn record(
+    recorder: &mut Option<Recorder>,
+    source: &dyn ProcessSource,
+    cpu_sampler: &mut CpuSampler,
+    pid_map: &HashMap<u32, Rc<Process>>,
+) -> Result<()> {
+    let Some(recorder) = recorder else {
+        return Ok(());
+    };
+    let system_status = source
+        .system_status()
+        .unwrap_or_else(|| system::get_system_status(cpu_sampler));
+    recorder.record(pid_map.values().map(Rc::as_ref), &system_status)
+}
+
+fn write_table(out: &mut impl Write, columns: &[OutputColumn], rows: &[Vec<Cell>]) -> Result<()> {
+    let header: Vec<String> = columns.iter().map(|c| c.title.clone()).collect();
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| row.iter().map(|(text, _)| text.clone()).collect())
+        .collect();
+
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    // Same alignment as the task list columns: text left, numbers right
+    for row in std::iter::once(&header).chain(&cells) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(columns)
+            .zip(&widths)
+            .map(|((cell, column), &width)| {
+                if column.align_right {
+                    format!("{:>width$}", cell)
+                } else {
+                    format!("{:<width$}", cell)
+                }
+            })
+            .collect();
+        writeln!(out, "{}", cells.join("  ").trim_end())?;
+    }
+    Ok(())
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn write_json(out: &mut impl Write, columns: &[OutputColumn], rows: &[Vec<Cell>]) -> Result<()> {
+    writeln!(out, "[")?;
+    for (i, row) in rows.iter().enumerate() {
+        let fields: Vec<String> = columns
+            .iter()
+            .zip(row)
+            .map(|(column, (_, value))| {
+                let value = match value {
+                    Value::Number(n) => n.to_string(),
+                    Value::Text(s) => json_string(s),
+                    Value::Empty => "null".to_string(),
+                };
+                format!("{}: {}", json_string(column.key), value)
+            })
+            .collect();
+        let separator = if i + 1 < rows.len() { "," } else { "" };
+        writeln!(out, "  {{{}}}{}", fields.join(", "), separator)?;
+    }
+    writeln!(out, "]")
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn write_csv(out: &mut impl Write, columns: &[OutputColumn], rows: &[Vec<Cell>]) -> Result<()> {
+    let header: Vec<&str> = columns.iter().map(|c| c.key).collect();
+    writeln!(out, "{}", header.join(","))?;
+    for row in rows {
+        let fields: Vec<String> = row
+            .iter()
+            .map(|(_, value)| match value {
+                Value::Number(n) => n.to_string(),
+                Value::Text(s) => csv_field(s),
+                Value::Empty => String::new(),
+            })
+            .collect();
+        writeln!(out, "{}", fields.join(","))?;
+    }
+    Ok(())
+}
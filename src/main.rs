@@ -1,184 +1,103 @@
 //#![windows_subsystem = "windows"]
 
-use std::mem::transmute;
-
-use resources::{FALSE, IDD_ABOUTBOX, ID_UPDATE_TIMER, TRUE};
-use windows::{
-    core::{w, Result},
-    Win32::{
-        Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, WPARAM},
-        System::{
-            LibraryLoader::GetModuleHandleW,
-            SystemInformation::{GetSystemInfo, SYSTEM_INFO},
-        },
-        UI::{
-            Controls::{LVN_COLUMNCLICK, LVN_GETDISPINFO, NMHDR},
-            WindowsAndMessaging::*,
-        },
-    },
-};
-
-use crate::resources::{to_pcwstr, IDC_TASKMANAGER};
-
+use std::{io, process::ExitCode};
+
+use cli::{Command, SourceOptions};
+
+#[cfg(windows)]
+mod affinity_dialog;
+mod agent;
+mod binary;
+mod cli;
+mod column;
+#[cfg(windows)]
+mod columns_dialog;
+mod connection;
+#[cfg(windows)]
+mod connections_dialog;
+mod environment;
+#[cfg(windows)]
+mod environment_dialog;
+#[cfg(windows)]
+mod gui;
+mod list;
 mod process;
+mod recording;
+#[cfg(windows)]
 mod resources;
+mod rows;
+#[cfg(windows)]
 mod run_dialog;
+#[cfg(windows)]
+mod search_box;
+mod security;
+mod snapshot;
+mod sort;
+#[cfg(windows)]
 mod state;
+#[cfg(windows)]
 mod status_bar;
+mod status_text;
 mod system;
+#[cfg(windows)]
 mod task_list;
+#[cfg(target_os = "linux")]
+mod tui;
+#[cfg(windows)]
 mod window;
 
 const REFRESH_INTERVAL_MS: u32 = 1000;
 
-fn main() -> Result<()> {
-    unsafe {
-        let instance = HINSTANCE(GetModuleHandleW(None)?.0);
-
-        let window_class = w!("window");
-        window::init_common_controls();
-        window::register_class(&instance, &window_class, wndproc)?;
-        window::create_window(&instance, &window_class)?;
-
-        let accel = LoadAcceleratorsW(Some(instance), to_pcwstr(IDC_TASKMANAGER))?;
-        let mut message = MSG::default();
-        while GetMessageW(&mut message, None, 0, 0).into() {
-            if TranslateAcceleratorW(message.hwnd, accel, &message) == 0 {
-                let _ = TranslateMessage(&message);
-                DispatchMessageW(&message);
-            }
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match cli::parse(&args) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("taskmanager: {}\n\n{}", err, cli::USAGE);
+            return ExitCode::from(2);
         }
-    }
-    Ok(())
-}
+    };
 
-unsafe extern "system" fn wndproc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
-    match msg {
-        WM_CREATE => on_wm_create(hwnd),
-        WM_COMMAND => on_wm_command(hwnd, msg, wparam, lparam),
-        WM_DESTROY => on_wm_destroy(hwnd),
-        WM_TIMER => on_wm_timer(hwnd),
-        WM_NOTIFY => on_wm_notify(hwnd, wparam, lparam),
-        WM_SIZE => on_wm_size(hwnd),
-        WM_CONTEXTMENU => on_wm_contextmenu(hwnd, lparam),
-        _ => DefWindowProcW(hwnd, msg, wparam, lparam),
-    }
-}
-
-const IDOK: usize = windows::Win32::UI::WindowsAndMessaging::IDOK.0 as usize;
-const IDCANCEL: usize = windows::Win32::UI::WindowsAndMessaging::IDCANCEL.0 as usize;
-unsafe extern "system" fn aboutdlgproc(
-    hwnd: HWND,
-    msg: u32,
-    wparam: WPARAM,
-    _lparam: LPARAM,
-) -> isize {
-    match msg {
-        WM_COMMAND if wparam.0 == IDOK || wparam.0 == IDCANCEL => {
-            let _ = EndDialog(hwnd, IDOK as isize);
-            TRUE
+    let result = match command {
+        Command::Gui(options) => run_gui(&options),
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            Ok(())
         }
-        WM_INITDIALOG | WM_COMMAND => TRUE,
-        _ => FALSE,
+        Command::List(options) => list::run(&options),
+        Command::Serve(options) => agent::run(&options),
+        Command::Tui(options) => run_tui(&options),
+    };
+
+    if let Err(err) = result {
+        eprintln!("taskmanager: {}", err);
+        return ExitCode::FAILURE;
     }
+    ExitCode::SUCCESS
 }
 
-fn on_wm_create(hwnd: HWND) -> LRESULT {
-    unsafe {
-        let instance = HINSTANCE(GetModuleHandleW(None).expect("shouldn't fail").0);
-        let task_list_hwnd = task_list::create_control(&instance, hwnd).expect("shouldn't fail");
-        let status_bar_hwnd = status_bar::create_control(&instance, hwnd).expect("shouldn't fail");
-        let (query, counter) = system::start_query_data_collection().expect("shouldn't fail");
-
-        let mut system_info = SYSTEM_INFO::default();
-        GetSystemInfo(&mut system_info);
-
-        state::initialize(
-            hwnd,
-            task_list_hwnd,
-            status_bar_hwnd,
-            system_info.dwNumberOfProcessors,
-            query,
-            counter,
-        );
-
-        task_list::refresh_process_list(hwnd, false);
-        let _ = on_wm_timer(hwnd);
-        SetTimer(Some(hwnd), ID_UPDATE_TIMER as usize, REFRESH_INTERVAL_MS, None);
-    }
-    LRESULT(0)
+#[cfg(windows)]
+fn run_gui(options: &SourceOptions) -> io::Result<()> {
+    gui::run(options)
 }
 
-fn on_wm_destroy(hwnd: HWND) -> LRESULT {
-    unsafe {
-        let _ = KillTimer(Some(hwnd), ID_UPDATE_TIMER as usize);
-        state::destroy(hwnd);
-        PostQuitMessage(0);
-        LRESULT(0)
-    }
-}
-
-fn on_wm_timer(hwnd: HWND) -> LRESULT {
-    let state = unsafe { state::get(hwnd) };
-    let _ = system::collect_query_data(state.pdh_query);
-    task_list::refresh_process_list(hwnd, true);
-    status_bar::update(hwnd);
-    LRESULT(0)
-}
-
-unsafe fn on_wm_command(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
-    let id = (wparam.0 & 0xffff) as u16;
-    match id {
-        resources::IDM_NEW_TASK => {
-            if let Err(err) = run_dialog::show(hwnd) {
-                println!("run_file error: {}", err);
-            }
-            LRESULT(0)
-        }
-        resources::IDM_EXIT => {
-            DestroyWindow(hwnd).unwrap();
-            LRESULT(0)
-        }
-        resources::IDM_ABOUT => {
-            let instance = HINSTANCE(GetModuleHandleW(None).expect("shouldn't fail").0);
-            let _ = DialogBoxParamW(
-                Some(instance),
-                to_pcwstr(IDD_ABOUTBOX),
-                Some(hwnd),
-                Some(aboutdlgproc),
-                LPARAM(0),
-            );
-            LRESULT(0)
-        }
-        resources::IDM_END_TASK => task_list::on_end_task_clicked(hwnd),
-        _ => DefWindowProcW(hwnd, msg, wparam, lparam),
-    }
-}
-
-unsafe fn on_wm_notify(hwnd: HWND, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
-    let lpnmh = transmute::<LPARAM, *const NMHDR>(lparam);
-    let code = (*lpnmh).code;
-    match code {
-        LVN_GETDISPINFO => task_list::on_get_display_info(hwnd, lparam),
-        LVN_COLUMNCLICK => task_list::on_column_click(hwnd, lparam),
-        _ => {
-            return DefWindowProcW(hwnd, WM_NOTIFY, wparam, lparam);
-        }
-    }
-    LRESULT(0)
+#[cfg(not(windows))]
+fn run_gui(_options: &SourceOptions) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the task manager window is only available on Windows, try 'taskmanager list'",
+    ))
 }
 
-fn on_wm_size(hwnd: HWND) -> LRESULT {
-    // safety: WM_CREATE will ensure the state has been stored in the window first
-    let state = unsafe { state::get(hwnd) };
-    task_list::resize_to_parent(state.task_list, hwnd, state.status_bar);
-    LRESULT(0)
+#[cfg(target_os = "linux")]
+fn run_tui(options: &SourceOptions) -> io::Result<()> {
+    tui::run(options)
 }
 
-unsafe fn on_wm_contextmenu(hwnd: HWND, lparam: LPARAM) -> LRESULT {
-    let lparam = lparam.0 as i32;
-    let x = lparam & 0xFFFF;
-    let y = (lparam >> 16) & 0xFFFF;
-    task_list::on_show_contextmenu(hwnd, x, y);
-    LRESULT(0)
+#[cfg(not(target_os = "linux"))]
+fn run_tui(_options: &SourceOptions) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the terminal interface is only available on Linux",
+    ))
 }
@@ -1,162 +1,602 @@
-use std::{collections::HashMap, mem::transmute, time::Instant};
-
-use widestring::U16CString;
-use windows::{
-    core::{Result, PCWSTR, PWSTR},
-    Win32::{
-        Foundation::{CloseHandle, FILETIME, HANDLE},
-        System::{
-            ProcessStatus::{
-                EnumProcesses, GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS,
-                PROCESS_MEMORY_COUNTERS_EX2,
-            },
-            Threading::{
-                GetProcessTimes, OpenProcess, QueryFullProcessImageNameW, TerminateProcess,
-                PROCESS_NAME_FORMAT, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_TERMINATE,
-            },
-        },
-        UI::Shell::PathFindFileNameW,
-    },
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Result},
+    rc::Rc,
+    str::FromStr,
+    sync::OnceLock,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    binary::BinaryInfo, connection::Connection, environment::Variable, security::SecurityContext,
+    system::SystemStatus,
 };
 
-use crate::state::TaskManagerState;
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(windows)]
+mod win32;
+
+#[cfg(target_os = "linux")]
+pub use linux::LinuxProcessSource as NativeProcessSource;
+#[cfg(windows)]
+pub use win32::Win32ProcessSource as NativeProcessSource;
+
+#[cfg(target_os = "linux")]
+use linux::current_user as get_current_user;
+#[cfg(windows)]
+use win32::current_user as get_current_user;
 
 #[derive(Debug, Clone)]
 pub struct Process {
     pub pid: u32,
-    pub image_name: U16CString,
+    // 0 if the process has no parent, or it couldn't be found out. The PID
+    // may since have been reused by an unrelated process.
+    pub parent_pid: u32,
+    pub image_name: String,
+    // Full path of the executable, empty when it couldn't be found out
+    pub image_path: String,
+    // The file at `image_path` is no longer the one the process was started
+    // from: it was deleted, or replaced by another
+    pub image_deleted: bool,
+    // Arguments joined into one line, with any that contain spaces quoted.
+    // Empty when it couldn't be read.
+    pub command_line: String,
+    // Account the process runs as, DOMAIN\name on Windows. Empty when
+    // unknown.
+    pub user: String,
+    // Linux only: who started a process that runs as someone else, like a
+    // setuid program. Empty when it's the same as `user`.
+    pub real_user: String,
+    // What the Memory column shows: the private working set, or the closest
+    // counter the OS has when it doesn't keep one
     pub private_working_set: usize,
-    cpu_time: u64,
-    sample_time: Instant,
+    pub memory_counters: MemoryCounters,
+    pub thread_count: u32,
+    // Open handles on Windows, file descriptors on Linux. None when they
+    // can't be counted, like another user's fds.
+    pub handle_count: Option<u32>,
+    // None when it couldn't be found out
+    pub start_time: Option<SystemTime>,
+    // Kernel + user time in 100ns units, regardless of what the OS reports in
+    pub(crate) cpu_time: u64,
+    pub(crate) sample_time: Instant,
+    // The clock time of the sample, which a replayed recording keeps
+    pub sample_wall_time: SystemTime,
+    // None when it couldn't be read
+    pub status: Option<Status>,
+    // The first sample that saw the process in its current status
+    pub(crate) status_since: Instant,
     pub cpu_usage: u64,
+    // Linux only: the logical CPU it last ran on
+    pub last_cpu: Option<u32>,
+    // None when the counters can't be read, like another user's on Linux
+    pub io: Option<IoCounters>,
+    // Bytes per second since the previous sample
+    pub io_read_rate: u64,
+    pub io_write_rate: u64,
+    // None when it couldn't be read
+    pub priority: Option<Priority>,
+    // Linux only: the nice value `priority` was worked out from, -20 to 19
+    pub nice: Option<i32>,
+    // As the OS describes it, like "best-effort 4" on Linux or "Low" on
+    // Windows. Empty when unknown.
+    pub io_priority: String,
+    // Linux only: the scheduling policy, like "Normal" or "FIFO 10". Empty
+    // when unknown.
+    pub scheduling_policy: String,
+    // TCP ports it listens on and UDP ports it's bound to, sorted
+    pub listening_ports: Vec<u16>,
+    // cgroup path on Linux, job object membership on Windows. Empty when
+    // unknown.
+    pub container: String,
+    // The machine and word size its code runs as, like "x86 (32-bit)" for a
+    // WOW64 process. Empty when unknown.
+    pub architecture: String,
+    pub security: SecurityContext,
+    // What the executable at `image_path` says about itself
+    pub binary: BinaryInfo,
 }
 
-unsafe fn get_process_image_name(process: HANDLE) -> Result<U16CString> {
-    let mut process_name: [u16; 1024] = [0; 1024];
-    let mut process_name_size: u32 = 1024;
-    QueryFullProcessImageNameW(
-        process,
-        PROCESS_NAME_FORMAT(0),
-        PWSTR(process_name.as_mut_ptr()),
-        &mut process_name_size,
-    )?;
-    let file_name = PathFindFileNameW(PCWSTR(process_name.as_ptr()));
-    Ok(U16CString::from_ptr_str(file_name.as_ptr()))
-}
-
-unsafe fn get_process_working_set_size(process: HANDLE) -> Result<usize> {
-    let mut process_memory_counters = PROCESS_MEMORY_COUNTERS_EX2::default();
-    GetProcessMemoryInfo(
-        process,
-        transmute::<*mut PROCESS_MEMORY_COUNTERS_EX2, *mut PROCESS_MEMORY_COUNTERS>(
-            &mut process_memory_counters,
-        ),
-        size_of::<PROCESS_MEMORY_COUNTERS_EX2>() as u32,
-    )?;
-
-    // Try to use PrivateWorkingSetSize first if the OS supports it, otherwise
-    // fallback on less accurate but more widely supported values.
-    if process_memory_counters.PrivateWorkingSetSize != 0 {
-        Ok(process_memory_counters.PrivateWorkingSetSize)
-    } else if process_memory_counters.PrivateUsage != 0 {
-        Ok(process_memory_counters.PrivateUsage)
-    } else {
-        Ok(process_memory_counters.WorkingSetSize)
-    }
-}
-
-fn filetime_to_u64(time: &FILETIME) -> u64 {
-    ((time.dwHighDateTime as u64) << 32) | (time.dwLowDateTime as u64)
-}
-
-unsafe fn get_process_cpu_time(process: HANDLE) -> Result<u64> {
-    let mut creation_time = FILETIME::default();
-    let mut exit_time = FILETIME::default();
-    let mut kernel_time = FILETIME::default();
-    let mut user_time = FILETIME::default();
-
-    GetProcessTimes(
-        process,
-        &mut creation_time,
-        &mut exit_time,
-        &mut kernel_time,
-        &mut user_time,
-    )?;
-
-    let kernel_time_64 = filetime_to_u64(&kernel_time);
-    let user_time_64 = filetime_to_u64(&user_time);
-    Ok(kernel_time_64 + user_time_64)
-}
-
-fn open_process(pid: &u32) -> Option<(u32, HANDLE)> {
-    let result = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, *pid) };
-    if let Ok(handle) = result {
-        Some((*pid, handle))
-    } else {
-        None
+// The OS's own memory counters, each None when the OS doesn't keep it or it
+// couldn't be read. Page faults are a count, the rest are bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryCounters {
+    // Resident memory, shared pages included
+    pub working_set: Option<u64>,
+    // Resident memory that isn't shared. Linux only counts anonymous memory,
+    // which a forked child may still share with its parent.
+    pub private_working_set: Option<u64>,
+    pub peak_working_set: Option<u64>,
+    // Private memory the process has committed, resident or not. Linux only
+    // commits what has been touched, so there it's anonymous memory that's
+    // resident or swapped out.
+    pub commit: Option<u64>,
+    pub virtual_size: Option<u64>,
+    // Every fault on Windows, only those that had to read from disk on Linux
+    pub page_faults: Option<u64>,
+    // Resident memory with each shared page split evenly between the
+    // processes sharing it (PSS), and resident memory no other process
+    // shares (USS). Only measured while one of them is the memory mode.
+    pub proportional: Option<u64>,
+    pub unique: Option<u64>,
+}
+
+// Bytes a process has read and written since it started. Linux counts what
+// actually reached storage; Windows counts all file, device and network I/O,
+// same as its Task Manager.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IoCounters {
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+}
+
+// Windows' priority classes. Linux has no such thing, so there each one
+// stands for a nice value, I/O priority and scheduling policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    BelowNormal,
+    Normal,
+    AboveNormal,
+    High,
+    Realtime,
+}
+
+impl Priority {
+    // Highest first, as menus list them
+    pub const ALL: [Priority; 6] = [
+        Priority::Realtime,
+        Priority::High,
+        Priority::AboveNormal,
+        Priority::Normal,
+        Priority::BelowNormal,
+        Priority::Low,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::Low => "Low",
+            Priority::BelowNormal => "Below normal",
+            Priority::Normal => "Normal",
+            Priority::AboveNormal => "Above normal",
+            Priority::High => "High",
+            Priority::Realtime => "Realtime",
+        }
+    }
+
+    // How recordings store it
+    pub fn key(self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::BelowNormal => "below_normal",
+            Priority::Normal => "normal",
+            Priority::AboveNormal => "above_normal",
+            Priority::High => "high",
+            Priority::Realtime => "realtime",
+        }
+    }
+}
+
+impl FromStr for Priority {
+    type Err = Error;
+
+    fn from_str(key: &str) -> Result<Self> {
+        Priority::ALL
+            .into_iter()
+            .find(|priority| priority.key() == key)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "unknown priority"))
+    }
+}
+
+// What a process is doing. Linux reports the scheduler state of its main
+// thread; Windows only distinguishes processes that can't run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Running,
+    Sleeping,
+    // Linux only: a kernel thread with nothing to do
+    Idle,
+    // Linux only: waiting in the kernel, usually on I/O, and deaf to signals
+    Uninterruptible,
+    Stopped,
+    Zombie,
+    // Windows only: every thread is suspended
+    Suspended,
+    // Windows only: a window has stopped handling its messages
+    NotResponding,
+}
+
+impl Status {
+    pub const ALL: [Status; 8] = [
+        Status::Running,
+        Status::Sleeping,
+        Status::Idle,
+        Status::Uninterruptible,
+        Status::Stopped,
+        Status::Zombie,
+        Status::Suspended,
+        Status::NotResponding,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Running => "Running",
+            Status::Sleeping => "Sleeping",
+            Status::Idle => "Idle",
+            Status::Uninterruptible => "Uninterruptible",
+            Status::Stopped => "Stopped",
+            Status::Zombie => "Zombie",
+            Status::Suspended => "Suspended",
+            Status::NotResponding => "Not Responding",
+        }
+    }
+
+    // How recordings store it
+    pub fn key(self) -> &'static str {
+        match self {
+            Status::Running => "running",
+            Status::Sleeping => "sleeping",
+            Status::Idle => "idle",
+            Status::Uninterruptible => "uninterruptible",
+            Status::Stopped => "stopped",
+            Status::Zombie => "zombie",
+            Status::Suspended => "suspended",
+            Status::NotResponding => "not_responding",
+        }
+    }
+
+    // States a process is stuck in rather than passing through, which the
+    // Status column shows the length of
+    pub fn is_stuck(self) -> bool {
+        matches!(self, Status::Uninterruptible | Status::NotResponding)
+    }
+}
+
+impl FromStr for Status {
+    type Err = Error;
+
+    fn from_str(key: &str) -> Result<Self> {
+        Status::ALL
+            .into_iter()
+            .find(|status| status.key() == key)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "unknown status"))
+    }
+}
+
+// Which figure the Memory column and the status bar's process total show
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemoryMode {
+    #[default]
+    PrivateWorkingSet,
+    Rss,
+    Pss,
+    Uss,
+}
+
+impl MemoryMode {
+    pub const ALL: [MemoryMode; 4] = [
+        MemoryMode::PrivateWorkingSet,
+        MemoryMode::Rss,
+        MemoryMode::Pss,
+        MemoryMode::Uss,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MemoryMode::PrivateWorkingSet => "Private",
+            MemoryMode::Rss => "RSS",
+            MemoryMode::Pss => "PSS",
+            MemoryMode::Uss => "USS",
+        }
+    }
+
+    // --memory value
+    pub fn key(self) -> &'static str {
+        match self {
+            MemoryMode::PrivateWorkingSet => "private",
+            MemoryMode::Rss => "rss",
+            MemoryMode::Pss => "pss",
+            MemoryMode::Uss => "uss",
+        }
+    }
+
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub fn next(self) -> MemoryMode {
+        let index = MemoryMode::ALL.iter().position(|&mode| mode == self);
+        MemoryMode::ALL[index.map_or(0, |index| (index + 1) % MemoryMode::ALL.len())]
+    }
+
+    // Whether sampling has to measure PSS and USS, which means walking every
+    // mapping of every process
+    pub fn needs_sharing(self) -> bool {
+        matches!(self, MemoryMode::Pss | MemoryMode::Uss)
     }
 }
 
-unsafe fn query_process_information(pid: u32, process: HANDLE) -> Result<Process> {
-    let image_name = get_process_image_name(process)?;
-    let working_set_size = get_process_working_set_size(process)?;
-    let cpu_time = get_process_cpu_time(process)?;
-    Ok(Process {
-        pid,
-        image_name,
-        private_working_set: working_set_size,
-        cpu_time,
-        sample_time: Instant::now(),
-        cpu_usage: 0,
-    })
+impl FromStr for MemoryMode {
+    type Err = Error;
+
+    fn from_str(key: &str) -> Result<Self> {
+        MemoryMode::ALL
+            .into_iter()
+            .find(|mode| mode.key() == key)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "unknown memory mode"))
+    }
 }
 
-pub fn get_processes(state: &TaskManagerState) -> Result<HashMap<u32, std::rc::Rc<Process>>> {
-    let mut pid_list: [u32; 1024] = [0; 1024];
-    let cb = size_of_val(&pid_list) as u32;
-    let mut cb_needed: u32 = 0;
+impl Process {
+    // The Memory column's figure. Counters that couldn't be read count as 0.
+    pub fn memory(&self, mode: MemoryMode) -> usize {
+        let counters = &self.memory_counters;
+        let bytes = match mode {
+            MemoryMode::PrivateWorkingSet => return self.private_working_set,
+            MemoryMode::Rss => counters.working_set,
+            MemoryMode::Pss => counters.proportional,
+            MemoryMode::Uss => counters.unique,
+        };
+        bytes.unwrap_or_default() as usize
+    }
+}
+
+// A backend that can enumerate the processes running on some machine
+pub trait ProcessSource {
+    // Processes that can't be queried (access denied, exited while being
+    // sampled) are left out rather than failing the whole sample.
+    fn sample(&mut self) -> Result<Vec<Process>>;
+
+    // Ends a process on the machine the samples come from
+    fn kill(&mut self, pid: u32) -> Result<()>;
+
+    // Shows the folder holding a process's executable in the file manager
+    fn open_file_location(&mut self, process: &Process) -> Result<()>;
+
+    // Changes a process's priority on the machine the samples come from
+    fn set_priority(&mut self, pid: u32, priority: Priority) -> Result<()>;
+
+    // The logical CPUs a process may run on, numbered from 0
+    fn affinity(&mut self, pid: u32) -> Result<Vec<u32>>;
 
-    unsafe {
-        EnumProcesses(pid_list.as_mut_ptr(), cb, &mut cb_needed)?;
+    // Restricts a process to the given logical CPUs
+    fn set_affinity(&mut self, pid: u32, cpus: &[u32]) -> Result<()>;
+
+    // The machine's TCP and UDP sockets and which processes own them
+    fn connections(&mut self) -> Result<Vec<Connection>>;
+
+    // A process's environment variables, read from it as of now
+    fn environment(&mut self, pid: u32) -> Result<Vec<Variable>>;
+
+    // Live sources only measure PSS and USS while one of them is being shown
+    fn set_memory_mode(&mut self, _mode: MemoryMode) {}
+
+    // Sources that replay samples taken elsewhere know that machine's
+    // processor count and system-wide figures. Live sources return None and
+    // the caller asks the local system instead.
+    fn num_cpus(&self) -> Option<u32> {
+        None
     }
 
-    if cb == cb_needed {
-        println!("might need a bigger array...");
+    fn system_status(&self) -> Option<SystemStatus> {
+        None
     }
+}
+
+// The user taskmanager runs as, in the same form as Process::user. It can't
+// change while running, so it's only looked up once.
+pub fn current_user() -> &'static str {
+    static CURRENT_USER: OnceLock<String> = OnceLock::new();
+    CURRENT_USER.get_or_init(get_current_user)
+}
 
+// The user "only my processes" means: whoever a remote or recorded source
+// sampled as, otherwise whoever runs taskmanager
+pub fn sampling_user(source: &dyn ProcessSource) -> String {
+    source
+        .system_status()
+        .map(|status| status.user)
+        .filter(|user| !user.is_empty())
+        .unwrap_or_else(|| current_user().to_string())
+}
+
+pub fn get_processes(
+    source: &mut dyn ProcessSource,
+    pid_map: &HashMap<u32, Rc<Process>>,
+    num_cpus: u32,
+) -> Result<HashMap<u32, Rc<Process>>> {
     let mut process_map = HashMap::new();
-    for (pid, process_handle) in pid_list.iter().filter_map(open_process) {
-        if let Ok(mut process) = unsafe { query_process_information(pid, process_handle) } {
-            if let Some(old_process) = state.pid_map.get(&pid) {
-                process.cpu_usage = get_cpu_usage(old_process, &process, state.num_cpus);
+    for mut process in source.sample()? {
+        if let Some(old_process) = pid_map.get(&process.pid) {
+            process.cpu_usage = get_cpu_usage(old_process, &process, num_cpus);
+            (process.io_read_rate, process.io_write_rate) = get_io_rates(old_process, &process);
+            if old_process.status == process.status {
+                process.status_since = old_process.status_since;
             }
-            process_map.insert(pid, std::rc::Rc::new(process));
         }
-        unsafe {
-            let _ = CloseHandle(process_handle);
-        };
+        process_map.insert(process.pid, Rc::new(process));
     }
 
     Ok(process_map)
 }
 
+// How long the process has been in its current status, as of its sample
+pub fn status_duration(process: &Process) -> Duration {
+    process
+        .sample_time
+        .saturating_duration_since(process.status_since)
+}
+
 pub fn get_cpu_usage(sample1: &Process, sample2: &Process, num_cpus: u32) -> u64 {
     let p1_time_ms = sample1.cpu_time / (1000 * 10);
     let p2_time_ms = sample2.cpu_time / (1000 * 10);
-    let delta = (p2_time_ms - p1_time_ms) as f64;
+    // A reused PID can start out with less CPU time than the old process had
+    let delta = p2_time_ms.saturating_sub(p1_time_ms) as f64;
 
-    let time_elapsed = sample2.sample_time.duration_since(sample1.sample_time);
+    let time_elapsed = sample2
+        .sample_time
+        .saturating_duration_since(sample1.sample_time);
     let time_elapsed_ms = time_elapsed.as_millis() as f64;
+    if time_elapsed_ms == 0.0 {
+        return 0;
+    }
 
     let res = (((delta / time_elapsed_ms) / (num_cpus as f64)) * 100.0).round();
     res as u64
 }
 
-pub fn kill_process(pid: u32) -> Result<()> {
-    unsafe {
-        let handle = OpenProcess(PROCESS_TERMINATE, false, pid)?;
-        TerminateProcess(handle, 1)?;
-        CloseHandle(handle)?;
-        Ok(())
+// Read and write rates in bytes per second, diffed the same way as CPU time
+pub fn get_io_rates(sample1: &Process, sample2: &Process) -> (u64, u64) {
+    let (Some(io1), Some(io2)) = (sample1.io, sample2.io) else {
+        return (0, 0);
+    };
+    let time_elapsed = sample2
+        .sample_time
+        .saturating_duration_since(sample1.sample_time);
+    let seconds = time_elapsed.as_secs_f64();
+    if seconds == 0.0 {
+        return (0, 0);
+    }
+    let rate = |bytes1: u64, bytes2: u64| (bytes2.saturating_sub(bytes1) as f64 / seconds) as u64;
+    (
+        rate(io1.read_bytes, io2.read_bytes),
+        rate(io1.write_bytes, io2.write_bytes),
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    // A process with every field set to something other than its default, so
+    // a field that's dropped along the way shows up
+    pub fn example_process(pid: u32) -> Process {
+        let now = Instant::now();
+        Process {
+            pid,
+            parent_pid: 1,
+            image_name: "example tool.exe".to_string(),
+            image_path: "C:\\Program Files\\Example\\example tool.exe".to_string(),
+            image_deleted: true,
+            command_line: "\"example tool.exe\" --level=100% --note \"two\nlines\"".to_string(),
+            user: "EXAMPLE\\someone".to_string(),
+            real_user: "root".to_string(),
+            private_working_set: 4096,
+            memory_counters: MemoryCounters {
+                working_set: Some(8192),
+                private_working_set: Some(4096),
+                peak_working_set: Some(16384),
+                commit: Some(12288),
+                virtual_size: Some(1 << 32),
+                page_faults: Some(42),
+                proportional: Some(6144),
+                unique: Some(2048),
+            },
+            thread_count: 7,
+            handle_count: Some(123),
+            start_time: Some(UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456)),
+            cpu_time: 5_000_000,
+            sample_time: now,
+            sample_wall_time: UNIX_EPOCH + Duration::from_micros(1_700_000_100_654_321),
+            status: Some(Status::Uninterruptible),
+            status_since: now,
+            cpu_usage: 0,
+            last_cpu: Some(3),
+            io: Some(IoCounters {
+                read_bytes: 1000,
+                write_bytes: 2000,
+            }),
+            io_read_rate: 0,
+            io_write_rate: 0,
+            priority: Some(Priority::AboveNormal),
+            nice: Some(-5),
+            io_priority: "best-effort 4".to_string(),
+            scheduling_policy: "FIFO 10".to_string(),
+            listening_ports: vec![80, 443],
+            container: "/system.slice/example.service".to_string(),
+            architecture: "x86-64 (64-bit)".to_string(),
+            security: SecurityContext {
+                elevated: Some(true),
+                integrity_level: Some(0x3000),
+                uid: Some(1000),
+                euid: Some(0),
+                capabilities: Some(0x3000),
+                no_new_privs: Some(false),
+                seccomp: Some(2),
+            },
+            binary: BinaryInfo {
+                description: "Example Tool".to_string(),
+                company: "Example Corp".to_string(),
+                version: "1.2.3.4".to_string(),
+                image_type: "ELF 64-bit x86-64".to_string(),
+                architecture: "x86-64 (64-bit)".to_string(),
+                build_id: "0123456789abcdef".to_string(),
+                interpreter: "/lib64/ld-linux-x86-64.so.2".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn cpu_usage() {
+        let before = example_process(1);
+        let mut after = example_process(1);
+        after.sample_time = before.sample_time + Duration::from_secs(1);
+        after.cpu_time = before.cpu_time + 10_000_000;
+        assert_eq!(get_cpu_usage(&before, &after, 1), 100);
+        assert_eq!(get_cpu_usage(&before, &after, 4), 25);
+    }
+
+    // A new process with the old one's PID can have used less CPU time
+    #[test]
+    fn cpu_usage_after_pid_reuse() {
+        let before = example_process(1);
+        let mut after = example_process(1);
+        after.sample_time = before.sample_time + Duration::from_secs(1);
+        after.cpu_time = 0;
+        assert_eq!(get_cpu_usage(&before, &after, 1), 0);
+    }
+
+    #[test]
+    fn cpu_usage_without_elapsed_time() {
+        let before = example_process(1);
+        let mut after = before.clone();
+        after.cpu_time += 10_000_000;
+        assert_eq!(get_cpu_usage(&before, &after, 1), 0);
+    }
+
+    #[test]
+    fn io_rates() {
+        let before = example_process(1);
+        let mut after = example_process(1);
+        after.sample_time = before.sample_time + Duration::from_secs(2);
+        let io = before.io.unwrap();
+        after.io = Some(IoCounters {
+            read_bytes: io.read_bytes + 4000,
+            write_bytes: io.write_bytes + 1000,
+        });
+        assert_eq!(get_io_rates(&before, &after), (2000, 500));
+
+        // A reused PID can start out with fewer bytes than the old process
+        after.io = Some(IoCounters {
+            read_bytes: 0,
+            write_bytes: 0,
+        });
+        assert_eq!(get_io_rates(&before, &after), (0, 0));
+    }
+
+    // Samples taken out of order, or at the same time, have no rate
+    #[test]
+    fn io_rates_without_elapsed_time() {
+        let mut before = example_process(1);
+        let after = example_process(1);
+        before.sample_time = after.sample_time + Duration::from_secs(1);
+        let mut io = after.io.unwrap();
+        io.read_bytes += 1000;
+        let after = Process {
+            io: Some(io),
+            ..after
+        };
+        assert_eq!(get_io_rates(&before, &after), (0, 0));
+        assert_eq!(get_io_rates(&after, &after), (0, 0));
     }
 }
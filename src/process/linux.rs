@@ -0,0 +1,950 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Error, ErrorKind, Result},
+    mem,
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use super::{IoCounters, MemoryCounters, MemoryMode, Priority, Process, ProcessSource, Status};
+use crate::{
+    binary::{BinaryCache, BinaryInfo},
+    connection::{self, Connection},
+    environment::{self, Variable},
+    security::SecurityContext,
+};
+
+pub struct LinuxProcessSource {
+    clock_ticks_per_sec: u64,
+    boot_time: Option<SystemTime>,
+    memory_mode: MemoryMode,
+    binary_cache: BinaryCache,
+}
+
+impl LinuxProcessSource {
+    pub fn new() -> Self {
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        LinuxProcessSource {
+            clock_ticks_per_sec: if ticks > 0 { ticks as u64 } else { 100 },
+            boot_time: get_boot_time(),
+            memory_mode: MemoryMode::default(),
+            binary_cache: BinaryCache::default(),
+        }
+    }
+}
+
+// The btime line of /proc/stat, in seconds since the Unix epoch
+fn get_boot_time() -> Option<SystemTime> {
+    let stat = fs::read_to_string("/proc/stat").ok()?;
+    let btime = stat.lines().find_map(|line| line.strip_prefix("btime "))?;
+    let btime = btime.trim().parse().ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(btime))
+}
+
+impl Default for LinuxProcessSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The fields of /proc/<pid>/stat that come after the parenthesised command
+// name, which may itself contain spaces and parentheses.
+fn stat_fields(stat: &str) -> Option<Vec<&str>> {
+    let end_of_comm = stat.rfind(')')?;
+    Some(stat[end_of_comm + 1..].split_whitespace().collect())
+}
+
+// Looks up a "Key:\tvalue" line in /proc/<pid>/status
+fn status_field<'a>(status: &'a str, key: &str) -> Option<&'a str> {
+    status.lines().find_map(|line| {
+        let (k, v) = line.split_once(':')?;
+        (k == key).then(|| v.trim())
+    })
+}
+
+// Parses a "1234 kB" value from /proc/<pid>/status into bytes
+fn parse_kb(value: &str) -> Option<usize> {
+    let kb = value.strip_suffix("kB").unwrap_or(value).trim();
+    kb.parse::<usize>().ok().map(|kb| kb * 1024)
+}
+
+// The executable's path, and whether it has been deleted or replaced since
+// the process started, which the kernel marks by appending " (deleted)".
+// Kernel threads and processes owned by other users have no readable exe
+// link.
+fn get_process_image_path(proc_dir: &Path) -> Option<(String, bool)> {
+    let exe = fs::read_link(proc_dir.join("exe")).ok()?;
+    let exe = exe.to_string_lossy();
+    match exe.strip_suffix(" (deleted)") {
+        Some(path) => Some((path.to_string(), true)),
+        None => Some((exe.into_owned(), false)),
+    }
+}
+
+fn get_process_image_name(image_path: &str, status: &str) -> Result<String> {
+    // Without a path, fall back on the (possibly truncated) command name
+    if let Some(file_name) = Path::new(image_path).file_name() {
+        return Ok(file_name.to_string_lossy().into_owned());
+    }
+    status_field(status, "Name")
+        .map(str::to_string)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "status has no Name"))
+}
+
+// Quotes an argument that wouldn't read back as itself once joined to the
+// others with spaces, escaping whatever a shell would treat specially inside
+// double quotes
+fn quote_argument(argument: &str) -> String {
+    let plain = |c: char| c.is_alphanumeric() || "-_./:=,+@%^~".contains(c);
+    if !argument.is_empty() && argument.chars().all(plain) {
+        return argument.to_string();
+    }
+    let mut quoted = String::from('"');
+    for c in argument.chars() {
+        match c {
+            '"' | '\\' | '$' | '`' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// /proc/<pid>/cmdline holds the arguments, each followed by a NUL. Processes
+// that rewrite their title (postgres, sshd) overwrite it with one string,
+// either unterminated or padded out with NULs, which is shown as it is. That
+// makes a trailing empty argument look like padding.
+fn format_cmdline(cmdline: &[u8]) -> String {
+    let cmdline = String::from_utf8_lossy(cmdline);
+    match cmdline.strip_suffix('\0') {
+        Some(arguments) if !arguments.ends_with('\0') => arguments
+            .split('\0')
+            .map(quote_argument)
+            .collect::<Vec<_>>()
+            .join(" "),
+        _ => cmdline.trim_end_matches('\0').to_string(),
+    }
+}
+
+fn get_process_working_set_size(status: &str) -> usize {
+    // RssAnon is the closest thing to a private working set; older kernels
+    // only report the total resident size.
+    status_field(status, "RssAnon")
+        .or_else(|| status_field(status, "VmRSS"))
+        .and_then(parse_kb)
+        .unwrap_or(0)
+}
+
+// VmHWM is the peak resident size; VmPeak is the peak virtual size
+fn get_process_memory_counters(status: &str, stat: &str) -> MemoryCounters {
+    let kb = |key| {
+        status_field(status, key)
+            .and_then(parse_kb)
+            .map(|bytes| bytes as u64)
+    };
+    let commit = match (kb("RssAnon"), kb("VmSwap")) {
+        (Some(resident), swapped) => Some(resident + swapped.unwrap_or(0)),
+        (None, _) => None,
+    };
+    MemoryCounters {
+        working_set: kb("VmRSS"),
+        private_working_set: kb("RssAnon"),
+        peak_working_set: kb("VmHWM"),
+        commit,
+        virtual_size: kb("VmSize"),
+        // majflt
+        page_faults: stat_fields(stat).and_then(|fields| fields.get(9)?.parse().ok()),
+        proportional: None,
+        unique: None,
+    }
+}
+
+// PSS and USS from smaps_rollup, which the kernel adds up by walking every
+// mapping, so it's only read when asked for. Needs the same access as
+// reading the process's memory.
+fn get_process_sharing(proc_dir: &Path, counters: &mut MemoryCounters) {
+    let Ok(rollup) = fs::read_to_string(proc_dir.join("smaps_rollup")) else {
+        return;
+    };
+    let kb = |key| {
+        status_field(&rollup, key)
+            .and_then(parse_kb)
+            .map(|bytes| bytes as u64)
+    };
+    counters.proportional = kb("Pss");
+    counters.unique = match (kb("Private_Clean"), kb("Private_Dirty")) {
+        (Some(clean), Some(dirty)) => Some(clean + dirty),
+        _ => None,
+    };
+}
+
+// The state letter in /proc/<pid>/stat. A stop for a debugger counts as
+// stopped; states a process only passes through are left unknown.
+fn get_process_status(stat: &str) -> Option<Status> {
+    let state = stat_fields(stat)?.first()?.chars().next()?;
+    Some(match state {
+        'R' => Status::Running,
+        'S' => Status::Sleeping,
+        'I' => Status::Idle,
+        'D' => Status::Uninterruptible,
+        'T' | 't' => Status::Stopped,
+        'Z' => Status::Zombie,
+        _ => return None,
+    })
+}
+
+fn get_process_thread_count(status: &str) -> u32 {
+    status_field(status, "Threads")
+        .and_then(|threads| threads.parse().ok())
+        .unwrap_or(0)
+}
+
+// Each open fd is an entry in /proc/<pid>/fd, which only the owner (or root)
+// can list. Along with the count, the ports of the listening sockets among
+// them.
+fn get_process_fds(
+    proc_dir: &Path,
+    listening_sockets: &HashMap<u64, u16>,
+) -> (Option<u32>, Vec<u16>) {
+    let Ok(fds) = fs::read_dir(proc_dir.join("fd")) else {
+        return (None, Vec::new());
+    };
+    let mut count = 0;
+    let mut ports = Vec::new();
+    for fd in fds.flatten() {
+        count += 1;
+        if let Some(port) =
+            connection::socket_inode(&fd.path()).and_then(|inode| listening_sockets.get(&inode))
+        {
+            ports.push(*port);
+        }
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    (Some(count), ports)
+}
+
+// /proc/<pid>/io has "key: value" lines like status, but only the owner (or
+// root) can read it
+fn get_process_io(proc_dir: &Path) -> Option<IoCounters> {
+    let io = fs::read_to_string(proc_dir.join("io")).ok()?;
+    let counter = |key| status_field(&io, key)?.parse().ok();
+    Some(IoCounters {
+        read_bytes: counter("read_bytes")?,
+        write_bytes: counter("write_bytes")?,
+    })
+}
+
+// starttime in stat counts clock ticks from boot to when the process started
+fn get_process_start_time(
+    stat: &str,
+    clock_ticks_per_sec: u64,
+    boot_time: Option<SystemTime>,
+) -> Option<SystemTime> {
+    let start_ticks: u64 = stat_fields(stat)?.get(19)?.parse().ok()?;
+    let since_boot = Duration::from_secs_f64(start_ticks as f64 / clock_ticks_per_sec as f64);
+    Some(boot_time? + since_boot)
+}
+
+// Who ioprio_get and ioprio_set are about, and the I/O scheduling classes,
+// from include/uapi/linux/ioprio.h
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+const IOPRIO_CLASS_NONE: libc::c_int = 0;
+const IOPRIO_CLASS_RT: libc::c_int = 1;
+const IOPRIO_CLASS_BE: libc::c_int = 2;
+const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+
+struct Scheduling {
+    policy: libc::c_int,
+    rt_priority: u32,
+    nice: i32,
+}
+
+// nice, rt_priority and policy from stat, which anyone can read
+fn get_process_scheduling(stat: &str) -> Option<Scheduling> {
+    let fields = stat_fields(stat)?;
+    Some(Scheduling {
+        nice: fields.get(16)?.parse().ok()?,
+        rt_priority: fields.get(37)?.parse().ok()?,
+        policy: fields.get(38)?.parse().ok()?,
+    })
+}
+
+fn is_realtime(policy: libc::c_int) -> bool {
+    matches!(
+        policy,
+        libc::SCHED_FIFO | libc::SCHED_RR | libc::SCHED_DEADLINE
+    )
+}
+
+// The priority class set_priority would have given the process, going by the
+// bands between the nice values it uses
+fn priority_from_scheduling(scheduling: &Scheduling) -> Priority {
+    if is_realtime(scheduling.policy) {
+        return Priority::Realtime;
+    }
+    if scheduling.policy == libc::SCHED_IDLE {
+        return Priority::Low;
+    }
+    match scheduling.nice {
+        ..=-10 => Priority::High,
+        -9..=-1 => Priority::AboveNormal,
+        0 => Priority::Normal,
+        1..=14 => Priority::BelowNormal,
+        _ => Priority::Low,
+    }
+}
+
+fn scheduling_policy_name(scheduling: &Scheduling) -> String {
+    match scheduling.policy {
+        libc::SCHED_OTHER => "Normal".to_string(),
+        libc::SCHED_FIFO => format!("FIFO {}", scheduling.rt_priority),
+        libc::SCHED_RR => format!("Round robin {}", scheduling.rt_priority),
+        libc::SCHED_BATCH => "Batch".to_string(),
+        libc::SCHED_IDLE => "Idle".to_string(),
+        libc::SCHED_DEADLINE => "Deadline".to_string(),
+        policy => policy.to_string(),
+    }
+}
+
+// Processes that never had an I/O priority set get one from their CPU
+// scheduling, which is what's shown for them, same as ionice does
+fn get_process_io_priority(pid: u32, scheduling: &Scheduling) -> String {
+    let ioprio = unsafe { libc::syscall(libc::SYS_ioprio_get, IOPRIO_WHO_PROCESS, pid) };
+    if ioprio < 0 {
+        return String::new();
+    }
+    let ioprio = ioprio as libc::c_int;
+    let level = ioprio & ((1 << IOPRIO_CLASS_SHIFT) - 1);
+    let nice_level = (scheduling.nice + 20) / 5;
+    match ioprio >> IOPRIO_CLASS_SHIFT {
+        IOPRIO_CLASS_NONE if is_realtime(scheduling.policy) => format!("realtime {}", nice_level),
+        IOPRIO_CLASS_NONE if scheduling.policy == libc::SCHED_IDLE => "idle".to_string(),
+        IOPRIO_CLASS_NONE => format!("best-effort {}", nice_level),
+        IOPRIO_CLASS_RT => format!("realtime {}", level),
+        IOPRIO_CLASS_BE => format!("best-effort {}", level),
+        IOPRIO_CLASS_IDLE => "idle".to_string(),
+        _ => String::new(),
+    }
+}
+
+// The processor field of stat
+fn get_process_last_cpu(stat: &str) -> Option<u32> {
+    stat_fields(stat)?.get(36)?.parse().ok()
+}
+
+fn get_process_cpu_time(stat: &str, clock_ticks_per_sec: u64) -> Result<u64> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "malformed stat");
+    let fields = stat_fields(stat).ok_or_else(invalid)?;
+    let utime: u64 = fields
+        .get(11)
+        .and_then(|f| f.parse().ok())
+        .ok_or_else(invalid)?;
+    let stime: u64 = fields
+        .get(12)
+        .and_then(|f| f.parse().ok())
+        .ok_or_else(invalid)?;
+    Ok((utime + stime) * 10_000_000 / clock_ticks_per_sec)
+}
+
+// Picks a path out of /proc/<pid>/cgroup, one "id:controllers:path" line per
+// hierarchy. The v2 path ("0::/path") comes first, then systemd's v1
+// hierarchy, then any other. On hybrid systems the v2 path is often just the
+// root, so a more specific path from a later hierarchy wins over it.
+fn parse_cgroup(cgroup: &str) -> Option<String> {
+    let entries: Vec<(&str, &str)> = cgroup
+        .lines()
+        .filter_map(|line| line.split_once(':')?.1.split_once(':'))
+        .collect();
+    let mut paths: Vec<&str> = Vec::new();
+    paths.extend(entries.iter().filter(|(c, _)| c.is_empty()).map(|e| e.1));
+    paths.extend(
+        entries
+            .iter()
+            .filter(|(c, _)| *c == "name=systemd")
+            .map(|e| e.1),
+    );
+    paths.extend(entries.iter().map(|e| e.1));
+    paths
+        .iter()
+        .find(|path| **path != "/")
+        .or_else(|| paths.first())
+        .map(|path| path.to_string())
+}
+
+// User names by UID from /etc/passwd. Users defined elsewhere (LDAP,
+// systemd's dynamic users) aren't in it and are shown as their UID.
+fn read_user_names() -> HashMap<u32, String> {
+    let mut user_names = HashMap::new();
+    let Ok(passwd) = fs::read_to_string("/etc/passwd") else {
+        return user_names;
+    };
+    for line in passwd.lines() {
+        let mut fields = line.split(':');
+        let (Some(name), Some(uid)) = (fields.next(), fields.nth(1)) else {
+            continue;
+        };
+        if let Ok(uid) = uid.parse() {
+            // The first entry wins, same as getpwuid
+            user_names.entry(uid).or_insert_with(|| name.to_string());
+        }
+    }
+    user_names
+}
+
+fn user_name(uid: u32, user_names: &HashMap<u32, String>) -> String {
+    user_names
+        .get(&uid)
+        .cloned()
+        .unwrap_or_else(|| uid.to_string())
+}
+
+// The effective user, and the real one when it differs. The Uid line of
+// /proc/<pid>/status holds the real, effective, saved and filesystem UIDs.
+fn get_process_users(status: &str, user_names: &HashMap<u32, String>) -> (String, String) {
+    let uids: Vec<u32> = status_field(status, "Uid")
+        .unwrap_or("")
+        .split_whitespace()
+        .filter_map(|uid| uid.parse().ok())
+        .collect();
+    let (Some(&real), Some(&effective)) = (uids.first(), uids.get(1)) else {
+        return (String::new(), String::new());
+    };
+    let user = user_name(effective, user_names);
+    let real_user = if real != effective {
+        user_name(real, user_names)
+    } else {
+        String::new()
+    };
+    (user, real_user)
+}
+
+// From the Uid, CapEff, NoNewPrivs and Seccomp lines of /proc/<pid>/status.
+// Kernels older than 4.10 have no NoNewPrivs line.
+fn get_process_security(status: &str) -> SecurityContext {
+    let mut uids = status_field(status, "Uid")
+        .unwrap_or("")
+        .split_whitespace()
+        .map(|uid| uid.parse().ok());
+    let uid = uids.next().flatten();
+    let euid = uids.next().flatten();
+    let capabilities =
+        status_field(status, "CapEff").and_then(|caps| u64::from_str_radix(caps, 16).ok());
+    let elevated = match (euid, capabilities) {
+        (Some(euid), Some(capabilities)) => Some(euid == 0 || capabilities != 0),
+        _ => None,
+    };
+    SecurityContext {
+        elevated,
+        integrity_level: None,
+        uid,
+        euid,
+        capabilities,
+        no_new_privs: status_field(status, "NoNewPrivs").map(|value| value == "1"),
+        seccomp: status_field(status, "Seccomp").and_then(|mode| mode.parse().ok()),
+    }
+}
+
+pub fn current_user() -> String {
+    user_name(unsafe { libc::geteuid() }, &read_user_names())
+}
+
+fn query_process_information(
+    pid: u32,
+    clock_ticks_per_sec: u64,
+    boot_time: Option<SystemTime>,
+    memory_mode: MemoryMode,
+    user_names: &HashMap<u32, String>,
+    listening_sockets: &HashMap<u64, u16>,
+) -> Result<Process> {
+    let proc_dir = Path::new("/proc").join(pid.to_string());
+    let stat = fs::read_to_string(proc_dir.join("stat"))?;
+    let status = fs::read_to_string(proc_dir.join("status"))?;
+
+    let (image_path, image_deleted) = get_process_image_path(&proc_dir).unwrap_or_default();
+    let image_name = get_process_image_name(&image_path, &status)?;
+    let working_set_size = get_process_working_set_size(&status);
+    let mut memory_counters = get_process_memory_counters(&status, &stat);
+    if memory_mode.needs_sharing() {
+        get_process_sharing(&proc_dir, &mut memory_counters);
+    }
+    let cpu_time = get_process_cpu_time(&stat, clock_ticks_per_sec)?;
+    let start_time = get_process_start_time(&stat, clock_ticks_per_sec, boot_time);
+    let (user, real_user) = get_process_users(&status, user_names);
+    let thread_count = get_process_thread_count(&status);
+    let (handle_count, listening_ports) = get_process_fds(&proc_dir, listening_sockets);
+    let io = get_process_io(&proc_dir);
+    let scheduling = get_process_scheduling(&stat);
+    let io_priority = scheduling
+        .as_ref()
+        .map(|scheduling| get_process_io_priority(pid, scheduling))
+        .unwrap_or_default();
+    let parent_pid = status_field(&status, "PPid")
+        .and_then(|ppid| ppid.parse().ok())
+        .unwrap_or(0);
+    // Empty for kernel threads
+    let command_line = fs::read(proc_dir.join("cmdline"))
+        .map(|cmdline| format_cmdline(&cmdline))
+        .unwrap_or_default();
+    let container = fs::read_to_string(proc_dir.join("cgroup"))
+        .ok()
+        .and_then(|cgroup| parse_cgroup(&cgroup))
+        .unwrap_or_default();
+    let sample_time = Instant::now();
+    Ok(Process {
+        pid,
+        parent_pid,
+        image_name,
+        image_path,
+        image_deleted,
+        command_line,
+        user,
+        real_user,
+        private_working_set: working_set_size,
+        memory_counters,
+        thread_count,
+        handle_count,
+        start_time,
+        cpu_time,
+        sample_time,
+        sample_wall_time: SystemTime::now(),
+        status: get_process_status(&stat),
+        status_since: sample_time,
+        cpu_usage: 0,
+        last_cpu: get_process_last_cpu(&stat),
+        io,
+        io_read_rate: 0,
+        io_write_rate: 0,
+        priority: scheduling.as_ref().map(priority_from_scheduling),
+        nice: scheduling.as_ref().map(|scheduling| scheduling.nice),
+        io_priority,
+        scheduling_policy: scheduling
+            .as_ref()
+            .map(scheduling_policy_name)
+            .unwrap_or_default(),
+        listening_ports,
+        container,
+        // Filled in from the image's BinaryInfo
+        architecture: String::new(),
+        security: get_process_security(&status),
+        binary: BinaryInfo::default(),
+    })
+}
+
+impl ProcessSource for LinuxProcessSource {
+    fn sample(&mut self) -> Result<Vec<Process>> {
+        let mut processes = Vec::new();
+        let user_names = read_user_names();
+        let listening_sockets = connection::listening_sockets();
+        for entry in fs::read_dir("/proc")?.flatten() {
+            let pid = entry
+                .file_name()
+                .to_str()
+                .and_then(|s| s.parse::<u32>().ok());
+            let Some(pid) = pid else {
+                continue;
+            };
+            let process = query_process_information(
+                pid,
+                self.clock_ticks_per_sec,
+                self.boot_time,
+                self.memory_mode,
+                &user_names,
+                &listening_sockets,
+            );
+            if let Ok(mut process) = process {
+                // A deleted image can only be read through the exe link,
+                // which like the link itself is only readable for other
+                // users' processes as root
+                let path = if process.image_deleted {
+                    format!("/proc/{}/exe", pid)
+                } else {
+                    process.image_path.clone()
+                };
+                process.binary = self.binary_cache.get(&path);
+                process.architecture = process.binary.architecture.clone();
+                processes.push(process);
+            }
+        }
+
+        Ok(processes)
+    }
+
+    fn kill(&mut self, pid: u32) -> Result<()> {
+        kill_process(pid)
+    }
+
+    fn open_file_location(&mut self, process: &Process) -> Result<()> {
+        open_file_location(&process.image_path)
+    }
+
+    fn set_priority(&mut self, pid: u32, priority: Priority) -> Result<()> {
+        set_priority(pid, priority)
+    }
+
+    fn affinity(&mut self, pid: u32) -> Result<Vec<u32>> {
+        get_affinity(pid)
+    }
+
+    fn set_affinity(&mut self, pid: u32, cpus: &[u32]) -> Result<()> {
+        set_affinity(pid, cpus)
+    }
+
+    fn connections(&mut self) -> Result<Vec<Connection>> {
+        connection::get_connections()
+    }
+
+    fn environment(&mut self, pid: u32) -> Result<Vec<Variable>> {
+        get_environment(pid)
+    }
+
+    fn set_memory_mode(&mut self, mode: MemoryMode) {
+        self.memory_mode = mode;
+    }
+}
+
+// The environment the process was started with, NUL separated. Kernel threads
+// have none.
+fn get_environment(pid: u32) -> Result<Vec<Variable>> {
+    let environ = fs::read(format!("/proc/{}/environ", pid)).map_err(environment::access_error)?;
+    Ok(environment::parse_entries(
+        environ
+            .split(|&byte| byte == 0)
+            .map(|entry| String::from_utf8_lossy(entry).into_owned()),
+    ))
+}
+
+fn kill_process(pid: u32) -> Result<()> {
+    let pid = libc::pid_t::try_from(pid).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    if unsafe { libc::kill(pid, libc::SIGKILL) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn check(result: libc::c_long) -> Result<()> {
+    if result < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Calls `f` with the ID of each of a process's threads, skipping any that exit
+// in the meantime
+fn for_each_thread(pid: u32, mut f: impl FnMut(libc::pid_t) -> Result<()>) -> Result<()> {
+    let tasks = fs::read_dir(Path::new("/proc").join(pid.to_string()).join("task"))?;
+    for task in tasks.flatten() {
+        let Some(tid) = task
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<libc::pid_t>().ok())
+        else {
+            continue;
+        };
+        match f(tid) {
+            Err(e) if e.raw_os_error() == Some(libc::ESRCH) => {}
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+// The scheduling policy, nice value and I/O priority all belong to threads
+// rather than processes, so every thread gets them. Raising any of them takes
+// root (or CAP_SYS_NICE); anyone may lower their own processes'.
+fn set_priority(pid: u32, priority: Priority) -> Result<()> {
+    let (policy, rt_priority, nice, ioprio_class) = match priority {
+        Priority::Low => (libc::SCHED_IDLE, 0, 19, IOPRIO_CLASS_IDLE),
+        Priority::BelowNormal => (libc::SCHED_OTHER, 0, 10, IOPRIO_CLASS_NONE),
+        Priority::Normal => (libc::SCHED_OTHER, 0, 0, IOPRIO_CLASS_NONE),
+        Priority::AboveNormal => (libc::SCHED_OTHER, 0, -5, IOPRIO_CLASS_NONE),
+        Priority::High => (libc::SCHED_OTHER, 0, -10, IOPRIO_CLASS_NONE),
+        Priority::Realtime => (libc::SCHED_RR, 1, 0, IOPRIO_CLASS_NONE),
+    };
+    let param = libc::sched_param {
+        sched_priority: rt_priority,
+    };
+    for_each_thread(pid, |tid| unsafe {
+        check(libc::sched_setscheduler(tid, policy, &param).into())?;
+        check(libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, nice).into())?;
+        check(libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            tid,
+            ioprio_class << IOPRIO_CLASS_SHIFT,
+        ))
+    })
+}
+
+// The main thread's affinity, which the others start out with
+fn get_affinity(pid: u32) -> Result<Vec<u32>> {
+    let pid = libc::pid_t::try_from(pid).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        check(libc::sched_getaffinity(pid, size_of::<libc::cpu_set_t>(), &mut set).into())?;
+        Ok((0..libc::CPU_SETSIZE as u32)
+            .filter(|&cpu| libc::CPU_ISSET(cpu as usize, &set))
+            .collect())
+    }
+}
+
+// Like `taskset --all-tasks`, every thread is moved, not just the main one
+fn set_affinity(pid: u32, cpus: &[u32]) -> Result<()> {
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    for &cpu in cpus {
+        if cpu >= libc::CPU_SETSIZE as u32 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("CPU {} is out of range", cpu),
+            ));
+        }
+        unsafe { libc::CPU_SET(cpu as usize, &mut set) };
+    }
+    for_each_thread(pid, |tid| unsafe {
+        check(libc::sched_setaffinity(tid, size_of::<libc::cpu_set_t>(), &set).into())
+    })
+}
+
+// Opens the folder in whatever the desktop uses to browse files. There's no
+// portable way to have it select the file too.
+fn open_file_location(image_path: &str) -> Result<()> {
+    let folder = Path::new(image_path)
+        .parent()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "the executable's location isn't known"))?;
+    let mut child = Command::new("xdg-open")
+        .arg(folder)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Reap it once it's done rather than leaving a zombie behind
+    thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER_STATUS: &str = "\
+Name:\tbash
+Umask:\t0022
+State:\tS (sleeping)
+Uid:\t1000\t1000\t1000\t1000
+Gid:\t1000\t1000\t1000\t1000
+CapInh:\t0000000000000000
+CapPrm:\t0000000000000000
+CapEff:\t0000000000000000
+NoNewPrivs:\t1
+Seccomp:\t2
+";
+
+    #[test]
+    fn security_of_an_ordinary_process() {
+        let security = get_process_security(USER_STATUS);
+        assert_eq!(
+            security,
+            SecurityContext {
+                elevated: Some(false),
+                integrity_level: None,
+                uid: Some(1000),
+                euid: Some(1000),
+                capabilities: Some(0),
+                no_new_privs: Some(true),
+                seccomp: Some(2),
+            }
+        );
+    }
+
+    // A setuid root program started by a user, on a kernel from before
+    // NoNewPrivs
+    #[test]
+    fn security_of_a_setuid_process() {
+        let status = "Uid:\t1000\t0\t0\t0\nCapEff:\t000001ffffffffff\nSeccomp:\t0\n";
+        let security = get_process_security(status);
+        assert_eq!(security.elevated, Some(true));
+        assert_eq!((security.uid, security.euid), (Some(1000), Some(0)));
+        assert_eq!(security.capabilities, Some(0x1ff_ffff_ffff));
+        assert_eq!(security.no_new_privs, None);
+        assert_eq!(security.seccomp, Some(0));
+    }
+
+    // File capabilities elevate without root
+    #[test]
+    fn security_with_capabilities_only() {
+        let status = USER_STATUS.replace("CapEff:\t0000000000000000", "CapEff:\t0000000000003000");
+        let security = get_process_security(&status);
+        assert_eq!(security.elevated, Some(true));
+        assert_eq!(security.capabilities, Some(0x3000));
+    }
+
+    #[test]
+    fn security_unknown() {
+        assert_eq!(get_process_security(""), SecurityContext::default());
+        let security = get_process_security("Uid:\t1000\t1000\t1000\t1000\n");
+        assert_eq!(security.elevated, None);
+        assert_eq!(security.euid, Some(1000));
+    }
+
+    // The comm field can hold spaces and parentheses of its own
+    #[test]
+    fn process_status() {
+        let states = [
+            ('R', Some(Status::Running)),
+            ('S', Some(Status::Sleeping)),
+            ('D', Some(Status::Uninterruptible)),
+            ('T', Some(Status::Stopped)),
+            ('t', Some(Status::Stopped)),
+            ('Z', Some(Status::Zombie)),
+            ('X', None),
+            ('I', Some(Status::Idle)),
+        ];
+        for (state, status) in states {
+            let stat = format!("1234 (a (b) c) {} 1 1234 1234 0 -1 4194560 ...", state);
+            assert_eq!(get_process_status(&stat), status, "state {}", state);
+        }
+        assert_eq!(get_process_status("1234 (truncated"), None);
+    }
+
+    #[test]
+    fn cgroup_v2() {
+        assert_eq!(
+            parse_cgroup("0::/system.slice/docker-0123456789ab.scope\n").as_deref(),
+            Some("/system.slice/docker-0123456789ab.scope")
+        );
+        // Inside a container the group is the root of its own hierarchy
+        assert_eq!(parse_cgroup("0::/\n").as_deref(), Some("/"));
+    }
+
+    // The v2 path is just the root when systemd still runs v1, so systemd's
+    // hierarchy is the more useful one
+    #[test]
+    fn cgroup_hybrid() {
+        let cgroup = "\
+5:memory:/user.slice/user-1000.slice
+1:name=systemd:/user.slice/user-1000.slice/session-2.scope
+0::/
+";
+        assert_eq!(
+            parse_cgroup(cgroup).as_deref(),
+            Some("/user.slice/user-1000.slice/session-2.scope")
+        );
+    }
+
+    #[test]
+    fn cgroup_v1_only() {
+        let cgroup = "\
+4:memory:/docker/0123456789ab
+3:cpu,cpuacct:/docker/0123456789ab
+1:name=systemd:/docker/0123456789ab
+";
+        assert_eq!(
+            parse_cgroup(cgroup).as_deref(),
+            Some("/docker/0123456789ab")
+        );
+        assert_eq!(parse_cgroup("4:memory:/\n").as_deref(), Some("/"));
+    }
+
+    #[test]
+    fn cgroup_missing() {
+        assert_eq!(parse_cgroup(""), None);
+        assert_eq!(parse_cgroup("not a cgroup line\n"), None);
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote_argument("--level=100%"), "--level=100%");
+        assert_eq!(quote_argument("My App"), r#""My App""#);
+        assert_eq!(quote_argument(""), r#""""#);
+        assert_eq!(quote_argument(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(quote_argument(r"$HOME\`x`"), r#""\$HOME\\\`x\`""#);
+        assert_eq!(quote_argument("two\nlines\x1b"), r#""two\nlines\x1b""#);
+    }
+
+    #[test]
+    fn cmdline_arguments() {
+        assert_eq!(format_cmdline(b"/opt/My App/bin\0"), r#""/opt/My App/bin""#);
+        assert_eq!(format_cmdline(b"ls\0-l\0my file\0"), r#"ls -l "my file""#);
+        assert_eq!(format_cmdline(b"printf\0\0x\0"), r#"printf "" x"#);
+        assert_eq!(
+            format_cmdline(b"echo\0say \"hi\"\0"),
+            r#"echo "say \"hi\"""#
+        );
+    }
+
+    #[test]
+    fn cmdline_rewritten_title() {
+        assert_eq!(
+            format_cmdline(b"postgres: checkpointer"),
+            "postgres: checkpointer"
+        );
+        assert_eq!(
+            format_cmdline(b"sshd: someone [priv]\0\0\0\0"),
+            "sshd: someone [priv]"
+        );
+        // Kernel threads have no command line at all
+        assert_eq!(format_cmdline(b""), "");
+    }
+
+    fn scheduling(policy: libc::c_int, nice: i32) -> Scheduling {
+        Scheduling {
+            policy,
+            rt_priority: 0,
+            nice,
+        }
+    }
+
+    #[test]
+    fn priority_bands() {
+        let priority = |nice| priority_from_scheduling(&scheduling(libc::SCHED_OTHER, nice));
+        assert_eq!(priority(-20), Priority::High);
+        assert_eq!(priority(-10), Priority::High);
+        assert_eq!(priority(-9), Priority::AboveNormal);
+        assert_eq!(priority(-1), Priority::AboveNormal);
+        assert_eq!(priority(0), Priority::Normal);
+        assert_eq!(priority(1), Priority::BelowNormal);
+        assert_eq!(priority(14), Priority::BelowNormal);
+        assert_eq!(priority(15), Priority::Low);
+        assert_eq!(priority(19), Priority::Low);
+    }
+
+    // Realtime and idle policies win over whatever nice value is left over
+    #[test]
+    fn priority_from_policy() {
+        let priority = |policy, nice| priority_from_scheduling(&scheduling(policy, nice));
+        assert_eq!(priority(libc::SCHED_FIFO, 0), Priority::Realtime);
+        assert_eq!(priority(libc::SCHED_RR, 19), Priority::Realtime);
+        assert_eq!(priority(libc::SCHED_DEADLINE, 0), Priority::Realtime);
+        assert_eq!(priority(libc::SCHED_IDLE, -20), Priority::Low);
+        assert_eq!(priority(libc::SCHED_BATCH, 0), Priority::Normal);
+    }
+
+    // What set_priority sets reads back as the same priority
+    #[test]
+    fn priority_round_trip() {
+        let settings = [
+            (Priority::Low, libc::SCHED_IDLE, 19),
+            (Priority::BelowNormal, libc::SCHED_OTHER, 10),
+            (Priority::Normal, libc::SCHED_OTHER, 0),
+            (Priority::AboveNormal, libc::SCHED_OTHER, -5),
+            (Priority::High, libc::SCHED_OTHER, -10),
+            (Priority::Realtime, libc::SCHED_RR, 0),
+        ];
+        for (priority, policy, nice) in settings {
+            assert_eq!(
+                priority_from_scheduling(&scheduling(policy, nice)),
+                priority
+            );
+        }
+    }
+}
@@ -0,0 +1,959 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::c_void,
+    io::{Error, ErrorKind},
+    mem::transmute,
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use widestring::U16CString;
+use windows::{
+    core::{w, Result, BOOL, HRESULT, PCWSTR, PWSTR},
+    Wdk::System::{
+        SystemInformation::{NtQuerySystemInformation, SystemProcessInformation},
+        SystemServices::VM_COUNTERS,
+        Threading::{
+            NtQueryInformationProcess, ProcessBasicInformation, ProcessIoPriority,
+            ProcessVmCounters,
+        },
+    },
+    Win32::{
+        Foundation::{
+            CloseHandle, ERROR_BAD_LENGTH, ERROR_FILE_NOT_FOUND, ERROR_PATH_NOT_FOUND,
+            E_ACCESSDENIED, E_UNEXPECTED, FILETIME, HANDLE, HWND, LPARAM,
+            STATUS_INFO_LENGTH_MISMATCH,
+        },
+        Security::{
+            GetLengthSid, GetSidSubAuthority, GetSidSubAuthorityCount, GetTokenInformation,
+            LookupAccountSidW, TokenElevation, TokenIntegrityLevel, TokenUser, PSID, SID_NAME_USE,
+            TOKEN_ELEVATION, TOKEN_INFORMATION_CLASS, TOKEN_MANDATORY_LABEL, TOKEN_QUERY,
+            TOKEN_USER,
+        },
+        Storage::FileSystem::{
+            GetFileAttributesExW, GetFileExInfoStandard, WIN32_FILE_ATTRIBUTE_DATA,
+        },
+        System::{
+            Diagnostics::{
+                Debug::ReadProcessMemory,
+                ToolHelp::{
+                    CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+                    TH32CS_SNAPPROCESS,
+                },
+            },
+            JobObjects::IsProcessInJob,
+            ProcessStatus::{
+                EnumProcesses, GetProcessMemoryInfo, QueryWorkingSet, PROCESS_MEMORY_COUNTERS,
+                PROCESS_MEMORY_COUNTERS_EX2,
+            },
+            SystemInformation::{GetSystemInfo, IMAGE_FILE_MACHINE, SYSTEM_INFO},
+            Threading::{
+                GetCurrentProcess, GetPriorityClass, GetProcessAffinityMask, GetProcessHandleCount,
+                GetProcessIoCounters, GetProcessTimes, IsWow64Process2, OpenProcess,
+                OpenProcessToken, QueryFullProcessImageNameW, SetPriorityClass,
+                SetProcessAffinityMask, TerminateProcess, ABOVE_NORMAL_PRIORITY_CLASS,
+                BELOW_NORMAL_PRIORITY_CLASS, HIGH_PRIORITY_CLASS, IDLE_PRIORITY_CLASS, IO_COUNTERS,
+                NORMAL_PRIORITY_CLASS, PEB, PROCESS_BASIC_INFORMATION, PROCESS_CREATION_FLAGS,
+                PROCESS_NAME_FORMAT, PROCESS_QUERY_INFORMATION, PROCESS_QUERY_LIMITED_INFORMATION,
+                PROCESS_SET_INFORMATION, PROCESS_TERMINATE, PROCESS_VM_READ,
+                REALTIME_PRIORITY_CLASS, RTL_USER_PROCESS_PARAMETERS,
+            },
+            WindowsProgramming::{SYSTEM_PROCESS_INFORMATION, SYSTEM_THREAD_INFORMATION},
+        },
+        UI::{
+            Shell::ShellExecuteW,
+            WindowsAndMessaging::{
+                EnumWindows, GetWindowThreadProcessId, IsHungAppWindow, IsWindowVisible,
+                SW_SHOWNORMAL,
+            },
+        },
+    },
+};
+
+use super::{IoCounters, MemoryCounters, MemoryMode, Priority, Process, ProcessSource, Status};
+use crate::{
+    binary::{self, BinaryCache, BinaryInfo},
+    connection::{self, Connection},
+    environment::{self, Variable},
+    security::SecurityContext,
+};
+
+#[derive(Default)]
+pub struct Win32ProcessSource {
+    // Account names by SID. Looking one up can mean asking a domain
+    // controller, so it's only done once per account.
+    user_names: HashMap<Vec<u8>, String>,
+    memory_mode: MemoryMode,
+    // Version resources by image path
+    binary_cache: BinaryCache,
+}
+
+impl Win32ProcessSource {
+    pub fn new() -> Self {
+        Win32ProcessSource::default()
+    }
+}
+
+unsafe fn get_process_image_path(process: HANDLE) -> Result<String> {
+    let mut process_name: [u16; 1024] = [0; 1024];
+    let mut process_name_size: u32 = 1024;
+    QueryFullProcessImageNameW(
+        process,
+        PROCESS_NAME_FORMAT(0),
+        PWSTR(process_name.as_mut_ptr()),
+        &mut process_name_size,
+    )?;
+    Ok(String::from_utf16_lossy(
+        &process_name[..process_name_size as usize],
+    ))
+}
+
+fn get_process_image_name(image_path: &str) -> String {
+    Path::new(image_path)
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// A running executable can't be deleted or overwritten, but it can be
+// renamed and another file put in its place. Either way the file at the path
+// is then missing, or newer than the process.
+unsafe fn is_image_deleted(image_path: &str, creation_time: u64) -> bool {
+    let path = U16CString::from_str_truncate(image_path);
+    let mut data = WIN32_FILE_ATTRIBUTE_DATA::default();
+    let result = GetFileAttributesExW(
+        PCWSTR(path.as_ptr()),
+        GetFileExInfoStandard,
+        &mut data as *mut WIN32_FILE_ATTRIBUTE_DATA as *mut c_void,
+    );
+    match result {
+        // Copying a file keeps its last write time but not its creation time
+        Ok(()) => {
+            let file_time =
+                filetime_to_u64(&data.ftCreationTime).max(filetime_to_u64(&data.ftLastWriteTime));
+            file_time > creation_time
+        }
+        Err(e) => {
+            e.code() == HRESULT::from_win32(ERROR_FILE_NOT_FOUND.0)
+                || e.code() == HRESULT::from_win32(ERROR_PATH_NOT_FOUND.0)
+        }
+    }
+}
+
+// Copies a structure out of another process's address space
+unsafe fn read_process_struct<T>(process: HANDLE, address: *const T) -> Result<T> {
+    let mut value: T = std::mem::zeroed();
+    ReadProcessMemory(
+        process,
+        address as *const c_void,
+        &mut value as *mut T as *mut c_void,
+        size_of::<T>(),
+        None,
+    )?;
+    Ok(value)
+}
+
+unsafe fn read_peb(process: HANDLE) -> Result<PEB> {
+    let mut basic_information: PROCESS_BASIC_INFORMATION = std::mem::zeroed();
+    NtQueryInformationProcess(
+        process,
+        ProcessBasicInformation,
+        &mut basic_information as *mut PROCESS_BASIC_INFORMATION as *mut c_void,
+        size_of::<PROCESS_BASIC_INFORMATION>() as u32,
+        &mut 0,
+    )
+    .ok()?;
+    read_process_struct(process, basic_information.PebBaseAddress)
+}
+
+unsafe fn read_command_line(process: HANDLE) -> Result<String> {
+    let peb = read_peb(process)?;
+    let parameters: RTL_USER_PROCESS_PARAMETERS =
+        read_process_struct(process, peb.ProcessParameters)?;
+
+    let command_line = parameters.CommandLine;
+    let mut buffer = vec![0u16; command_line.Length as usize / 2];
+    ReadProcessMemory(
+        process,
+        command_line.Buffer.as_ptr() as *const c_void,
+        buffer.as_mut_ptr() as *mut c_void,
+        buffer.len() * 2,
+        None,
+    )?;
+    Ok(String::from_utf16_lossy(&buffer))
+}
+
+// Where RTL_USER_PROCESS_PARAMETERS keeps the environment block and its size
+// in bytes. The windows crate's definition stops before them.
+#[cfg(target_pointer_width = "64")]
+const ENVIRONMENT_OFFSET: usize = 0x80;
+#[cfg(target_pointer_width = "64")]
+const ENVIRONMENT_SIZE_OFFSET: usize = 0x3f0;
+#[cfg(target_pointer_width = "32")]
+const ENVIRONMENT_OFFSET: usize = 0x48;
+#[cfg(target_pointer_width = "32")]
+const ENVIRONMENT_SIZE_OFFSET: usize = 0x290;
+
+// A block that claims to be bigger than this is taken to be garbage
+const MAX_ENVIRONMENT_SIZE: usize = 16 << 20;
+
+// The block of NUL terminated NAME=value strings the process's parameters
+// point at, ending with an empty one
+unsafe fn read_environment(process: HANDLE) -> Result<Vec<u16>> {
+    let peb = read_peb(process)?;
+    let parameters = peb.ProcessParameters as *const u8;
+    let address: *const c_void =
+        read_process_struct(process, parameters.add(ENVIRONMENT_OFFSET) as *const _)?;
+    let size: usize =
+        read_process_struct(process, parameters.add(ENVIRONMENT_SIZE_OFFSET) as *const _)?;
+    if size > MAX_ENVIRONMENT_SIZE {
+        return Err(E_UNEXPECTED.into());
+    }
+
+    let mut buffer = vec![0u16; size / 2];
+    ReadProcessMemory(
+        process,
+        address,
+        buffer.as_mut_ptr() as *mut c_void,
+        buffer.len() * 2,
+        None,
+    )?;
+    Ok(buffer)
+}
+
+// Needs the same access as the command line. Those errors come as HRESULTs,
+// which io::Error doesn't recognise as access denied.
+fn get_environment(pid: u32) -> std::io::Result<Vec<Variable>> {
+    let block = unsafe {
+        OpenProcess(
+            PROCESS_QUERY_LIMITED_INFORMATION | PROCESS_VM_READ,
+            false,
+            pid,
+        )
+        .and_then(|process| {
+            let block = read_environment(process);
+            let _ = CloseHandle(process);
+            block
+        })
+    };
+    let block = block.map_err(|e| {
+        if e.code() == E_ACCESSDENIED {
+            environment::access_error(ErrorKind::PermissionDenied.into())
+        } else {
+            e.into()
+        }
+    })?;
+    Ok(environment::parse_entries(
+        block
+            .split(|&c| c == 0)
+            .take_while(|entry| !entry.is_empty())
+            .map(String::from_utf16_lossy),
+    ))
+}
+
+// The command line only exists in the process's own memory, reached through
+// its PEB. It needs more access than the rest of the sample, which elevated
+// and protected processes don't give out, so those get an empty one. The
+// string is already quoted the way the process was started.
+unsafe fn get_process_command_line(pid: u32) -> String {
+    let Ok(process) = OpenProcess(
+        PROCESS_QUERY_LIMITED_INFORMATION | PROCESS_VM_READ,
+        false,
+        pid,
+    ) else {
+        return String::new();
+    };
+    let command_line = read_command_line(process).unwrap_or_default();
+    let _ = CloseHandle(process);
+    command_line
+}
+
+unsafe fn get_process_memory_info(process: HANDLE) -> Result<PROCESS_MEMORY_COUNTERS_EX2> {
+    let mut process_memory_counters = PROCESS_MEMORY_COUNTERS_EX2::default();
+    GetProcessMemoryInfo(
+        process,
+        transmute::<*mut PROCESS_MEMORY_COUNTERS_EX2, *mut PROCESS_MEMORY_COUNTERS>(
+            &mut process_memory_counters,
+        ),
+        size_of::<PROCESS_MEMORY_COUNTERS_EX2>() as u32,
+    )?;
+    Ok(process_memory_counters)
+}
+
+fn get_process_working_set_size(process_memory_counters: &PROCESS_MEMORY_COUNTERS_EX2) -> usize {
+    // Try to use PrivateWorkingSetSize first if the OS supports it, otherwise
+    // fallback on less accurate but more widely supported values.
+    if process_memory_counters.PrivateWorkingSetSize != 0 {
+        process_memory_counters.PrivateWorkingSetSize
+    } else if process_memory_counters.PrivateUsage != 0 {
+        process_memory_counters.PrivateUsage
+    } else {
+        process_memory_counters.WorkingSetSize
+    }
+}
+
+// The virtual size is only in the native API's VM_COUNTERS
+unsafe fn get_process_virtual_size(process: HANDLE) -> Option<u64> {
+    let mut vm_counters = VM_COUNTERS::default();
+    NtQueryInformationProcess(
+        process,
+        ProcessVmCounters,
+        &mut vm_counters as *mut VM_COUNTERS as *mut c_void,
+        size_of::<VM_COUNTERS>() as u32,
+        &mut 0,
+    )
+    .ok()
+    .ok()?;
+    Some(vm_counters.VirtualSize as u64)
+}
+
+unsafe fn get_process_memory_counters(
+    process: HANDLE,
+    process_memory_counters: &PROCESS_MEMORY_COUNTERS_EX2,
+) -> MemoryCounters {
+    MemoryCounters {
+        working_set: Some(process_memory_counters.WorkingSetSize as u64),
+        // Zero before Windows 10 1809, which doesn't keep it
+        private_working_set: Some(process_memory_counters.PrivateWorkingSetSize as u64)
+            .filter(|&size| size != 0),
+        peak_working_set: Some(process_memory_counters.PeakWorkingSetSize as u64),
+        commit: Some(process_memory_counters.PrivateUsage as u64),
+        virtual_size: get_process_virtual_size(process),
+        page_faults: Some(process_memory_counters.PageFaultCount as u64),
+        proportional: None,
+        unique: None,
+    }
+}
+
+fn get_page_size() -> u64 {
+    let mut system_info = SYSTEM_INFO::default();
+    unsafe { GetSystemInfo(&mut system_info) };
+    system_info.dwPageSize as u64
+}
+
+// One entry per resident page, each a PSAPI_WORKING_SET_BLOCK bitfield. The
+// first word of the buffer is the entry count, which is also how many it
+// needs when it's too small.
+unsafe fn query_working_set(process: HANDLE) -> Result<Vec<usize>> {
+    let mut buffer = vec![0usize; 4096];
+    loop {
+        match QueryWorkingSet(
+            process,
+            buffer.as_mut_ptr() as *mut c_void,
+            size_of_val(buffer.as_slice()) as u32,
+        ) {
+            Ok(()) => {
+                let count = buffer[0].min(buffer.len() - 1);
+                return Ok(buffer[1..=count].to_vec());
+            }
+            // Leaves room for pages faulted in before the next try
+            Err(e) if e.code() == ERROR_BAD_LENGTH.to_hresult() => {
+                let needed = buffer[0] + buffer[0] / 8 + 1;
+                buffer = vec![0usize; needed.max(buffer.len() * 2)];
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// PSS and USS from the working set's pages, each of which says whether it's
+// shared and with how many processes. Walking the pages needs more access
+// than the rest of the sample, so processes that don't give it out are left
+// without them.
+unsafe fn get_process_sharing(pid: u32, page_size: u64, counters: &mut MemoryCounters) {
+    let Ok(process) = OpenProcess(PROCESS_QUERY_INFORMATION | PROCESS_VM_READ, false, pid) else {
+        return;
+    };
+    let pages = query_working_set(process);
+    let _ = CloseHandle(process);
+    let Ok(pages) = pages else {
+        return;
+    };
+
+    let (mut proportional, mut unique) = (0, 0);
+    for page in pages {
+        // ShareCount is 3 bits, so it tops out at 7 sharers
+        let share_count = ((page >> 5) & 0x7) as u64;
+        let shared = (page >> 8) & 1 == 1;
+        if shared && share_count > 1 {
+            proportional += page_size / share_count;
+        } else {
+            proportional += page_size;
+            unique += page_size;
+        }
+    }
+    counters.proportional = Some(proportional);
+    counters.unique = Some(unique);
+}
+
+// A WOW64 process reports the machine it's emulated as, and anything else
+// runs as the machine itself
+unsafe fn get_process_architecture(process: HANDLE) -> String {
+    let mut process_machine = IMAGE_FILE_MACHINE::default();
+    let mut native_machine = IMAGE_FILE_MACHINE::default();
+    if IsWow64Process2(process, &mut process_machine, Some(&mut native_machine)).is_err() {
+        return String::new();
+    }
+    let machine = if process_machine.0 == 0 {
+        native_machine
+    } else {
+        process_machine
+    };
+    binary::pe_architecture(machine.0)
+}
+
+fn filetime_to_u64(time: &FILETIME) -> u64 {
+    ((time.dwHighDateTime as u64) << 32) | (time.dwLowDateTime as u64)
+}
+
+// 100ns intervals from 1601 to 1970
+const UNIX_EPOCH_FILETIME: u64 = 116_444_736_000_000_000;
+
+// A FILETIME timestamp, None for times before 1970 like the zero the idle
+// and System processes report
+fn filetime_to_system_time(time: u64) -> Option<SystemTime> {
+    let since_epoch = time.checked_sub(UNIX_EPOCH_FILETIME).filter(|&t| t > 0)?;
+    Some(UNIX_EPOCH + Duration::from_nanos(since_epoch * 100))
+}
+
+// When the process started and the kernel + user time it has used since
+unsafe fn get_process_times(process: HANDLE) -> Result<(u64, u64)> {
+    let mut creation_time = FILETIME::default();
+    let mut exit_time = FILETIME::default();
+    let mut kernel_time = FILETIME::default();
+    let mut user_time = FILETIME::default();
+
+    GetProcessTimes(
+        process,
+        &mut creation_time,
+        &mut exit_time,
+        &mut kernel_time,
+        &mut user_time,
+    )?;
+
+    let kernel_time_64 = filetime_to_u64(&kernel_time);
+    let user_time_64 = filetime_to_u64(&user_time);
+    Ok((
+        filetime_to_u64(&creation_time),
+        kernel_time_64 + user_time_64,
+    ))
+}
+
+// One class of information about a process's access token. Sized in u64s to
+// keep the pointers inside the structs aligned.
+unsafe fn get_token_information(
+    process: HANDLE,
+    class: TOKEN_INFORMATION_CLASS,
+) -> Result<Vec<u64>> {
+    let mut token = HANDLE::default();
+    OpenProcessToken(process, TOKEN_QUERY, &mut token)?;
+
+    let mut size = 0;
+    let _ = GetTokenInformation(token, class, None, 0, &mut size);
+    let mut buffer = vec![0u64; (size as usize).div_ceil(8)];
+    let result = GetTokenInformation(
+        token,
+        class,
+        Some(buffer.as_mut_ptr() as *mut c_void),
+        size,
+        &mut size,
+    );
+    let _ = CloseHandle(token);
+    result?;
+    Ok(buffer)
+}
+
+// The SID of the account a process runs as, from its access token
+unsafe fn get_process_sid(process: HANDLE) -> Result<Vec<u8>> {
+    let buffer = get_token_information(process, TokenUser)?;
+    let sid = (*(buffer.as_ptr() as *const TOKEN_USER)).User.Sid;
+    let length = GetLengthSid(sid) as usize;
+    Ok(std::slice::from_raw_parts(sid.0 as *const u8, length).to_vec())
+}
+
+unsafe fn lookup_account_name(sid: &[u8]) -> Result<String> {
+    let mut name = [0u16; 256];
+    let mut name_len = name.len() as u32;
+    let mut domain = [0u16; 256];
+    let mut domain_len = domain.len() as u32;
+    let mut sid_type = SID_NAME_USE::default();
+    LookupAccountSidW(
+        PCWSTR::null(),
+        PSID(sid.as_ptr() as *mut c_void),
+        Some(PWSTR(name.as_mut_ptr())),
+        &mut name_len,
+        Some(PWSTR(domain.as_mut_ptr())),
+        &mut domain_len,
+        &mut sid_type,
+    )?;
+    let name = String::from_utf16_lossy(&name[..name_len as usize]);
+    let domain = String::from_utf16_lossy(&domain[..domain_len as usize]);
+    if domain.is_empty() {
+        Ok(name)
+    } else {
+        Ok(format!("{}\\{}", domain, name))
+    }
+}
+
+unsafe fn get_process_user(process: HANDLE, user_names: &mut HashMap<Vec<u8>, String>) -> String {
+    let Ok(sid) = get_process_sid(process) else {
+        return String::new();
+    };
+    user_names
+        .entry(sid)
+        .or_insert_with_key(|sid| lookup_account_name(sid).unwrap_or_default())
+        .clone()
+}
+
+// Whether the token is elevated, and the RID of its mandatory label: the
+// last subauthority of the label's SID
+unsafe fn get_process_security(process: HANDLE) -> SecurityContext {
+    let elevated = get_token_information(process, TokenElevation)
+        .ok()
+        .map(|buffer| (*(buffer.as_ptr() as *const TOKEN_ELEVATION)).TokenIsElevated != 0);
+    let integrity_level = get_token_information(process, TokenIntegrityLevel)
+        .ok()
+        .and_then(|buffer| {
+            let sid = (*(buffer.as_ptr() as *const TOKEN_MANDATORY_LABEL))
+                .Label
+                .Sid;
+            let count = *GetSidSubAuthorityCount(sid);
+            let rid = GetSidSubAuthority(sid, count.checked_sub(1)? as u32);
+            Some(*rid)
+        });
+    SecurityContext {
+        elevated,
+        integrity_level,
+        ..Default::default()
+    }
+}
+
+pub fn current_user() -> String {
+    unsafe { get_process_user(GetCurrentProcess(), &mut HashMap::new()) }
+}
+
+// Job objects are as close as Windows gets to a cgroup. Their names can't be
+// read back from a process, so all that's known is whether it's in one.
+unsafe fn get_process_container(process: HANDLE) -> String {
+    let mut in_job = BOOL(0);
+    match IsProcessInJob(process, None, &mut in_job) {
+        Ok(()) if in_job.as_bool() => "Job".to_string(),
+        _ => String::new(),
+    }
+}
+
+// What the Toolhelp snapshot knows about a process that its handle doesn't
+// give out
+struct SnapshotEntry {
+    parent_pid: u32,
+    thread_count: u32,
+}
+
+// Entries for every running process, from one snapshot rather than a query
+// per process
+fn get_snapshot_entries() -> HashMap<u32, SnapshotEntry> {
+    let mut entries = HashMap::new();
+    unsafe {
+        let Ok(snapshot) = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) else {
+            return entries;
+        };
+        let mut entry = PROCESSENTRY32W {
+            dwSize: size_of::<PROCESSENTRY32W>() as u32,
+            ..Default::default()
+        };
+        let mut result = Process32FirstW(snapshot, &mut entry);
+        while result.is_ok() {
+            entries.insert(
+                entry.th32ProcessID,
+                SnapshotEntry {
+                    parent_pid: entry.th32ParentProcessID,
+                    thread_count: entry.cntThreads,
+                },
+            );
+            result = Process32NextW(snapshot, &mut entry);
+        }
+        let _ = CloseHandle(snapshot);
+    }
+    entries
+}
+
+// KTHREAD_STATE Waiting and KWAIT_REASON Suspended
+const THREAD_STATE_WAITING: u32 = 5;
+const WAIT_REASON_SUSPENDED: u32 = 5;
+
+// Processes whose every thread is suspended, like a UWP app in the
+// background or one started suspended. Thread states are only in the native
+// API's process list, which has a variable size entry per process with its
+// threads straight after it.
+unsafe fn get_suspended_processes() -> HashSet<u32> {
+    let mut suspended = HashSet::new();
+    let mut buffer: Vec<u64> = vec![0; 64 * 1024];
+    loop {
+        let mut needed = 0;
+        let status = NtQuerySystemInformation(
+            SystemProcessInformation,
+            buffer.as_mut_ptr() as *mut c_void,
+            size_of_val(buffer.as_slice()) as u32,
+            &mut needed,
+        );
+        if status == STATUS_INFO_LENGTH_MISMATCH {
+            // Leaves room for processes started before the next try
+            buffer = vec![0; (needed as usize / 8 + 1) * 2];
+            continue;
+        }
+        if status.is_err() {
+            return suspended;
+        }
+        break;
+    }
+
+    let mut entry = buffer.as_ptr() as *const u8;
+    loop {
+        let process = &*(entry as *const SYSTEM_PROCESS_INFORMATION);
+        let threads = std::slice::from_raw_parts(
+            entry.add(size_of::<SYSTEM_PROCESS_INFORMATION>()) as *const SYSTEM_THREAD_INFORMATION,
+            process.NumberOfThreads as usize,
+        );
+        let all_suspended = threads.iter().all(|thread| {
+            thread.ThreadState == THREAD_STATE_WAITING && thread.WaitReason == WAIT_REASON_SUSPENDED
+        });
+        if !threads.is_empty() && all_suspended {
+            suspended.insert(process.UniqueProcessId.0 as u32);
+        }
+        if process.NextEntryOffset == 0 {
+            break;
+        }
+        entry = entry.add(process.NextEntryOffset as usize);
+    }
+    suspended
+}
+
+unsafe extern "system" fn add_hung_window(window: HWND, lparam: LPARAM) -> BOOL {
+    let hung = &mut *(lparam.0 as *mut HashSet<u32>);
+    if IsWindowVisible(window).as_bool() && IsHungAppWindow(window).as_bool() {
+        let mut pid = 0;
+        GetWindowThreadProcessId(window, Some(&raw mut pid));
+        hung.insert(pid);
+    }
+    true.into()
+}
+
+// Processes with a visible top-level window that hasn't handled a message
+// in 5 seconds, which is what Windows calls Not Responding
+unsafe fn get_hung_processes() -> HashSet<u32> {
+    let mut hung = HashSet::new();
+    let _ = EnumWindows(Some(add_hung_window), LPARAM(&raw mut hung as isize));
+    hung
+}
+
+unsafe fn get_process_io(process: HANDLE) -> Option<IoCounters> {
+    let mut io_counters = IO_COUNTERS::default();
+    GetProcessIoCounters(process, &mut io_counters).ok()?;
+    Some(IoCounters {
+        read_bytes: io_counters.ReadTransferCount,
+        write_bytes: io_counters.WriteTransferCount,
+    })
+}
+
+unsafe fn get_process_handle_count(process: HANDLE) -> Option<u32> {
+    let mut handle_count = 0;
+    GetProcessHandleCount(process, &mut handle_count).ok()?;
+    Some(handle_count)
+}
+
+fn priority_class(priority: Priority) -> PROCESS_CREATION_FLAGS {
+    match priority {
+        Priority::Low => IDLE_PRIORITY_CLASS,
+        Priority::BelowNormal => BELOW_NORMAL_PRIORITY_CLASS,
+        Priority::Normal => NORMAL_PRIORITY_CLASS,
+        Priority::AboveNormal => ABOVE_NORMAL_PRIORITY_CLASS,
+        Priority::High => HIGH_PRIORITY_CLASS,
+        Priority::Realtime => REALTIME_PRIORITY_CLASS,
+    }
+}
+
+unsafe fn get_process_priority(process: HANDLE) -> Option<Priority> {
+    // 0 when it fails
+    let class = PROCESS_CREATION_FLAGS(GetPriorityClass(process));
+    Priority::ALL
+        .into_iter()
+        .find(|&priority| priority_class(priority) == class)
+}
+
+// The IO_PRIORITY_HINT the process's I/O starts out with, which only the
+// native API gives out
+unsafe fn get_process_io_priority(process: HANDLE) -> String {
+    let mut hint = 0u32;
+    let status = NtQueryInformationProcess(
+        process,
+        ProcessIoPriority,
+        &mut hint as *mut u32 as *mut c_void,
+        size_of::<u32>() as u32,
+        &mut 0,
+    );
+    if status.is_err() {
+        return String::new();
+    }
+    match hint {
+        0 => "Very low",
+        1 => "Low",
+        2 => "Normal",
+        3 => "High",
+        4 => "Critical",
+        _ => "",
+    }
+    .to_string()
+}
+
+fn open_process(pid: &u32) -> Option<(u32, HANDLE)> {
+    let result = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, *pid) };
+    if let Ok(handle) = result {
+        Some((*pid, handle))
+    } else {
+        None
+    }
+}
+
+unsafe fn query_process_information(
+    pid: u32,
+    process: HANDLE,
+    snapshot_entries: &HashMap<u32, SnapshotEntry>,
+    user_names: &mut HashMap<Vec<u8>, String>,
+    listening_ports: &HashMap<u32, Vec<u16>>,
+    // Set when PSS and USS are wanted
+    page_size: Option<u64>,
+    status: Status,
+) -> Result<Process> {
+    let image_path = get_process_image_path(process)?;
+    let image_name = get_process_image_name(&image_path);
+    let memory_info = get_process_memory_info(process)?;
+    let working_set_size = get_process_working_set_size(&memory_info);
+    let mut memory_counters = get_process_memory_counters(process, &memory_info);
+    if let Some(page_size) = page_size {
+        get_process_sharing(pid, page_size, &mut memory_counters);
+    }
+    let (creation_time, cpu_time) = get_process_times(process)?;
+    let image_deleted = is_image_deleted(&image_path, creation_time);
+    let container = get_process_container(process);
+    let command_line = get_process_command_line(pid);
+    let user = get_process_user(process, user_names);
+    let handle_count = get_process_handle_count(process);
+    let io = get_process_io(process);
+    let priority = get_process_priority(process);
+    let io_priority = get_process_io_priority(process);
+    let entry = snapshot_entries.get(&pid);
+    let sample_time = Instant::now();
+    Ok(Process {
+        pid,
+        parent_pid: entry.map_or(0, |e| e.parent_pid),
+        image_name,
+        image_path,
+        image_deleted,
+        command_line,
+        user,
+        real_user: String::new(),
+        private_working_set: working_set_size,
+        memory_counters,
+        thread_count: entry.map_or(0, |e| e.thread_count),
+        handle_count,
+        start_time: filetime_to_system_time(creation_time),
+        cpu_time,
+        sample_time,
+        sample_wall_time: SystemTime::now(),
+        status: Some(status),
+        status_since: sample_time,
+        cpu_usage: 0,
+        last_cpu: None,
+        io,
+        io_read_rate: 0,
+        io_write_rate: 0,
+        priority,
+        nice: None,
+        io_priority,
+        scheduling_policy: String::new(),
+        listening_ports: listening_ports.get(&pid).cloned().unwrap_or_default(),
+        container,
+        architecture: get_process_architecture(process),
+        security: get_process_security(process),
+        binary: BinaryInfo::default(),
+    })
+}
+
+// EnumProcesses doesn't say how big the list would have been, only that it
+// filled the buffer, so it's retried with twice the room until it doesn't
+fn get_pids() -> Result<Vec<u32>> {
+    let mut pid_list = vec![0u32; 1024];
+    loop {
+        let cb = (pid_list.len() * size_of::<u32>()) as u32;
+        let mut cb_needed: u32 = 0;
+        unsafe {
+            EnumProcesses(pid_list.as_mut_ptr(), cb, &mut cb_needed)?;
+        }
+        if cb_needed < cb {
+            pid_list.truncate(cb_needed as usize / size_of::<u32>());
+            return Ok(pid_list);
+        }
+        pid_list.resize(pid_list.len() * 2, 0);
+    }
+}
+
+impl ProcessSource for Win32ProcessSource {
+    fn sample(&mut self) -> std::io::Result<Vec<Process>> {
+        let pid_list = get_pids()?;
+        let snapshot_entries = get_snapshot_entries();
+        let listening_ports = connection::listening_ports();
+        let page_size = self.memory_mode.needs_sharing().then(get_page_size);
+        let suspended = unsafe { get_suspended_processes() };
+        let hung = unsafe { get_hung_processes() };
+        let mut processes = Vec::new();
+        for (pid, process_handle) in pid_list.iter().filter_map(open_process) {
+            // A suspended process's windows look hung too, so suspension is
+            // checked first
+            let status = if suspended.contains(&pid) {
+                Status::Suspended
+            } else if hung.contains(&pid) {
+                Status::NotResponding
+            } else {
+                Status::Running
+            };
+            let process = unsafe {
+                query_process_information(
+                    pid,
+                    process_handle,
+                    &snapshot_entries,
+                    &mut self.user_names,
+                    &listening_ports,
+                    page_size,
+                    status,
+                )
+            };
+            if let Ok(mut process) = process {
+                process.binary = self.binary_cache.get(&process.image_path);
+                processes.push(process);
+            }
+            unsafe {
+                let _ = CloseHandle(process_handle);
+            };
+        }
+
+        Ok(processes)
+    }
+
+    fn kill(&mut self, pid: u32) -> std::io::Result<()> {
+        kill_process(pid)
+    }
+
+    fn open_file_location(&mut self, process: &Process) -> std::io::Result<()> {
+        open_file_location(process)
+    }
+
+    fn set_priority(&mut self, pid: u32, priority: Priority) -> std::io::Result<()> {
+        set_priority(pid, priority)
+    }
+
+    fn affinity(&mut self, pid: u32) -> std::io::Result<Vec<u32>> {
+        get_affinity(pid)
+    }
+
+    fn set_affinity(&mut self, pid: u32, cpus: &[u32]) -> std::io::Result<()> {
+        set_affinity(pid, cpus)
+    }
+
+    fn connections(&mut self) -> std::io::Result<Vec<Connection>> {
+        connection::get_connections()
+    }
+
+    fn environment(&mut self, pid: u32) -> std::io::Result<Vec<Variable>> {
+        get_environment(pid)
+    }
+
+    fn set_memory_mode(&mut self, mode: MemoryMode) {
+        self.memory_mode = mode;
+    }
+}
+
+fn kill_process(pid: u32) -> std::io::Result<()> {
+    unsafe {
+        let handle = OpenProcess(PROCESS_TERMINATE, false, pid)?;
+        TerminateProcess(handle, 1)?;
+        CloseHandle(handle)?;
+        Ok(())
+    }
+}
+
+// Asking for Realtime without the privilege to raise priorities quietly
+// gets High instead
+fn set_priority(pid: u32, priority: Priority) -> std::io::Result<()> {
+    unsafe {
+        let handle = OpenProcess(PROCESS_SET_INFORMATION, false, pid)?;
+        let result = SetPriorityClass(handle, priority_class(priority));
+        let _ = CloseHandle(handle);
+        result?;
+        Ok(())
+    }
+}
+
+// Affinity masks have a bit per CPU of the processor group the process runs
+// in, so only the first 64 CPUs can be picked
+fn get_affinity(pid: u32) -> std::io::Result<Vec<u32>> {
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid)?;
+        let mut process_mask = 0;
+        let mut system_mask = 0;
+        let result = GetProcessAffinityMask(handle, &mut process_mask, &mut system_mask);
+        let _ = CloseHandle(handle);
+        result?;
+        Ok((0..usize::BITS)
+            .filter(|cpu| process_mask & (1 << cpu) != 0)
+            .collect())
+    }
+}
+
+fn set_affinity(pid: u32, cpus: &[u32]) -> std::io::Result<()> {
+    let mut mask = 0usize;
+    for &cpu in cpus {
+        if cpu >= usize::BITS {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("CPU {} is outside the processor group", cpu),
+            ));
+        }
+        mask |= 1 << cpu;
+    }
+    unsafe {
+        let handle = OpenProcess(PROCESS_SET_INFORMATION, false, pid)?;
+        let result = SetProcessAffinityMask(handle, mask);
+        let _ = CloseHandle(handle);
+        result?;
+        Ok(())
+    }
+}
+
+// Opens Explorer on the executable's folder with the file selected, or just
+// the folder if the file is gone
+fn open_file_location(process: &Process) -> std::io::Result<()> {
+    let folder = Path::new(&process.image_path)
+        .parent()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "the executable's location isn't known"))?;
+    let parameters = if process.image_deleted {
+        format!("\"{}\"", folder.display())
+    } else {
+        format!("/select,\"{}\"", process.image_path)
+    };
+    let parameters = U16CString::from_str_truncate(parameters);
+    let result = unsafe {
+        ShellExecuteW(
+            None,
+            w!("open"),
+            w!("explorer.exe"),
+            PCWSTR(parameters.as_ptr()),
+            None,
+            SW_SHOWNORMAL,
+        )
+    };
+    // Anything above 32 is success
+    if result.0 as usize <= 32 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,245 @@
+// Recordings are a header line followed by one snapshot per refresh tick:
+//
+//     taskmanager-recording version=1 num_cpus=<count>
+//     tick ...
+//
+// See snapshot.rs for the tick encoding.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Result, Write},
+    path::Path,
+    time::Instant,
+};
+
+use crate::{
+    connection::Connection,
+    environment::Variable,
+    process::{Priority, Process, ProcessSource},
+    snapshot,
+    system::SystemStatus,
+};
+
+const MAGIC: &str = "taskmanager-recording";
+const VERSION: u32 = 1;
+
+pub struct Recorder {
+    out: BufWriter<File>,
+    epoch: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, num_cpus: u32) -> Result<Recorder> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "{} version={} num_cpus={}", MAGIC, VERSION, num_cpus)?;
+        Ok(Recorder {
+            out,
+            epoch: Instant::now(),
+        })
+    }
+
+    pub fn record<'a>(
+        &mut self,
+        processes: impl ExactSizeIterator<Item = &'a Process>,
+        system_status: &SystemStatus,
+    ) -> Result<()> {
+        snapshot::write_snapshot(&mut self.out, self.epoch, processes, system_status)?;
+        // Flush every tick so a recording survives whatever it was capturing
+        self.out.flush()
+    }
+}
+
+pub struct ReplaySource {
+    reader: BufReader<File>,
+    epoch: Instant,
+    num_cpus: u32,
+    system_status: Option<SystemStatus>,
+}
+
+fn header_value(header: &str, key: &str) -> Option<u32> {
+    header
+        .split_whitespace()
+        .filter_map(|word| word.split_once('='))
+        .find(|(k, _)| *k == key)
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+impl ReplaySource {
+    pub fn open(path: &Path) -> Result<ReplaySource> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+
+        if header.split_whitespace().next() != Some(MAGIC) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} is not a taskmanager recording", path.display()),
+            ));
+        }
+        let version = header_value(&header, "version").unwrap_or(0);
+        if version != VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported recording version {}", version),
+            ));
+        }
+
+        Ok(ReplaySource {
+            reader,
+            epoch: Instant::now(),
+            num_cpus: header_value(&header, "num_cpus").unwrap_or(1).max(1),
+            system_status: None,
+        })
+    }
+}
+
+impl ProcessSource for ReplaySource {
+    fn sample(&mut self) -> Result<Vec<Process>> {
+        let Some(snapshot) = snapshot::read_snapshot(&mut self.reader, self.epoch)? else {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "end of recording"));
+        };
+        self.system_status = Some(snapshot.system_status);
+        Ok(snapshot.processes)
+    }
+
+    fn kill(&mut self, _pid: u32) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "can't end a task in a recording",
+        ))
+    }
+
+    fn open_file_location(&mut self, _process: &Process) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "can't open files from a recording",
+        ))
+    }
+
+    fn set_priority(&mut self, _pid: u32, _priority: Priority) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "can't change priorities in a recording",
+        ))
+    }
+
+    fn affinity(&mut self, _pid: u32) -> Result<Vec<u32>> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "recordings don't include CPU affinity",
+        ))
+    }
+
+    fn set_affinity(&mut self, _pid: u32, _cpus: &[u32]) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "can't change CPU affinity in a recording",
+        ))
+    }
+
+    fn connections(&mut self) -> Result<Vec<Connection>> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "recordings don't include connections",
+        ))
+    }
+
+    fn environment(&mut self, _pid: u32) -> Result<Vec<Variable>> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "recordings don't include environment variables",
+        ))
+    }
+
+    fn num_cpus(&self) -> Option<u32> {
+        Some(self.num_cpus)
+    }
+
+    fn system_status(&self) -> Option<SystemStatus> {
+        self.system_status.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs, path::PathBuf, time::Duration};
+
+    use super::*;
+    use crate::process::{self, tests::example_process};
+
+    // Removed again when the test is done, pass or fail
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> TempFile {
+            let file_name = format!("taskmanager-{}-{}", std::process::id(), name);
+            TempFile(std::env::temp_dir().join(file_name))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    // Two ticks a second apart in which process 1 uses half a CPU's worth of
+    // time and process 2 exits
+    fn record_example(path: &Path) {
+        let mut recorder = Recorder::create(path, 4).unwrap();
+        let mut first = [example_process(1), example_process(2)];
+        for process in &mut first {
+            process.sample_time = recorder.epoch + Duration::from_secs(1);
+        }
+        let mut second = [example_process(1)];
+        second[0].sample_time = recorder.epoch + Duration::from_secs(2);
+        second[0].cpu_time = first[0].cpu_time + 5_000_000;
+
+        let system_status = SystemStatus::default();
+        recorder.record(first.iter(), &system_status).unwrap();
+        recorder.record(second.iter(), &system_status).unwrap();
+    }
+
+    // PID and CPU usage of each process in every tick of the recording
+    fn replay(path: &Path) -> Vec<Vec<(u32, u64)>> {
+        let mut source = ReplaySource::open(path).unwrap();
+        assert_eq!(source.num_cpus(), Some(4));
+        let mut ticks = Vec::new();
+        let mut pid_map = HashMap::new();
+        loop {
+            match process::get_processes(&mut source, &pid_map, 4) {
+                Ok(new_pid_map) => pid_map = new_pid_map,
+                Err(e) => {
+                    assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+                    return ticks;
+                }
+            }
+            let mut tick: Vec<(u32, u64)> =
+                pid_map.values().map(|p| (p.pid, p.cpu_usage)).collect();
+            tick.sort();
+            ticks.push(tick);
+        }
+    }
+
+    #[test]
+    fn replay_is_deterministic() {
+        let file = TempFile::new("replay.rec");
+        record_example(&file.0);
+
+        let ticks = replay(&file.0);
+        assert_eq!(ticks, vec![vec![(1, 0), (2, 0)], vec![(1, 13)]]);
+        assert_eq!(replay(&file.0), ticks);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let file = TempFile::new("not-a-recording.txt");
+        fs::write(&file.0, "hello\n").unwrap();
+        let error = ReplaySource::open(&file.0).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        fs::write(&file.0, format!("{} version=99 num_cpus=1\n", MAGIC)).unwrap();
+        let error = ReplaySource::open(&file.0).err().unwrap();
+        assert!(error.to_string().contains("version 99"));
+    }
+}
@@ -15,7 +15,53 @@ pub const IDM_NEW_TASK: u16 = 104;
 pub const IDM_END_TASK: u16 = 105;
 pub const IDM_TASK_CONTEXT_MENU: u16 = 106;
 pub const IDD_ABOUTBOX: u16 = 107;
+pub const IDM_TREE_VIEW: u16 = 108;
+pub const IDM_OPEN_FILE_LOCATION: u16 = 109;
+pub const IDM_ONLY_MY_PROCESSES: u16 = 110;
+pub const IDM_USER_TOTALS: u16 = 111;
+pub const IDM_CONNECTIONS: u16 = 112;
+pub const IDD_CONNECTIONS: u16 = 113;
+// The priorities in between are picked by their offset from
+// IDM_PRIORITY_REALTIME, in the order of Priority::ALL
+pub const IDM_PRIORITY_REALTIME: u16 = 114;
+#[allow(dead_code)]
+pub const IDM_PRIORITY_HIGH: u16 = 115;
+#[allow(dead_code)]
+pub const IDM_PRIORITY_ABOVE_NORMAL: u16 = 116;
+#[allow(dead_code)]
+pub const IDM_PRIORITY_NORMAL: u16 = 117;
+#[allow(dead_code)]
+pub const IDM_PRIORITY_BELOW_NORMAL: u16 = 118;
+pub const IDM_PRIORITY_LOW: u16 = 119;
+pub const IDM_SET_AFFINITY: u16 = 120;
+pub const IDD_AFFINITY: u16 = 121;
+pub const IDM_SELECT_COLUMNS: u16 = 122;
+pub const IDD_COLUMNS: u16 = 123;
+// Likewise by offset from IDM_MEMORY_PRIVATE, in the order of MemoryMode::ALL
+pub const IDM_MEMORY_PRIVATE: u16 = 124;
+#[allow(dead_code)]
+pub const IDM_MEMORY_RSS: u16 = 125;
+#[allow(dead_code)]
+pub const IDM_MEMORY_PSS: u16 = 126;
+pub const IDM_MEMORY_USS: u16 = 127;
+pub const IDM_ONLY_ELEVATED: u16 = 128;
+pub const IDM_SECURITY_DETAILS: u16 = 129;
+pub const IDM_ENVIRONMENT: u16 = 130;
+pub const IDD_ENVIRONMENT: u16 = 131;
+
+pub const IDC_CONNECTION_LIST: i32 = 1001;
+pub const IDC_END_OWNING_PROCESS: i32 = 1002;
+pub const IDC_AFFINITY_TEXT: i32 = 1003;
+pub const IDC_COLUMN_LIST: i32 = 1004;
+pub const IDC_ENVIRONMENT_SEARCH: i32 = 1005;
+pub const IDC_ENVIRONMENT_LIST: i32 = 1006;
+pub const IDC_COPY_ENVIRONMENT: i32 = 1007;
+pub const IDC_EXPORT_ENVIRONMENT: i32 = 1008;
+// The affinity dialog's checkboxes are made at run time, one per CPU, with
+// IDs counting up from here
+pub const IDC_FIRST_CPU: i32 = 1100;
 
 pub const ID_TASK_LIST: i32 = 2000;
 pub const ID_UPDATE_TIMER: i32 = 2001;
 pub const ID_STATUS_BAR: i32 = 2002;
+pub const ID_SEARCH_BOX: i32 = 2003;
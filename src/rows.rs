@@ -0,0 +1,484 @@
+// Turns a sample into the rows the process list shows, either one per
+// process or as a tree of parents and children.
+
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::{
+    process::{MemoryMode, Process},
+    sort::{self, SortState},
+};
+
+// A process as the list shows it. In tree mode the CPU and memory figures
+// are totals for the process and everything below it.
+#[derive(Clone)]
+pub struct Row {
+    pub process: Rc<Process>,
+    pub cpu_usage: u64,
+    pub memory: usize,
+    // Tree mode only: how many ancestors are above the row
+    pub depth: Option<usize>,
+    // Tree mode only: whether the row's children are showing, None if it
+    // has none
+    pub expanded: Option<bool>,
+}
+
+impl Row {
+    fn new(process: &Rc<Process>, memory_mode: MemoryMode) -> Row {
+        Row {
+            process: process.clone(),
+            cpu_usage: process.cpu_usage,
+            memory: process.memory(memory_mode),
+            depth: None,
+            expanded: None,
+        }
+    }
+
+    // The image name, indented and marked to show its place in the tree
+    pub fn name(&self) -> String {
+        let Some(depth) = self.depth else {
+            return self.process.image_name.clone();
+        };
+        let marker = match self.expanded {
+            Some(true) => "\u{25be} ",
+            Some(false) => "\u{25b8} ",
+            None => "  ",
+        };
+        format!(
+            "{}{}{}",
+            "  ".repeat(depth),
+            marker,
+            self.process.image_name
+        )
+    }
+}
+
+// Which processes the list shows. The default shows them all.
+#[derive(Clone, Default)]
+pub struct Filter {
+    // Shown if it appears in the image name or command line, ignoring case
+    pub text: String,
+    // Shown if the process runs as this user, or was started by them
+    pub user: Option<String>,
+    // Shown only if it runs elevated, or privileged on Linux
+    pub elevated_only: bool,
+}
+
+impl Filter {
+    fn is_empty(&self) -> bool {
+        self.text.is_empty() && self.user.is_none() && !self.elevated_only
+    }
+
+    // `text` has to be lowercase already
+    fn matches(&self, process: &Process, text: &str) -> bool {
+        let text_matches = process.image_name.to_lowercase().contains(text)
+            || process.command_line.to_lowercase().contains(text);
+        let user_matches = self
+            .user
+            .as_ref()
+            .is_none_or(|user| process.user == *user || process.real_user == *user);
+        let elevated_matches = !self.elevated_only || process.security.elevated == Some(true);
+        text_matches && user_matches && elevated_matches
+    }
+}
+
+// The processes that match `filter`. With `with_ancestors`, the parents of
+// each match come along too, up to its root, so a tree can still show where
+// the matches are.
+// The process in the sample with `process`'s parent PID, unless that PID has
+// since been reused by a process started after it. A missing start time is
+// given the benefit of the doubt.
+fn parent_of<'a>(
+    pid_map: &'a HashMap<u32, Rc<Process>>,
+    process: &Process,
+) -> Option<&'a Rc<Process>> {
+    if process.parent_pid == process.pid {
+        return None;
+    }
+    let parent = pid_map.get(&process.parent_pid)?;
+    match (parent.start_time, process.start_time) {
+        (Some(parent_start), Some(start)) if parent_start > start => None,
+        _ => Some(parent),
+    }
+}
+
+fn filter_processes(
+    pid_map: &HashMap<u32, Rc<Process>>,
+    filter: &Filter,
+    with_ancestors: bool,
+) -> HashMap<u32, Rc<Process>> {
+    let text = filter.text.to_lowercase();
+    let mut kept = HashMap::new();
+    for process in pid_map.values().filter(|p| filter.matches(p, &text)) {
+        kept.insert(process.pid, process.clone());
+        if !with_ancestors {
+            continue;
+        }
+        let mut child = process;
+        while let Some(parent) = parent_of(pid_map, child) {
+            // Already kept means the rest of the way up is too, and it stops
+            // PID reuse loops
+            if kept.insert(parent.pid, parent.clone()).is_some() {
+                break;
+            }
+            child = parent;
+        }
+    }
+    kept
+}
+
+pub fn flat_rows(
+    pid_map: &HashMap<u32, Rc<Process>>,
+    sort_state: SortState,
+    filter: &Filter,
+    memory_mode: MemoryMode,
+) -> Vec<Row> {
+    let new_row = |process| Row::new(process, memory_mode);
+    let mut rows: Vec<Row> = if filter.is_empty() {
+        pid_map.values().map(new_row).collect()
+    } else {
+        filter_processes(pid_map, filter, false)
+            .values()
+            .map(new_row)
+            .collect()
+    };
+    sort::sort_rows(&mut rows, sort_state);
+    rows
+}
+
+struct Node {
+    row: Row,
+    children: Vec<Node>,
+}
+
+fn build_node(
+    process: &Rc<Process>,
+    children: &HashMap<u32, Vec<Rc<Process>>>,
+    visited: &mut HashSet<u32>,
+    memory_mode: MemoryMode,
+) -> Node {
+    visited.insert(process.pid);
+    let mut node = Node {
+        row: Row::new(process, memory_mode),
+        children: Vec::new(),
+    };
+    for child in children.get(&process.pid).into_iter().flatten() {
+        // A reused PID can make a process its own ancestor
+        if visited.contains(&child.pid) {
+            continue;
+        }
+        let child = build_node(child, children, visited, memory_mode);
+        node.row.cpu_usage += child.row.cpu_usage;
+        node.row.memory += child.row.memory;
+        node.children.push(child);
+    }
+    node
+}
+
+fn flatten(
+    mut nodes: Vec<Node>,
+    depth: usize,
+    sort_state: SortState,
+    collapsed: &HashSet<u32>,
+    rows: &mut Vec<Row>,
+) {
+    nodes.sort_by(|a, b| sort::compare_rows(&a.row, &b.row, sort_state));
+    for mut node in nodes {
+        let expanded = !collapsed.contains(&node.row.process.pid);
+        node.row.depth = Some(depth);
+        node.row.expanded = (!node.children.is_empty()).then_some(expanded);
+        rows.push(node.row);
+        if expanded {
+            flatten(node.children, depth + 1, sort_state, collapsed, rows);
+        }
+    }
+}
+
+// Processes whose parent isn't in the sample (it exited, it's in another
+// session, its PID was reused, or there never was one) become roots. Siblings
+// are sorted by `sort_state`, using the subtree totals for CPU and memory.
+// The children of PIDs in `collapsed` are left out. With a `filter`, only the
+// matching processes and their ancestors are shown, and the totals only count
+// those.
+pub fn tree_rows(
+    pid_map: &HashMap<u32, Rc<Process>>,
+    sort_state: SortState,
+    collapsed: &HashSet<u32>,
+    filter: &Filter,
+    memory_mode: MemoryMode,
+) -> Vec<Row> {
+    let filtered;
+    let pid_map = if filter.is_empty() {
+        pid_map
+    } else {
+        filtered = filter_processes(pid_map, filter, true);
+        &filtered
+    };
+
+    let mut children: HashMap<u32, Vec<Rc<Process>>> = HashMap::new();
+    let mut roots = Vec::new();
+    for process in pid_map.values() {
+        if let Some(parent) = parent_of(pid_map, process) {
+            children
+                .entry(parent.pid)
+                .or_default()
+                .push(process.clone());
+        } else {
+            roots.push(process.clone());
+        }
+    }
+
+    let mut visited = HashSet::new();
+    let mut nodes: Vec<Node> = roots
+        .iter()
+        .map(|root| build_node(root, &children, &mut visited, memory_mode))
+        .collect();
+    // Whatever is left is a loop of processes that are each other's parents
+    // through reused PIDs. Break it open at the lowest PID.
+    let mut leftover: Vec<&Rc<Process>> = pid_map
+        .values()
+        .filter(|p| !visited.contains(&p.pid))
+        .collect();
+    leftover.sort_by_key(|p| p.pid);
+    for process in leftover {
+        if !visited.contains(&process.pid) {
+            nodes.push(build_node(process, &children, &mut visited, memory_mode));
+        }
+    }
+
+    let mut rows = Vec::with_capacity(pid_map.len());
+    flatten(nodes, 0, sort_state, collapsed, &mut rows);
+    rows
+}
+
+// What one user's processes add up to
+pub struct UserTotal {
+    pub user: String,
+    pub processes: usize,
+    pub cpu_usage: u64,
+    pub memory: usize,
+}
+
+// Totals for every user with a process in the sample, the heaviest memory
+// user first
+pub fn user_totals(pid_map: &HashMap<u32, Rc<Process>>, memory_mode: MemoryMode) -> Vec<UserTotal> {
+    let mut totals: HashMap<&str, UserTotal> = HashMap::new();
+    for process in pid_map.values() {
+        let total = totals.entry(&process.user).or_insert_with(|| UserTotal {
+            user: process.user.clone(),
+            processes: 0,
+            cpu_usage: 0,
+            memory: 0,
+        });
+        total.processes += 1;
+        total.cpu_usage += process.cpu_usage;
+        total.memory += process.memory(memory_mode);
+    }
+    let mut totals: Vec<UserTotal> = totals.into_values().collect();
+    totals.sort_by(|a, b| b.memory.cmp(&a.memory).then_with(|| a.user.cmp(&b.user)));
+    totals
+}
+
+// Every process's memory added up, the way the status bar shows it
+pub fn memory_total(pid_map: &HashMap<u32, Rc<Process>>, memory_mode: MemoryMode) -> usize {
+    pid_map
+        .values()
+        .map(|process| process.memory(memory_mode))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{process::tests::example_process, sort::SortKey};
+
+    fn pid_map(processes: &[(u32, u32, u64)]) -> HashMap<u32, Rc<Process>> {
+        processes
+            .iter()
+            .map(|&(pid, parent_pid, cpu_usage)| {
+                let mut process = example_process(pid);
+                process.parent_pid = parent_pid;
+                process.cpu_usage = cpu_usage;
+                (pid, Rc::new(process))
+            })
+            .collect()
+    }
+
+    // PID, depth and CPU total of each row
+    fn shape(rows: &[Row]) -> Vec<(u32, usize, u64)> {
+        rows.iter()
+            .map(|row| (row.process.pid, row.depth.unwrap(), row.cpu_usage))
+            .collect()
+    }
+
+    const BY_PID: SortState = SortState::SortUp(SortKey::Pid);
+
+    fn text(text: &str) -> Filter {
+        Filter {
+            text: text.to_string(),
+            ..Filter::default()
+        }
+    }
+
+    #[test]
+    fn tree_with_totals() {
+        let pid_map = pid_map(&[(1, 0, 1), (2, 1, 2), (3, 2, 4), (4, 1, 8), (9, 5, 16)]);
+        let rows = tree_rows(
+            &pid_map,
+            BY_PID,
+            &HashSet::new(),
+            &Filter::default(),
+            MemoryMode::default(),
+        );
+        assert_eq!(
+            shape(&rows),
+            vec![(1, 0, 15), (2, 1, 6), (3, 2, 4), (4, 1, 8), (9, 0, 16)]
+        );
+        assert_eq!(rows[0].expanded, Some(true));
+        assert_eq!(rows[2].expanded, None);
+    }
+
+    #[test]
+    fn collapsed_rows_keep_their_totals() {
+        let pid_map = pid_map(&[(1, 0, 1), (2, 1, 2), (3, 2, 4)]);
+        let collapsed = HashSet::from([2]);
+        let rows = tree_rows(
+            &pid_map,
+            BY_PID,
+            &collapsed,
+            &Filter::default(),
+            MemoryMode::default(),
+        );
+        assert_eq!(shape(&rows), vec![(1, 0, 7), (2, 1, 6)]);
+        assert_eq!(rows[1].expanded, Some(false));
+    }
+
+    // Processes that are each other's parents are still all shown, once
+    #[test]
+    fn parent_loop() {
+        let pid_map = pid_map(&[(5, 6, 1), (6, 5, 2), (7, 7, 4)]);
+        let rows = tree_rows(
+            &pid_map,
+            BY_PID,
+            &HashSet::new(),
+            &Filter::default(),
+            MemoryMode::default(),
+        );
+        assert_eq!(shape(&rows), vec![(5, 0, 3), (6, 1, 2), (7, 0, 4)]);
+    }
+
+    // A parent PID that now belongs to a younger process isn't the parent
+    #[test]
+    fn reused_parent_pid() {
+        let mut pid_map = pid_map(&[(1, 0, 1), (2, 1, 2), (3, 1, 4), (4, 1, 8)]);
+        let start_time = pid_map[&1].start_time.unwrap();
+        for (pid, start_time) in [
+            (2, Some(start_time - Duration::from_secs(60))),
+            (3, Some(start_time + Duration::from_secs(60))),
+            (4, None),
+        ] {
+            let mut process = (*pid_map[&pid]).clone();
+            process.start_time = start_time;
+            pid_map.insert(pid, Rc::new(process));
+        }
+        let rows = tree_rows(
+            &pid_map,
+            BY_PID,
+            &HashSet::new(),
+            &Filter::default(),
+            MemoryMode::default(),
+        );
+        assert_eq!(
+            shape(&rows),
+            vec![(1, 0, 13), (3, 1, 4), (4, 1, 8), (2, 0, 2)]
+        );
+
+        let mut process = (*pid_map[&2]).clone();
+        process.image_name = "Needle.exe".to_string();
+        pid_map.insert(2, Rc::new(process));
+        let rows = tree_rows(
+            &pid_map,
+            BY_PID,
+            &HashSet::new(),
+            &text("needle"),
+            MemoryMode::default(),
+        );
+        assert_eq!(shape(&rows), vec![(2, 0, 2)]);
+    }
+
+    // Matches keep their ancestors in a tree, and only themselves in a list
+    #[test]
+    fn filtered() {
+        let mut pid_map = pid_map(&[(1, 0, 1), (2, 1, 2), (3, 2, 4), (4, 1, 8)]);
+        let mut process = (*pid_map[&3]).clone();
+        process.image_name = "Needle.exe".to_string();
+        pid_map.insert(3, Rc::new(process));
+
+        let rows = tree_rows(
+            &pid_map,
+            BY_PID,
+            &HashSet::new(),
+            &text("needle"),
+            MemoryMode::default(),
+        );
+        assert_eq!(shape(&rows), vec![(1, 0, 7), (2, 1, 6), (3, 2, 4)]);
+
+        let rows = flat_rows(&pid_map, BY_PID, &text("NEEDLE"), MemoryMode::default());
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].process.pid, 3);
+        assert_eq!(
+            flat_rows(&pid_map, BY_PID, &Filter::default(), MemoryMode::default()).len(),
+            4
+        );
+    }
+
+    // A process started by the user counts as theirs even once it has
+    // switched to another one
+    #[test]
+    fn filtered_by_user() {
+        let mut pid_map = pid_map(&[(1, 0, 1), (2, 1, 2), (3, 1, 4)]);
+        for (pid, user, real_user) in [
+            (1, "root", "root"),
+            (2, "alice", "alice"),
+            (3, "root", "alice"),
+        ] {
+            let mut process = (*pid_map[&pid]).clone();
+            process.user = user.to_string();
+            process.real_user = real_user.to_string();
+            pid_map.insert(pid, Rc::new(process));
+        }
+        let filter = Filter {
+            user: Some("alice".to_string()),
+            ..Filter::default()
+        };
+        let rows = flat_rows(&pid_map, BY_PID, &filter, MemoryMode::default());
+        let pids: Vec<u32> = rows.iter().map(|row| row.process.pid).collect();
+        assert_eq!(pids, vec![2, 3]);
+    }
+
+    #[test]
+    fn totals_per_user() {
+        let mut pid_map = pid_map(&[(1, 0, 1), (2, 1, 2), (3, 1, 4)]);
+        for (pid, user, memory) in [(1, "root", 100), (2, "alice", 300), (3, "root", 50)] {
+            let mut process = (*pid_map[&pid]).clone();
+            process.user = user.to_string();
+            process.private_working_set = memory;
+            pid_map.insert(pid, Rc::new(process));
+        }
+        let totals: Vec<(String, usize, u64, usize)> = user_totals(&pid_map, MemoryMode::default())
+            .into_iter()
+            .map(|t| (t.user, t.processes, t.cpu_usage, t.memory))
+            .collect();
+        assert_eq!(
+            totals,
+            vec![
+                ("alice".to_string(), 1, 2, 300),
+                ("root".to_string(), 2, 5, 150)
+            ]
+        );
+    }
+}
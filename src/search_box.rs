@@ -0,0 +1,59 @@
+use std::ffi::c_void;
+use windows::{
+    core::{w, Result},
+    Win32::{
+        Foundation::{HINSTANCE, HWND, LPARAM, WPARAM},
+        UI::{
+            Controls::{EM_SETCUEBANNER, WC_EDITW},
+            WindowsAndMessaging::{
+                CreateWindowExW, GetWindowTextLengthW, GetWindowTextW, SendMessageW,
+                ES_AUTOHSCROLL, HMENU, WINDOW_STYLE, WS_CHILD, WS_EX_CLIENTEDGE, WS_TABSTOP,
+                WS_VISIBLE,
+            },
+        },
+    },
+};
+
+// In pixels, above the task list
+pub const SEARCH_BOX_HEIGHT: i32 = 24;
+
+pub fn create_control(instance: &HINSTANCE, parent: HWND) -> Result<HWND> {
+    let window_style = WINDOW_STYLE(ES_AUTOHSCROLL as u32) | WS_TABSTOP | WS_CHILD | WS_VISIBLE;
+    let hwnd = unsafe {
+        CreateWindowExW(
+            WS_EX_CLIENTEDGE,
+            WC_EDITW,
+            w!(""),
+            window_style,
+            0,
+            0,
+            0,
+            0,
+            Some(parent),
+            Some(HMENU(crate::resources::ID_SEARCH_BOX as *mut c_void)),
+            Some(*instance),
+            None,
+        )?
+    };
+
+    // Greyed out hint shown while the box is empty
+    let cue = w!("Search by name or command line");
+    unsafe {
+        SendMessageW(
+            hwnd,
+            EM_SETCUEBANNER,
+            Some(WPARAM(0)),
+            Some(LPARAM(cue.as_ptr() as isize)),
+        );
+    }
+    Ok(hwnd)
+}
+
+pub fn get_text(search_box: HWND) -> String {
+    unsafe {
+        let len = GetWindowTextLengthW(search_box);
+        let mut text = vec![0u16; len as usize + 1];
+        let copied = GetWindowTextW(search_box, &mut text);
+        String::from_utf16_lossy(&text[..copied as usize])
+    }
+}
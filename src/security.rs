@@ -0,0 +1,187 @@
+// How privileged a process is, in the terms each OS judges it by: the
+// elevation and integrity level of its access token on Windows, its user IDs,
+// capabilities and sandboxing on Linux. The OS that doesn't have a field
+// leaves it as None.
+
+// Linux's capabilities, by bit number in the CapEff mask
+const CAPABILITY_NAMES: [&str; 41] = [
+    "chown",
+    "dac_override",
+    "dac_read_search",
+    "fowner",
+    "fsetid",
+    "kill",
+    "setgid",
+    "setuid",
+    "setpcap",
+    "linux_immutable",
+    "net_bind_service",
+    "net_broadcast",
+    "net_admin",
+    "net_raw",
+    "ipc_lock",
+    "ipc_owner",
+    "sys_module",
+    "sys_rawio",
+    "sys_chroot",
+    "sys_ptrace",
+    "sys_pacct",
+    "sys_admin",
+    "sys_boot",
+    "sys_nice",
+    "sys_resource",
+    "sys_time",
+    "sys_tty_config",
+    "mknod",
+    "lease",
+    "audit_write",
+    "audit_control",
+    "setfcap",
+    "mac_override",
+    "mac_admin",
+    "syslog",
+    "wake_alarm",
+    "block_suspend",
+    "audit_read",
+    "perfmon",
+    "bpf",
+    "checkpoint_restore",
+];
+
+const ALL_CAPABILITIES: u64 = (1 << CAPABILITY_NAMES.len()) - 1;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SecurityContext {
+    // Running with an administrator's full token on Windows. On Linux, as
+    // root or with any effective capability.
+    pub elevated: Option<bool>,
+    // Windows only: the RID of the token's mandatory label, like 0x3000 for
+    // High
+    pub integrity_level: Option<u32>,
+    // Linux only: the real and effective user IDs
+    pub uid: Option<u32>,
+    pub euid: Option<u32>,
+    // Linux only: the CapEff mask
+    pub capabilities: Option<u64>,
+    pub no_new_privs: Option<bool>,
+    // Linux only: 0 when disabled, 1 for strict mode, 2 for a filter
+    pub seccomp: Option<u32>,
+}
+
+fn integrity_level_name(rid: u32) -> &'static str {
+    match rid {
+        0..0x1000 => "Untrusted",
+        0x1000..0x2000 => "Low",
+        0x2000..0x3000 => "Medium",
+        0x3000..0x4000 => "High",
+        0x4000..0x5000 => "System",
+        _ => "Protected",
+    }
+}
+
+fn seccomp_name(mode: u32) -> &'static str {
+    match mode {
+        0 => "disabled",
+        1 => "strict",
+        2 => "filter",
+        _ => "unknown",
+    }
+}
+
+// "all" when it has every capability this version knows of, since listing
+// 41 names is no help
+fn capability_names(capabilities: u64) -> String {
+    if capabilities == 0 {
+        return "none".to_string();
+    }
+    if capabilities & ALL_CAPABILITIES == ALL_CAPABILITIES {
+        return "all".to_string();
+    }
+    CAPABILITY_NAMES
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| capabilities & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+impl SecurityContext {
+    // What the Security column shows, like "Elevated, High integrity" or
+    // "Not elevated, seccomp filter"
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        match self.elevated {
+            Some(true) => parts.push("Elevated".to_string()),
+            Some(false) => parts.push("Not elevated".to_string()),
+            None => {}
+        }
+        if let Some(rid) = self.integrity_level {
+            parts.push(format!("{} integrity", integrity_level_name(rid)));
+        }
+        match self.capabilities {
+            Some(0) | None => {}
+            Some(capabilities) if capabilities & ALL_CAPABILITIES == ALL_CAPABILITIES => {
+                parts.push("all capabilities".to_string())
+            }
+            Some(capabilities) => parts.push(format!("{} capabilities", capabilities.count_ones())),
+        }
+        if self.no_new_privs == Some(true) {
+            parts.push("no_new_privs".to_string());
+        }
+        if let Some(mode) = self.seccomp.filter(|&mode| mode != 0) {
+            parts.push(format!("seccomp {}", seccomp_name(mode)));
+        }
+        parts.join(", ")
+    }
+
+    // Everything that's known, as label and value pairs
+    pub fn details(&self) -> Vec<(&'static str, String)> {
+        let mut details = Vec::new();
+        if let Some(elevated) = self.elevated {
+            details.push(("Elevated", yes_no(elevated)));
+        }
+        if let Some(rid) = self.integrity_level {
+            details.push((
+                "Integrity level",
+                format!("{} (0x{:04X})", integrity_level_name(rid), rid),
+            ));
+        }
+        if let Some(uid) = self.uid {
+            details.push(("UID", uid.to_string()));
+        }
+        if let Some(euid) = self.euid {
+            details.push(("EUID", euid.to_string()));
+        }
+        if let Some(capabilities) = self.capabilities {
+            details.push((
+                "Capabilities",
+                format!(
+                    "{} (0x{:016x})",
+                    capability_names(capabilities),
+                    capabilities
+                ),
+            ));
+        }
+        if let Some(no_new_privs) = self.no_new_privs {
+            details.push(("NoNewPrivs", yes_no(no_new_privs)));
+        }
+        if let Some(mode) = self.seccomp {
+            details.push(("Seccomp", seccomp_name(mode).to_string()));
+        }
+        details
+    }
+
+    // Sorts by elevation first, then by how much it can do
+    pub fn rank(&self) -> (Option<bool>, Option<u32>, u32) {
+        (
+            self.elevated,
+            self.integrity_level,
+            self.capabilities.map_or(0, u64::count_ones),
+        )
+    }
+}
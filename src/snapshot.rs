@@ -0,0 +1,485 @@
+// Text encoding for one refresh tick's worth of samples:
+//
+//     tick processes=<count> cpu=<percent> total_phys=<bytes> ...
+//     process pid=<pid> name=<image name> private_working_set=<bytes> ...
+//     ...followed by <count> process lines in total
+//
+// Each line is a record type followed by key=value pairs. Values are
+// percent-escaped so they never contain whitespace or '='. Readers
+// skip keys they don't know and leave missing ones at their defaults, so new
+// fields can be added without breaking older files.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, Error, ErrorKind, Result, Write},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use crate::{
+    binary::BinaryInfo,
+    process::{IoCounters, MemoryCounters, Process},
+    security::SecurityContext,
+    system::{MemoryStatus, SystemStatus},
+};
+
+pub struct Snapshot {
+    pub processes: Vec<Process>,
+    pub system_status: SystemStatus,
+}
+
+// Escapes everything split_whitespace() splits on, not just ASCII, since
+// names and command lines can contain any character
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '%' || c == '=' || c.is_whitespace() {
+            for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                escaped.push_str(&format!("%{:02X}", byte));
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+fn unescape(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(byte)) => {
+                unescaped.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                unescaped.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+pub struct Record {
+    pub kind: String,
+    fields: HashMap<String, String>,
+}
+
+impl Record {
+    pub fn parse(line: &str) -> Record {
+        let mut words = line.split_whitespace();
+        let kind = words.next().unwrap_or_default().to_string();
+        let fields = words
+            .filter_map(|word| word.split_once('='))
+            .map(|(key, value)| (key.to_string(), unescape(value)))
+            .collect();
+        Record { kind, fields }
+    }
+
+    pub fn get<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+        self.fields.get(key).and_then(|value| value.parse().ok())
+    }
+
+    pub fn get_or_default<T: std::str::FromStr + Default>(&self, key: &str) -> T {
+        self.get(key).unwrap_or_default()
+    }
+}
+
+// None at end of input
+pub fn read_record(reader: &mut impl BufRead) -> Result<Option<Record>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(Record::parse(&line)))
+}
+
+fn write_system_status(out: &mut String, status: &SystemStatus) {
+    out.push_str(&format!(" user={}", escape(&status.user)));
+    if let Some(cpu_usage) = status.cpu_usage {
+        out.push_str(&format!(" cpu={}", cpu_usage));
+    }
+    if let Some(memory) = &status.memory {
+        out.push_str(&format!(
+            " total_phys={} avail_phys={} memory_load={} commit_total={} commit_limit={} \
+             swap_total={} swap_free={}",
+            memory.total,
+            memory.available,
+            memory.load,
+            memory.commit_total,
+            memory.commit_limit,
+            memory.swap_total,
+            memory.swap_free
+        ));
+    }
+}
+
+fn read_system_status(record: &Record) -> SystemStatus {
+    let memory = record.get("total_phys").map(|total: u64| {
+        let available: u64 = record.get_or_default("avail_phys");
+        MemoryStatus {
+            total,
+            available,
+            used: total.saturating_sub(available),
+            load: record.get_or_default("memory_load"),
+            commit_total: record.get_or_default("commit_total"),
+            commit_limit: record.get_or_default("commit_limit"),
+            swap_total: record.get_or_default("swap_total"),
+            swap_free: record.get_or_default("swap_free"),
+        }
+    });
+    SystemStatus {
+        cpu_usage: record.get("cpu"),
+        memory,
+        user: record.get_or_default("user"),
+    }
+}
+
+fn write_process(out: &mut String, process: &Process, epoch: Instant) {
+    let sample_time = process.sample_time.saturating_duration_since(epoch);
+    let sample_wall_time = process
+        .sample_wall_time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    out.push_str(&format!(
+        "process pid={} parent_pid={} name={} image_path={} image_deleted={} command_line={} \
+         user={} real_user={} private_working_set={} threads={} cpu_time={} sample_time={} \
+         sample_wall_time={} io_priority={} scheduling={} container={}",
+        process.pid,
+        process.parent_pid,
+        escape(&process.image_name),
+        escape(&process.image_path),
+        process.image_deleted,
+        escape(&process.command_line),
+        escape(&process.user),
+        escape(&process.real_user),
+        process.private_working_set,
+        process.thread_count,
+        process.cpu_time,
+        sample_time.as_micros(),
+        sample_wall_time.as_micros(),
+        escape(&process.io_priority),
+        escape(&process.scheduling_policy),
+        escape(&process.container)
+    ));
+    if let Some(status) = process.status {
+        out.push_str(&format!(" status={}", status.key()));
+    }
+    if let Some(priority) = process.priority {
+        out.push_str(&format!(" priority={}", priority.key()));
+    }
+    if let Some(nice) = process.nice {
+        out.push_str(&format!(" nice={}", nice));
+    }
+    let memory = &process.memory_counters;
+    for (key, value) in [
+        ("working_set", memory.working_set),
+        ("private_ws", memory.private_working_set),
+        ("peak_working_set", memory.peak_working_set),
+        ("commit", memory.commit),
+        ("virtual_size", memory.virtual_size),
+        ("page_faults", memory.page_faults),
+        ("pss", memory.proportional),
+        ("uss", memory.unique),
+    ] {
+        if let Some(value) = value {
+            out.push_str(&format!(" {}={}", key, value));
+        }
+    }
+    let binary = &process.binary;
+    for (key, value) in [
+        ("architecture", &process.architecture),
+        ("description", &binary.description),
+        ("company", &binary.company),
+        ("version", &binary.version),
+        ("image_type", &binary.image_type),
+        ("image_architecture", &binary.architecture),
+        ("build_id", &binary.build_id),
+        ("interpreter", &binary.interpreter),
+    ] {
+        if !value.is_empty() {
+            out.push_str(&format!(" {}={}", key, escape(value)));
+        }
+    }
+    let security = &process.security;
+    for (key, value) in [
+        ("elevated", security.elevated),
+        ("no_new_privs", security.no_new_privs),
+    ] {
+        if let Some(value) = value {
+            out.push_str(&format!(" {}={}", key, value));
+        }
+    }
+    for (key, value) in [
+        ("integrity_level", security.integrity_level),
+        ("uid", security.uid),
+        ("euid", security.euid),
+        ("seccomp", security.seccomp),
+    ] {
+        if let Some(value) = value {
+            out.push_str(&format!(" {}={}", key, value));
+        }
+    }
+    if let Some(capabilities) = security.capabilities {
+        out.push_str(&format!(" capabilities={}", capabilities));
+    }
+    if let Some(last_cpu) = process.last_cpu {
+        out.push_str(&format!(" last_cpu={}", last_cpu));
+    }
+    if let Some(handle_count) = process.handle_count {
+        out.push_str(&format!(" handles={}", handle_count));
+    }
+    if !process.listening_ports.is_empty() {
+        let ports: Vec<String> = process.listening_ports.iter().map(u16::to_string).collect();
+        out.push_str(&format!(" listening_ports={}", ports.join(",")));
+    }
+    if let Some(start_time) = process
+        .start_time
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+    {
+        out.push_str(&format!(" start_time={}", start_time.as_micros()));
+    }
+    if let Some(io) = process.io {
+        out.push_str(&format!(
+            " io_read={} io_write={}",
+            io.read_bytes, io.write_bytes
+        ));
+    }
+}
+
+fn read_process(record: &Record, epoch: Instant) -> Result<Process> {
+    if record.kind != "process" {
+        return Err(invalid_data(format!(
+            "expected a process record, found '{}'",
+            record.kind
+        )));
+    }
+    let pid = record
+        .get("pid")
+        .ok_or_else(|| invalid_data("process record without a pid".to_string()))?;
+    let sample_time = epoch + Duration::from_micros(record.get_or_default("sample_time"));
+    Ok(Process {
+        pid,
+        parent_pid: record.get_or_default("parent_pid"),
+        image_name: record.get_or_default("name"),
+        image_path: record.get_or_default("image_path"),
+        image_deleted: record.get_or_default("image_deleted"),
+        command_line: record.get_or_default("command_line"),
+        user: record.get_or_default("user"),
+        real_user: record.get_or_default("real_user"),
+        private_working_set: record.get_or_default("private_working_set"),
+        memory_counters: MemoryCounters {
+            working_set: record.get("working_set"),
+            private_working_set: record.get("private_ws"),
+            peak_working_set: record.get("peak_working_set"),
+            commit: record.get("commit"),
+            virtual_size: record.get("virtual_size"),
+            page_faults: record.get("page_faults"),
+            proportional: record.get("pss"),
+            unique: record.get("uss"),
+        },
+        thread_count: record.get_or_default("threads"),
+        handle_count: record.get("handles"),
+        start_time: record
+            .get("start_time")
+            .map(|micros| UNIX_EPOCH + Duration::from_micros(micros)),
+        cpu_time: record.get_or_default("cpu_time"),
+        sample_time,
+        sample_wall_time: UNIX_EPOCH
+            + Duration::from_micros(record.get_or_default("sample_wall_time")),
+        status: record.get("status"),
+        status_since: sample_time,
+        cpu_usage: 0,
+        last_cpu: record.get("last_cpu"),
+        io: record.get("io_read").map(|read_bytes| IoCounters {
+            read_bytes,
+            write_bytes: record.get_or_default("io_write"),
+        }),
+        io_read_rate: 0,
+        io_write_rate: 0,
+        priority: record.get("priority"),
+        nice: record.get("nice"),
+        io_priority: record.get_or_default("io_priority"),
+        scheduling_policy: record.get_or_default("scheduling"),
+        listening_ports: record
+            .get::<String>("listening_ports")
+            .map(|ports| {
+                ports
+                    .split(',')
+                    .filter_map(|port| port.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        container: record.get_or_default("container"),
+        architecture: record.get_or_default("architecture"),
+        security: SecurityContext {
+            elevated: record.get("elevated"),
+            integrity_level: record.get("integrity_level"),
+            uid: record.get("uid"),
+            euid: record.get("euid"),
+            capabilities: record.get("capabilities"),
+            no_new_privs: record.get("no_new_privs"),
+            seccomp: record.get("seccomp"),
+        },
+        binary: BinaryInfo {
+            description: record.get_or_default("description"),
+            company: record.get_or_default("company"),
+            version: record.get_or_default("version"),
+            image_type: record.get_or_default("image_type"),
+            architecture: record.get_or_default("image_architecture"),
+            build_id: record.get_or_default("build_id"),
+            interpreter: record.get_or_default("interpreter"),
+        },
+    })
+}
+
+// Sample times are written relative to `epoch`, which the reader replaces
+// with its own.
+pub fn write_snapshot<'a>(
+    out: &mut impl Write,
+    epoch: Instant,
+    processes: impl ExactSizeIterator<Item = &'a Process>,
+    system_status: &SystemStatus,
+) -> Result<()> {
+    let mut text = format!("tick processes={}", processes.len());
+    write_system_status(&mut text, system_status);
+    text.push('\n');
+    for process in processes {
+        write_process(&mut text, process, epoch);
+        text.push('\n');
+    }
+    out.write_all(text.as_bytes())
+}
+
+// Reads the process lines that follow a tick record
+pub fn read_tick(reader: &mut impl BufRead, tick: &Record, epoch: Instant) -> Result<Snapshot> {
+    if tick.kind != "tick" {
+        return Err(invalid_data(format!(
+            "expected a tick record, found '{}'",
+            tick.kind
+        )));
+    }
+
+    let count: usize = tick.get_or_default("processes");
+    let mut processes = Vec::with_capacity(count);
+    for _ in 0..count {
+        let record =
+            read_record(reader)?.ok_or_else(|| invalid_data("tick ended early".to_string()))?;
+        processes.push(read_process(&record, epoch)?);
+    }
+
+    Ok(Snapshot {
+        processes,
+        system_status: read_system_status(tick),
+    })
+}
+
+// None at a clean end of input
+pub fn read_snapshot(reader: &mut impl BufRead, epoch: Instant) -> Result<Option<Snapshot>> {
+    let Some(tick) = read_record(reader)? else {
+        return Ok(None);
+    };
+    read_tick(reader, &tick, epoch).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::process::tests::example_process;
+
+    fn example_status() -> SystemStatus {
+        SystemStatus {
+            cpu_usage: Some(12.5),
+            memory: Some(MemoryStatus {
+                total: 16 << 30,
+                available: 4 << 30,
+                used: 12 << 30,
+                load: 75,
+                commit_total: 20 << 30,
+                commit_limit: 32 << 30,
+                swap_total: 8 << 30,
+                swap_free: 6 << 30,
+            }),
+            user: "EXAMPLE\\someone".to_string(),
+        }
+    }
+
+    fn encode(processes: &[Process], system_status: &SystemStatus, epoch: Instant) -> String {
+        let mut out = Vec::new();
+        write_snapshot(&mut out, epoch, processes.iter(), system_status).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn escape_round_trip() {
+        let value = "a b=c%d\te\nf\rg\u{3000}h\x0Ci\u{a0}j\u{2028}k\u{85}l\x0Bm";
+        let escaped = escape(value);
+        assert!(!escaped.contains(|c: char| c == '=' || c.is_whitespace()));
+        assert_eq!(unescape(&escaped), value);
+    }
+
+    // Decoding and encoding again gives back the same text, so every field
+    // that's written is also read
+    #[test]
+    fn snapshot_round_trip() {
+        let epoch = Instant::now();
+        let mut later = example_process(2);
+        later.image_name = "ideographic\u{3000}space\x0C.exe".to_string();
+        later.sample_time = epoch + Duration::from_millis(1500);
+        let processes = vec![example_process(1), later];
+        let text = encode(&processes, &example_status(), epoch);
+
+        let reader_epoch = Instant::now();
+        let snapshot = read_snapshot(&mut Cursor::new(&text), reader_epoch)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            encode(&snapshot.processes, &snapshot.system_status, reader_epoch),
+            text
+        );
+
+        assert_eq!(snapshot.processes[0].image_name, processes[0].image_name);
+        assert_eq!(snapshot.processes[1].image_name, processes[1].image_name);
+        assert_eq!(
+            snapshot.processes[1].sample_time - reader_epoch,
+            Duration::from_millis(1500)
+        );
+    }
+
+    #[test]
+    fn unknown_keys_and_missing_fields() {
+        let text = "tick processes=1 cpu=50 from_the_future=1\nprocess pid=7 also_new=x\n";
+        let snapshot = read_snapshot(&mut Cursor::new(text), Instant::now())
+            .unwrap()
+            .unwrap();
+        assert_eq!(snapshot.system_status.cpu_usage, Some(50.0));
+        assert!(snapshot.system_status.memory.is_none());
+        let process = &snapshot.processes[0];
+        assert_eq!(process.pid, 7);
+        assert_eq!(process.image_name, "");
+    }
+
+    #[test]
+    fn truncated_tick() {
+        let text = "tick processes=2\nprocess pid=1\n";
+        let result = read_snapshot(&mut Cursor::new(text), Instant::now());
+        assert_eq!(result.err().unwrap().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn end_of_input() {
+        let result = read_snapshot(&mut Cursor::new(""), Instant::now());
+        assert!(result.unwrap().is_none());
+    }
+}
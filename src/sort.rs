@@ -0,0 +1,194 @@
+use std::cmp::{Ordering, Reverse};
+
+#[cfg(windows)]
+use widestring::U16CString;
+#[cfg(windows)]
+use windows::Win32::Globalization::{
+    CompareStringEx, COMPARE_STRING_FLAGS, CSTR_EQUAL, CSTR_GREATER_THAN, CSTR_LESS_THAN,
+    LOCALE_NAME_SYSTEM_DEFAULT,
+};
+
+use crate::{
+    process::{self, Process},
+    rows::Row,
+};
+
+#[derive(Clone, Copy)]
+pub enum SortState {
+    SortUp(SortKey),
+    SortDown(SortKey),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Name,
+    Pid,
+    Cpu,
+    Memory,
+    WorkingSet,
+    PrivateWorkingSet,
+    PeakWorkingSet,
+    Commit,
+    VirtualSize,
+    PageFaults,
+    Threads,
+    Handles,
+    IoRead,
+    IoWrite,
+    IoTotal,
+    ListeningPorts,
+    StartTime,
+    RunningFor,
+    Status,
+    Priority,
+    IoPriority,
+    SchedulingPolicy,
+    LastCpu,
+    User,
+    Container,
+    Architecture,
+    Security,
+    Description,
+    Company,
+    Version,
+    ImageType,
+    BuildId,
+    Interpreter,
+    ImagePath,
+    CommandLine,
+}
+
+impl SortState {
+    // What clicking a column header does: sort by that column, flipping the
+    // direction each time.
+    pub fn toggled(self, sort_key: SortKey) -> SortState {
+        match self {
+            SortState::SortUp(_) => SortState::SortDown(sort_key),
+            SortState::SortDown(_) => SortState::SortUp(sort_key),
+        }
+    }
+
+    // The same direction by another column
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub fn with_key(self, sort_key: SortKey) -> SortState {
+        match self {
+            SortState::SortUp(_) => SortState::SortUp(sort_key),
+            SortState::SortDown(_) => SortState::SortDown(sort_key),
+        }
+    }
+
+    pub fn key(self) -> SortKey {
+        match self {
+            SortState::SortUp(sort_key) | SortState::SortDown(sort_key) => sort_key,
+        }
+    }
+}
+
+#[cfg(windows)]
+fn lexical_str_cmp(a: &str, b: &str) -> Ordering {
+    let a = U16CString::from_str_truncate(a);
+    let b = U16CString::from_str_truncate(b);
+    let result = unsafe {
+        CompareStringEx(
+            LOCALE_NAME_SYSTEM_DEFAULT,
+            COMPARE_STRING_FLAGS(0),
+            a.as_slice(),
+            b.as_slice(),
+            None,
+            None,
+            None,
+        )
+    };
+    match result {
+        CSTR_LESS_THAN => Ordering::Less,
+        CSTR_EQUAL => Ordering::Equal,
+        CSTR_GREATER_THAN => Ordering::Greater,
+        _ => unreachable!(),
+    }
+}
+
+// Approximates the case-insensitive ordering CompareStringEx gives on Windows
+#[cfg(not(windows))]
+fn lexical_str_cmp(a: &str, b: &str) -> Ordering {
+    a.to_lowercase()
+        .cmp(&b.to_lowercase())
+        .then_with(|| a.cmp(b))
+}
+
+fn io_total(process: &Process) -> Option<u64> {
+    process.io.map(|io| io.read_bytes + io.write_bytes)
+}
+
+// Dotted versions compare part by part as numbers, so 10.0 sorts after 9.1
+fn version_cmp(a: &str, b: &str) -> Ordering {
+    let parts = |version: &str| -> Vec<u64> {
+        version
+            .split('.')
+            .map(|part| part.trim().parse().unwrap_or(0))
+            .collect()
+    };
+    parts(a).cmp(&parts(b)).then_with(|| lexical_str_cmp(a, b))
+}
+
+fn compare_by_key(a: &Row, b: &Row, sort_key: SortKey) -> Ordering {
+    let (pa, pb) = (&a.process, &b.process);
+    let (memory_a, memory_b) = (&pa.memory_counters, &pb.memory_counters);
+    let ordering = match sort_key {
+        SortKey::Name => lexical_str_cmp(&pa.image_name, &pb.image_name),
+        SortKey::Pid => Ordering::Equal,
+        SortKey::Cpu => a.cpu_usage.cmp(&b.cpu_usage),
+        SortKey::Memory => a.memory.cmp(&b.memory),
+        SortKey::WorkingSet => memory_a.working_set.cmp(&memory_b.working_set),
+        SortKey::PrivateWorkingSet => memory_a
+            .private_working_set
+            .cmp(&memory_b.private_working_set),
+        SortKey::PeakWorkingSet => memory_a.peak_working_set.cmp(&memory_b.peak_working_set),
+        SortKey::Commit => memory_a.commit.cmp(&memory_b.commit),
+        SortKey::VirtualSize => memory_a.virtual_size.cmp(&memory_b.virtual_size),
+        SortKey::PageFaults => memory_a.page_faults.cmp(&memory_b.page_faults),
+        SortKey::Threads => pa.thread_count.cmp(&pb.thread_count),
+        SortKey::Handles => pa.handle_count.cmp(&pb.handle_count),
+        SortKey::IoRead => pa.io_read_rate.cmp(&pb.io_read_rate),
+        SortKey::IoWrite => pa.io_write_rate.cmp(&pb.io_write_rate),
+        SortKey::IoTotal => io_total(pa).cmp(&io_total(pb)),
+        SortKey::ListeningPorts => pa.listening_ports.cmp(&pb.listening_ports),
+        SortKey::StartTime => pa.start_time.cmp(&pb.start_time),
+        // The earlier it started, the longer it has been running
+        SortKey::RunningFor => pa.start_time.map(Reverse).cmp(&pb.start_time.map(Reverse)),
+        // Among processes stuck the same way, the longest stuck is last
+        SortKey::Status => (pa.status, process::status_duration(pa))
+            .cmp(&(pb.status, process::status_duration(pb))),
+        // Within a priority class, a higher nice value is a lower priority
+        SortKey::Priority => {
+            (pa.priority, pa.nice.map(Reverse)).cmp(&(pb.priority, pb.nice.map(Reverse)))
+        }
+        SortKey::IoPriority => lexical_str_cmp(&pa.io_priority, &pb.io_priority),
+        SortKey::SchedulingPolicy => lexical_str_cmp(&pa.scheduling_policy, &pb.scheduling_policy),
+        SortKey::LastCpu => pa.last_cpu.cmp(&pb.last_cpu),
+        SortKey::User => lexical_str_cmp(&pa.user, &pb.user),
+        SortKey::Container => lexical_str_cmp(&pa.container, &pb.container),
+        SortKey::Architecture => lexical_str_cmp(&pa.architecture, &pb.architecture),
+        SortKey::Security => pa.security.rank().cmp(&pb.security.rank()),
+        SortKey::Description => lexical_str_cmp(&pa.binary.description, &pb.binary.description),
+        SortKey::Company => lexical_str_cmp(&pa.binary.company, &pb.binary.company),
+        SortKey::Version => version_cmp(&pa.binary.version, &pb.binary.version),
+        SortKey::ImageType => lexical_str_cmp(&pa.binary.image_type, &pb.binary.image_type),
+        SortKey::BuildId => pa.binary.build_id.cmp(&pb.binary.build_id),
+        SortKey::Interpreter => lexical_str_cmp(&pa.binary.interpreter, &pb.binary.interpreter),
+        SortKey::ImagePath => lexical_str_cmp(&pa.image_path, &pb.image_path),
+        SortKey::CommandLine => lexical_str_cmp(&pa.command_line, &pb.command_line),
+    };
+    ordering.then_with(|| pa.pid.cmp(&pb.pid))
+}
+
+// Ties are broken by PID, so no two rows compare equal
+pub fn compare_rows(a: &Row, b: &Row, sort_state: SortState) -> Ordering {
+    match sort_state {
+        SortState::SortUp(sort_key) => compare_by_key(a, b, sort_key),
+        SortState::SortDown(sort_key) => compare_by_key(b, a, sort_key),
+    }
+}
+
+pub fn sort_rows(rows: &mut [Row], sort_state: SortState) {
+    rows.sort_by(|a, b| compare_rows(a, b, sort_state));
+}
@@ -1,38 +1,42 @@
-use std::rc::Rc;
+use std::{cell::RefCell, rc::Rc};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use windows::Win32::{
-    System::Performance::{PDH_HCOUNTER, PDH_HQUERY},
+    Foundation::HWND,
     UI::WindowsAndMessaging::{GetWindowLongPtrW, SetWindowLongPtrW, GWLP_USERDATA},
 };
 
-use crate::{process::Process, HWND};
-
-#[derive(Clone, Copy)]
-pub enum SortState {
-    SortUp(SortKey),
-    SortDown(SortKey),
-}
-
-#[derive(Clone, Copy)]
-pub enum SortKey {
-    Name,
-    Pid,
-    Cpu,
-    Memory,
-}
+use crate::{
+    column::{self, Column},
+    process::{MemoryMode, Process, ProcessSource},
+    recording::Recorder,
+    rows::{Filter, Row},
+    sort::{SortKey, SortState},
+    system::CpuSampler,
+};
 
 #[derive(Clone)]
 pub struct TaskManagerState {
     pub task_list: HWND,
     pub status_bar: HWND,
+    pub search_box: HWND,
     pub num_cpus: u32,
     pub sort_state: SortState,
+    // The task list's columns, in display order
+    pub columns: Vec<&'static Column>,
+    pub tree_mode: bool,
+    // PIDs whose children are hidden in tree mode
+    pub collapsed: HashSet<u32>,
+    pub filter: Filter,
+    pub memory_mode: MemoryMode,
+
+    pub cpu_sampler: Rc<RefCell<CpuSampler>>,
 
-    pub pdh_query: PDH_HQUERY,
-    pub pdh_cpu_usage_counter: PDH_HCOUNTER,
+    pub source: Rc<RefCell<Box<dyn ProcessSource>>>,
+    pub recorder: Option<Rc<RefCell<Recorder>>>,
 
-    pub processes: Vec<Rc<Process>>,
+    // What the task list shows, in order
+    pub rows: Vec<Row>,
     pub pid_map: HashMap<u32, Rc<Process>>,
 }
 
@@ -55,17 +59,24 @@ where
 
 pub unsafe fn update_processes(
     hwnd: HWND,
-    new_processes: Vec<Rc<Process>>,
+    new_rows: Vec<Row>,
     new_pid_map: HashMap<u32, Rc<Process>>,
 ) {
     update(hwnd, |old| TaskManagerState {
         task_list: old.task_list,
         status_bar: old.status_bar,
+        search_box: old.search_box,
         num_cpus: old.num_cpus,
         sort_state: old.sort_state,
-        pdh_query: old.pdh_query,
-        pdh_cpu_usage_counter: old.pdh_cpu_usage_counter,
-        processes: new_processes,
+        columns: old.columns.clone(),
+        tree_mode: old.tree_mode,
+        collapsed: old.collapsed.clone(),
+        filter: old.filter.clone(),
+        memory_mode: old.memory_mode,
+        cpu_sampler: old.cpu_sampler.clone(),
+        source: old.source.clone(),
+        recorder: old.recorder.clone(),
+        rows: new_rows,
         pid_map: new_pid_map,
     });
 }
@@ -74,11 +85,118 @@ pub unsafe fn set_sort_state(hwnd: HWND, new_sort: SortState) {
     update(hwnd, |old| TaskManagerState {
         task_list: old.task_list,
         status_bar: old.status_bar,
+        search_box: old.search_box,
         num_cpus: old.num_cpus,
         sort_state: new_sort,
-        pdh_query: old.pdh_query,
-        pdh_cpu_usage_counter: old.pdh_cpu_usage_counter,
-        processes: old.processes.clone(),
+        columns: old.columns.clone(),
+        tree_mode: old.tree_mode,
+        collapsed: old.collapsed.clone(),
+        filter: old.filter.clone(),
+        memory_mode: old.memory_mode,
+        cpu_sampler: old.cpu_sampler.clone(),
+        source: old.source.clone(),
+        recorder: old.recorder.clone(),
+        rows: old.rows.clone(),
+        pid_map: old.pid_map.clone(),
+    });
+}
+
+pub unsafe fn set_columns(hwnd: HWND, columns: Vec<&'static Column>) {
+    update(hwnd, |old| TaskManagerState {
+        task_list: old.task_list,
+        status_bar: old.status_bar,
+        search_box: old.search_box,
+        num_cpus: old.num_cpus,
+        sort_state: old.sort_state,
+        columns,
+        tree_mode: old.tree_mode,
+        collapsed: old.collapsed.clone(),
+        filter: old.filter.clone(),
+        memory_mode: old.memory_mode,
+        cpu_sampler: old.cpu_sampler.clone(),
+        source: old.source.clone(),
+        recorder: old.recorder.clone(),
+        rows: old.rows.clone(),
+        pid_map: old.pid_map.clone(),
+    });
+}
+
+pub unsafe fn set_tree_mode(hwnd: HWND, tree_mode: bool, collapsed: HashSet<u32>) {
+    update(hwnd, |old| TaskManagerState {
+        task_list: old.task_list,
+        status_bar: old.status_bar,
+        search_box: old.search_box,
+        num_cpus: old.num_cpus,
+        sort_state: old.sort_state,
+        columns: old.columns.clone(),
+        tree_mode,
+        collapsed,
+        filter: old.filter.clone(),
+        memory_mode: old.memory_mode,
+        cpu_sampler: old.cpu_sampler.clone(),
+        source: old.source.clone(),
+        recorder: old.recorder.clone(),
+        rows: old.rows.clone(),
+        pid_map: old.pid_map.clone(),
+    });
+}
+
+pub unsafe fn set_filter(hwnd: HWND, filter: Filter) {
+    update(hwnd, |old| TaskManagerState {
+        task_list: old.task_list,
+        status_bar: old.status_bar,
+        search_box: old.search_box,
+        num_cpus: old.num_cpus,
+        sort_state: old.sort_state,
+        columns: old.columns.clone(),
+        tree_mode: old.tree_mode,
+        collapsed: old.collapsed.clone(),
+        filter,
+        memory_mode: old.memory_mode,
+        cpu_sampler: old.cpu_sampler.clone(),
+        source: old.source.clone(),
+        recorder: old.recorder.clone(),
+        rows: old.rows.clone(),
+        pid_map: old.pid_map.clone(),
+    });
+}
+
+pub unsafe fn set_memory_mode(hwnd: HWND, memory_mode: MemoryMode) {
+    update(hwnd, |old| TaskManagerState {
+        task_list: old.task_list,
+        status_bar: old.status_bar,
+        search_box: old.search_box,
+        num_cpus: old.num_cpus,
+        sort_state: old.sort_state,
+        columns: old.columns.clone(),
+        tree_mode: old.tree_mode,
+        collapsed: old.collapsed.clone(),
+        filter: old.filter.clone(),
+        memory_mode,
+        cpu_sampler: old.cpu_sampler.clone(),
+        source: old.source.clone(),
+        recorder: old.recorder.clone(),
+        rows: old.rows.clone(),
+        pid_map: old.pid_map.clone(),
+    });
+}
+
+pub unsafe fn stop_recording(hwnd: HWND) {
+    update(hwnd, |old| TaskManagerState {
+        task_list: old.task_list,
+        status_bar: old.status_bar,
+        search_box: old.search_box,
+        num_cpus: old.num_cpus,
+        sort_state: old.sort_state,
+        columns: old.columns.clone(),
+        tree_mode: old.tree_mode,
+        collapsed: old.collapsed.clone(),
+        filter: old.filter.clone(),
+        memory_mode: old.memory_mode,
+        cpu_sampler: old.cpu_sampler.clone(),
+        source: old.source.clone(),
+        recorder: None,
+        rows: old.rows.clone(),
         pid_map: old.pid_map.clone(),
     });
 }
@@ -87,18 +205,26 @@ pub unsafe fn initialize(
     hwnd: HWND,
     task_list_hwnd: HWND,
     status_bar_hwnd: HWND,
+    search_box_hwnd: HWND,
     num_cpus: u32,
-    query: PDH_HQUERY,
-    counter: PDH_HCOUNTER,
+    source: Rc<RefCell<Box<dyn ProcessSource>>>,
+    recorder: Option<Rc<RefCell<Recorder>>>,
 ) {
     let state = TaskManagerState {
         task_list: task_list_hwnd,
         status_bar: status_bar_hwnd,
+        search_box: search_box_hwnd,
         num_cpus,
         sort_state: SortState::SortUp(SortKey::Name),
-        pdh_query: query,
-        pdh_cpu_usage_counter: counter,
-        processes: Vec::new(),
+        columns: column::default_columns(),
+        tree_mode: false,
+        collapsed: HashSet::new(),
+        filter: Filter::default(),
+        memory_mode: MemoryMode::default(),
+        cpu_sampler: Rc::new(RefCell::new(CpuSampler::new())),
+        source,
+        recorder,
+        rows: Vec::new(),
         pid_map: HashMap::new(),
     };
 
@@ -109,9 +235,7 @@ pub unsafe fn initialize(
 pub unsafe fn destroy(hwnd: HWND) {
     let state_ptr = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut TaskManagerState;
     if !state_ptr.is_null() {
-        let state = Box::from_raw(state_ptr);
-        crate::system::end_query_data_collection(state.pdh_query);
-        drop(state);
+        drop(Box::from_raw(state_ptr));
         SetWindowLongPtrW(hwnd, GWLP_USERDATA, 0);
     }
 }
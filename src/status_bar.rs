@@ -1,4 +1,3 @@
-use human_bytes::human_bytes;
 use std::ffi::c_void;
 use windows::{
     core::{w, Result},
@@ -14,10 +13,13 @@ use windows::{
     },
 };
 
-const STATUS_BAR_NUM_PARTS: usize = 3;
+use crate::{rows, state::TaskManagerState, status_text, system::SystemStatus};
+
+const STATUS_BAR_NUM_PARTS: usize = 4;
 const STATUS_BAR_PART_PROCESS_COUNT: usize = 0;
 const STATUS_BAR_PART_CPU_USAGE: usize = 1;
 const STATUS_BAR_PART_PROCESS_MEMORY: usize = 2;
+const STATUS_BAR_PART_MEMORY_TOTAL: usize = 3;
 
 pub fn create_control(instance: &HINSTANCE, parent: HWND) -> Result<HWND> {
     let window_style = WINDOW_STYLE(SBARS_SIZEGRIP) | WS_CHILD | WS_VISIBLE;
@@ -60,44 +62,50 @@ pub fn create_control(instance: &HINSTANCE, parent: HWND) -> Result<HWND> {
     }
 }
 
-fn build_memory_status_string() -> Result<String> {
-    let mem_status = crate::system::get_memory_status()?;
-    let mem_in_use = mem_status.ullTotalPhys - mem_status.ullAvailPhys;
-    Ok(format!(
-        "Memory Usage: {}/{} ({}%)",
-        human_bytes(mem_in_use as f64),
-        human_bytes(mem_status.ullTotalPhys as f64),
-        mem_status.dwMemoryLoad
-    ))
+// The replayed figures when the task list shows a recording, otherwise this
+// machine's own
+pub fn get_system_status(state: &TaskManagerState) -> SystemStatus {
+    if let Some(system_status) = state.source.borrow().system_status() {
+        return system_status;
+    }
+    crate::system::get_system_status(&mut state.cpu_sampler.borrow_mut())
 }
 
-pub fn update(main_window: HWND) {
+pub fn update(main_window: HWND, system_status: &SystemStatus) {
     let state = unsafe { crate::state::get(main_window) };
     set_text(
         state.status_bar,
         STATUS_BAR_PART_PROCESS_COUNT,
-        &format!("Processes: {}", state.processes.len()),
+        &status_text::process_count(state.pid_map.len()),
+    );
+    set_text(
+        state.status_bar,
+        STATUS_BAR_PART_CPU_USAGE,
+        &status_text::cpu_usage(system_status),
     );
-
-    let cpu_usage_str =
-        if let Ok(cpu_usage) = crate::system::get_cpu_usage(state.pdh_cpu_usage_counter) {
-            &format!("CPU Usage: {}%", cpu_usage as i32)
-        } else {
-            "CPU usage unavailable"
-        };
-
-    set_text(state.status_bar, STATUS_BAR_PART_CPU_USAGE, cpu_usage_str);
-
-    let mem_status_string = build_memory_status_string().unwrap_or_default();
     set_text(
         state.status_bar,
         STATUS_BAR_PART_PROCESS_MEMORY,
-        &mem_status_string,
+        &status_text::memory_usage(system_status),
+    );
+    set_text(
+        state.status_bar,
+        STATUS_BAR_PART_MEMORY_TOTAL,
+        &status_text::process_memory(
+            state.memory_mode,
+            rows::memory_total(&state.pid_map, state.memory_mode),
+        ),
     );
 }
 
+// Shown in place of the process count until the next successful refresh
+pub fn show_message(main_window: HWND, message: &str) {
+    let state = unsafe { crate::state::get(main_window) };
+    set_text(state.status_bar, STATUS_BAR_PART_PROCESS_COUNT, message);
+}
+
 fn set_text(status_bar: HWND, part: usize, text: &str) {
-    let wide_text = widestring::U16CString::from_str(text).unwrap();
+    let wide_text = widestring::U16CString::from_str_truncate(text);
     unsafe {
         SendMessageW(
             status_bar,
@@ -0,0 +1,73 @@
+use human_bytes::human_bytes;
+
+use crate::{
+    process::MemoryMode,
+    rows::UserTotal,
+    security::SecurityContext,
+    system::{MemoryStatus, SystemStatus},
+};
+
+pub fn process_count(count: usize) -> String {
+    format!("Processes: {}", count)
+}
+
+pub fn cpu_usage(status: &SystemStatus) -> String {
+    if let Some(cpu_usage) = status.cpu_usage {
+        format!("CPU Usage: {}%", cpu_usage as i32)
+    } else {
+        "CPU usage unavailable".to_string()
+    }
+}
+
+fn memory_status(mem_status: &MemoryStatus) -> String {
+    format!(
+        "Memory Usage: {}/{} ({}%)",
+        human_bytes(mem_status.used as f64),
+        human_bytes(mem_status.total as f64),
+        mem_status.load
+    )
+}
+
+pub fn memory_usage(status: &SystemStatus) -> String {
+    status
+        .memory
+        .as_ref()
+        .map(memory_status)
+        .unwrap_or_default()
+}
+
+// What the processes' Memory column adds up to. PSS is the one mode where
+// that's a fair share of the machine's memory.
+pub fn process_memory(memory_mode: MemoryMode, total: usize) -> String {
+    format!(
+        "Processes ({}): {}",
+        memory_mode.name(),
+        human_bytes(total as f64)
+    )
+}
+
+// One line of the window's user totals
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn user_total(total: &UserTotal) -> String {
+    format!(
+        "{}: {} processes, CPU {}%, Memory {}",
+        total.user,
+        total.processes,
+        total.cpu_usage,
+        human_bytes(total.memory as f64)
+    )
+}
+
+// The security details of a process, one "Label: value" per item, joined by
+// `separator`
+pub fn security_details(security: &SecurityContext, separator: &str) -> String {
+    let details = security.details();
+    if details.is_empty() {
+        return "no security details available".to_string();
+    }
+    details
+        .iter()
+        .map(|(label, value)| format!("{}: {}", label, value))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
@@ -1,85 +1,111 @@
-use windows::{
-    core::{w, Error, Result, PCWSTR},
-    Win32::{
-        Foundation::{ERROR_SUCCESS, WIN32_ERROR},
-        System::{
-            Performance::{
-                PdhAddCounterW, PdhCloseQuery, PdhCollectQueryData, PdhGetFormattedCounterValue,
-                PdhOpenQueryW, PDH_FMT_COUNTERVALUE, PDH_FMT_DOUBLE, PDH_HCOUNTER, PDH_HQUERY,
-            },
-            SystemInformation::{GlobalMemoryStatusEx, MEMORYSTATUSEX},
-        },
-    },
-};
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(windows)]
+mod win32;
 
-pub fn get_memory_status() -> Result<MEMORYSTATUSEX> {
-    let mut memory_status = MEMORYSTATUSEX {
-        dwLength: size_of::<MEMORYSTATUSEX>() as u32,
-        ..Default::default()
-    };
-    unsafe { GlobalMemoryStatusEx(&mut memory_status)? }
-    Ok(memory_status)
+#[cfg(target_os = "linux")]
+pub use linux::{get_cpu_times, get_memory_status, get_num_cpus, local_time};
+#[cfg(windows)]
+pub use win32::{get_cpu_times, get_memory_status, get_num_cpus, local_time};
+
+// Physical memory, commit charge and swap, in bytes
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStatus {
+    pub total: u64,
+    pub available: u64,
+    pub used: u64,
+    // Percentage of physical memory in use
+    pub load: u32,
+    pub commit_total: u64,
+    pub commit_limit: u64,
+    pub swap_total: u64,
+    pub swap_free: u64,
 }
 
-pub fn start_query_data_collection() -> Result<(PDH_HQUERY, PDH_HCOUNTER)> {
-    let mut query = PDH_HQUERY::default();
-    let status = unsafe { WIN32_ERROR(PdhOpenQueryW(PCWSTR(std::ptr::null()), 0, &mut query)) };
-    if status != ERROR_SUCCESS {
-        let err = Error::from_thread();
-        eprintln!("failed to open pdhquery: {}", err);
-        return Err(err);
-    }
+// System-wide figures that go alongside a process sample. Anything the
+// machine couldn't report is None.
+#[derive(Debug, Clone, Default)]
+pub struct SystemStatus {
+    pub cpu_usage: Option<f64>,
+    pub memory: Option<MemoryStatus>,
+    // Who taskmanager samples the machine as, which is whose processes
+    // "only my processes" shows
+    pub user: String,
+}
 
-    let mut counter = PDH_HCOUNTER::default();
-    let status = unsafe {
-        WIN32_ERROR(PdhAddCounterW(
-            query,
-            w!("\\Processor(_Total)\\% Processor Time"),
-            0,
-            &mut counter,
-        ))
-    };
-    if status != ERROR_SUCCESS {
-        let err = Error::from_thread();
-        eprintln!("failed to add counter: {}", err);
-        return Err(err);
-    }
+// A point in time on this machine's clock, in its time zone
+pub struct LocalTime {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
 
-    collect_query_data(query)?;
-    Ok((query, counter))
+// Time all processors together have spent idle and in total since boot, in
+// whatever unit the OS counts in
+#[derive(Debug, Clone, Copy)]
+pub struct CpuTimes {
+    pub idle: u64,
+    pub total: u64,
 }
 
-pub fn collect_query_data(query: PDH_HQUERY) -> Result<()> {
-    let status = unsafe { WIN32_ERROR(PdhCollectQueryData(query)) };
-    if status != ERROR_SUCCESS {
-        let err = Error::from_thread();
-        eprintln!("failed to collect query data: {}", err);
-        return Err(err);
+// Busy percentage between two readings, None if no time has passed
+pub fn get_cpu_usage(sample1: CpuTimes, sample2: CpuTimes) -> Option<f64> {
+    let total = sample2.total.checked_sub(sample1.total)?;
+    let idle = sample2.idle.saturating_sub(sample1.idle).min(total);
+    if total == 0 {
+        return None;
     }
-    Ok(())
+    Some((total - idle) as f64 / total as f64 * 100.0)
 }
 
-pub fn get_cpu_usage(counter: PDH_HCOUNTER) -> Result<f64> {
-    let mut value = PDH_FMT_COUNTERVALUE::default();
-    let status = unsafe {
-        WIN32_ERROR(PdhGetFormattedCounterValue(
-            counter,
-            PDH_FMT_DOUBLE,
-            None,
-            &mut value,
-        ))
-    };
-    if status != ERROR_SUCCESS {
-        let err = Error::from_thread();
-        eprintln!("failed to get formatted counter value: {}", err);
-        return Err(err);
+// Turns successive CPU time readings into a utilization percentage
+pub struct CpuSampler {
+    last_times: Option<CpuTimes>,
+    last_usage: Option<f64>,
+}
+
+impl CpuSampler {
+    pub fn new() -> CpuSampler {
+        CpuSampler {
+            last_times: get_cpu_times().ok(),
+            last_usage: None,
+        }
     }
 
-    unsafe { Ok(value.Anonymous.doubleValue) }
+    // Utilization since the previous call, or since the sampler was created.
+    // Calls in quick succession repeat the last figure rather than dividing
+    // by no time at all.
+    pub fn sample(&mut self) -> Option<f64> {
+        let Ok(times) = get_cpu_times() else {
+            return None;
+        };
+        if let Some(usage) = self
+            .last_times
+            .and_then(|last_times| get_cpu_usage(last_times, times))
+        {
+            self.last_times = Some(times);
+            self.last_usage = Some(usage);
+        } else if self.last_times.is_none() {
+            self.last_times = Some(times);
+        }
+        self.last_usage
+    }
+}
+
+impl Default for CpuSampler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-pub fn end_query_data_collection(query: PDH_HQUERY) {
-    if !query.is_invalid() {
-        unsafe { PdhCloseQuery(query) };
+// This machine's figures for the refresh tick that just happened
+pub fn get_system_status(cpu_sampler: &mut CpuSampler) -> SystemStatus {
+    SystemStatus {
+        cpu_usage: cpu_sampler.sample(),
+        memory: get_memory_status().ok(),
+        user: crate::process::current_user().to_string(),
     }
 }
@@ -0,0 +1,198 @@
+use std::{
+    fs,
+    io::{Error, ErrorKind, Result},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{CpuTimes, LocalTime, MemoryStatus};
+
+mod cgroup;
+
+pub fn get_num_cpus() -> u32 {
+    let num_cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    if num_cpus > 0 {
+        num_cpus as u32
+    } else {
+        1
+    }
+}
+
+// Reads the aggregate "cpu" line of /proc/stat, which counts clock ticks
+// spent in user, nice, system, idle, iowait, irq, softirq and steal time.
+// The guest columns after those are already included in user and nice.
+fn parse_cpu_times(stat: &str) -> Option<CpuTimes> {
+    let line = stat.lines().find(|line| line.starts_with("cpu "))?;
+    let ticks: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .take(8)
+        .map(|field| field.parse().ok())
+        .collect::<Option<_>>()?;
+    if ticks.len() < 4 {
+        return None;
+    }
+
+    let iowait = ticks.get(4).copied().unwrap_or(0);
+    Some(CpuTimes {
+        idle: ticks[3] + iowait,
+        total: ticks.iter().sum(),
+    })
+}
+
+pub fn get_cpu_times() -> Result<CpuTimes> {
+    if let Some(times) = cgroup::get_cpu_times(get_num_cpus()) {
+        return Ok(times);
+    }
+    let stat = fs::read_to_string("/proc/stat")?;
+    parse_cpu_times(&stat).ok_or_else(|| Error::new(ErrorKind::InvalidData, "malformed /proc/stat"))
+}
+
+// Reads the "Key:   <n> kB" lines of /proc/meminfo. Kernels older than 3.14
+// have no MemAvailable, so that falls back on MemFree.
+fn parse_meminfo(meminfo: &str) -> Option<MemoryStatus> {
+    let field = |key: &str| {
+        meminfo
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(k, _)| *k == key)
+            .and_then(|(_, value)| value.split_whitespace().next()?.parse::<u64>().ok())
+            .map(|kb| kb * 1024)
+    };
+
+    let total = field("MemTotal")?;
+    let available = field("MemAvailable").or_else(|| field("MemFree"))?;
+    let used = total.saturating_sub(available);
+    let load = (used * 100).checked_div(total).unwrap_or(0);
+    Some(MemoryStatus {
+        total,
+        available,
+        used,
+        load: load as u32,
+        commit_total: field("Committed_AS").unwrap_or(0),
+        commit_limit: field("CommitLimit").unwrap_or(0),
+        swap_total: field("SwapTotal").unwrap_or(0),
+        swap_free: field("SwapFree").unwrap_or(0),
+    })
+}
+
+// A cgroup memory.max below the machine's memory takes the place of the
+// physical memory figures. Commit and swap stay machine-wide.
+pub fn get_memory_status() -> Result<MemoryStatus> {
+    let meminfo = fs::read_to_string("/proc/meminfo")?;
+    let mut status = parse_meminfo(&meminfo)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "malformed /proc/meminfo"))?;
+
+    if let Some(limit) = cgroup::memory_limit().filter(|limit| limit.limit < status.total) {
+        status.total = limit.limit;
+        status.used = limit.usage;
+        status.available = limit.limit - limit.usage;
+        status.load = (limit.usage * 100).checked_div(limit.limit).unwrap_or(0) as u32;
+    }
+    Ok(status)
+}
+
+// Uses the TZ variable or /etc/localtime, same as date(1)
+pub fn local_time(time: SystemTime) -> Option<LocalTime> {
+    let seconds = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let seconds = libc::time_t::try_from(seconds).ok()?;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&seconds, &mut tm) }.is_null() {
+        return None;
+    }
+    Some(LocalTime {
+        year: (tm.tm_year + 1900) as u32,
+        month: (tm.tm_mon + 1) as u32,
+        day: tm.tm_mday as u32,
+        hour: tm.tm_hour as u32,
+        minute: tm.tm_min as u32,
+        second: tm.tm_sec as u32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::get_cpu_usage;
+
+    const STAT: &str = "\
+cpu  4705 150 1120 16250 520 0 25 10 30 0
+cpu0 2350 75 560 8125 260 0 12 5 15 0
+cpu1 2355 75 560 8125 260 0 13 5 15 0
+intr 114930548 113199788 3 0 5 263 0 4 [...]
+ctxt 1990473
+btime 1062191376
+";
+
+    const MEMINFO: &str = "\
+MemTotal:       16303428 kB
+MemFree:         1103216 kB
+MemAvailable:    8151712 kB
+Buffers:          512340 kB
+Cached:          6212844 kB
+SwapTotal:       2097148 kB
+SwapFree:        2000000 kB
+CommitLimit:    10248860 kB
+Committed_AS:   12582912 kB
+";
+
+    // Only user, nice, system, idle, iowait, irq, softirq and steal count;
+    // the guest columns are already in user and nice
+    #[test]
+    fn cpu_times() {
+        let times = parse_cpu_times(STAT).unwrap();
+        assert_eq!(times.idle, 16250 + 520);
+        assert_eq!(times.total, 4705 + 150 + 1120 + 16250 + 520 + 25 + 10);
+    }
+
+    // Kernels before 2.5.41 only have the first four columns
+    #[test]
+    fn cpu_times_from_an_old_kernel() {
+        let times = parse_cpu_times("cpu 100 0 50 850\n").unwrap();
+        assert_eq!(times.idle, 850);
+        assert_eq!(times.total, 1000);
+    }
+
+    #[test]
+    fn cpu_times_malformed() {
+        assert!(parse_cpu_times("cpu0 1 2 3 4\n").is_none());
+        assert!(parse_cpu_times("cpu 1 2 3\n").is_none());
+        assert!(parse_cpu_times("cpu 1 2 x 4\n").is_none());
+    }
+
+    #[test]
+    fn cpu_usage_between_readings() {
+        let first = parse_cpu_times("cpu 100 0 100 800 0\n").unwrap();
+        let second = parse_cpu_times("cpu 150 0 150 900 0\n").unwrap();
+        assert_eq!(get_cpu_usage(first, second), Some(50.0));
+        assert_eq!(get_cpu_usage(second, second), None);
+    }
+
+    #[test]
+    fn meminfo() {
+        let status = parse_meminfo(MEMINFO).unwrap();
+        assert_eq!(status.total, 16303428 * 1024);
+        assert_eq!(status.available, 8151712 * 1024);
+        assert_eq!(status.used, (16303428 - 8151712) * 1024);
+        assert_eq!(status.load, 50);
+        assert_eq!(status.commit_total, 12582912 * 1024);
+        assert_eq!(status.commit_limit, 10248860 * 1024);
+        assert_eq!(status.swap_total, 2097148 * 1024);
+        assert_eq!(status.swap_free, 2000000 * 1024);
+    }
+
+    #[test]
+    fn meminfo_without_mem_available() {
+        let meminfo = "MemTotal: 1000 kB\nMemFree: 250 kB\n";
+        let status = parse_meminfo(meminfo).unwrap();
+        assert_eq!(status.available, 250 * 1024);
+        assert_eq!(status.load, 75);
+        assert_eq!(status.swap_total, 0);
+    }
+
+    #[test]
+    fn meminfo_malformed() {
+        assert!(parse_meminfo("MemFree: 250 kB\n").is_none());
+        assert!(parse_meminfo("MemTotal: lots\nMemFree: 250 kB\n").is_none());
+        assert!(parse_meminfo("MemTotal: 1000 kB\n").is_none());
+    }
+}
@@ -0,0 +1,231 @@
+// cgroup v2 limits on the group this process runs in, or any group above it.
+// Inside a container that's usually the container's own group, mounted as
+// the root of the hierarchy.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::system::CpuTimes;
+
+// Where the v2 hierarchy is mounted according to /proc/self/mounts:
+// /sys/fs/cgroup on most systems, but /sys/fs/cgroup/unified on hybrid v1/v2
+// ones
+fn parse_cgroup2_root(mounts: &str) -> Option<PathBuf> {
+    mounts.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let mount_point = fields.nth(1)?;
+        (fields.next()? == "cgroup2").then(|| PathBuf::from(mount_point))
+    })
+}
+
+// The group named in /proc/self/cgroup followed by its ancestors, up to the
+// root of the hierarchy. Empty on cgroup v1 systems.
+fn parse_cgroup_dirs(mounts: &str, cgroup: &str) -> Vec<PathBuf> {
+    let Some(path) = cgroup.lines().find_map(|line| line.strip_prefix("0::")) else {
+        return Vec::new();
+    };
+    let Some(root) = parse_cgroup2_root(mounts) else {
+        return Vec::new();
+    };
+    root.join(path.trim_start_matches('/'))
+        .ancestors()
+        .take_while(|dir| dir.starts_with(&root))
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+fn cgroup_dirs() -> Vec<PathBuf> {
+    let (Ok(mounts), Ok(cgroup)) = (
+        fs::read_to_string("/proc/self/mounts"),
+        fs::read_to_string("/proc/self/cgroup"),
+    ) else {
+        return Vec::new();
+    };
+    parse_cgroup_dirs(&mounts, &cgroup)
+}
+
+fn read_u64(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+// Looks up a "key value" line in a file like memory.stat or cpu.stat
+fn parse_stat_value(stat: &str, key: &str) -> Option<u64> {
+    stat.lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix(' '))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+fn stat_value(dir: &Path, file: &str, key: &str) -> Option<u64> {
+    parse_stat_value(&fs::read_to_string(dir.join(file)).ok()?, key)
+}
+
+// cpu.max is "<quota> <period>" in microseconds, where the quota is "max"
+// when there's no limit. Returns the quota as a number of processors.
+fn parse_cpu_max(cpu_max: &str) -> Option<f64> {
+    let mut fields = cpu_max.split_whitespace();
+    let quota: f64 = fields.next()?.parse().ok()?;
+    let period: f64 = fields.next()?.parse().ok()?;
+    (period > 0.0).then_some(quota / period)
+}
+
+pub struct MemoryLimit {
+    pub limit: u64,
+    pub usage: u64,
+}
+
+// The tightest memory.max, and what the group it's set on is using. Page
+// cache the kernel can drop without swapping doesn't count as used, same as
+// MemAvailable.
+pub fn memory_limit() -> Option<MemoryLimit> {
+    let (dir, limit) = cgroup_dirs()
+        .into_iter()
+        .filter_map(|dir| {
+            // "max" when there's no limit, which doesn't parse
+            let limit = read_u64(&dir.join("memory.max"))?;
+            Some((dir, limit))
+        })
+        .min_by_key(|&(_, limit)| limit)?;
+
+    let current = read_u64(&dir.join("memory.current"))?;
+    let inactive_file = stat_value(&dir, "memory.stat", "inactive_file").unwrap_or(0);
+    Some(MemoryLimit {
+        limit,
+        usage: current.saturating_sub(inactive_file).min(limit),
+    })
+}
+
+// The tightest cpu.max as a number of processors, along with the group it's
+// set on
+fn cpu_limit() -> Option<(PathBuf, f64)> {
+    cgroup_dirs()
+        .into_iter()
+        .filter_map(|dir| {
+            let cpus = parse_cpu_max(&fs::read_to_string(dir.join("cpu.max")).ok()?)?;
+            Some((dir, cpus))
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+}
+
+fn monotonic_micros() -> u64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1_000
+}
+
+// When cpu.max allows fewer processors than the machine has, the time the
+// group has used out of the time its quota allows, in microseconds.
+// Otherwise None, and the machine-wide figures apply.
+pub fn get_cpu_times(num_cpus: u32) -> Option<CpuTimes> {
+    let (dir, cpus) = cpu_limit()?;
+    if cpus >= num_cpus as f64 {
+        return None;
+    }
+    let usage = stat_value(&dir, "cpu.stat", "usage_usec")?;
+    let total = (monotonic_micros() as f64 * cpus) as u64;
+    Some(CpuTimes {
+        idle: total.saturating_sub(usage),
+        total,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTS: &str = "\
+proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
+cgroup2 /sys/fs/cgroup cgroup2 rw,nosuid,nodev,noexec,relatime 0 0
+";
+
+    // Hybrid systems mount v1 controllers alongside a v2 hierarchy
+    const HYBRID_MOUNTS: &str = "\
+tmpfs /sys/fs/cgroup tmpfs ro,nosuid,nodev,noexec,mode=755 0 0
+cgroup2 /sys/fs/cgroup/unified cgroup2 rw,nosuid,nodev,noexec,relatime 0 0
+cgroup /sys/fs/cgroup/memory cgroup rw,nosuid,nodev,noexec,relatime,memory 0 0
+";
+
+    const V1_MOUNTS: &str = "\
+tmpfs /sys/fs/cgroup tmpfs ro,nosuid,nodev,noexec,mode=755 0 0
+cgroup /sys/fs/cgroup/memory cgroup rw,nosuid,nodev,noexec,relatime,memory 0 0
+cgroup /sys/fs/cgroup/cpu,cpuacct cgroup rw,nosuid,nodev,noexec,relatime,cpu,cpuacct 0 0
+";
+
+    fn dirs(dirs: &[&str]) -> Vec<PathBuf> {
+        dirs.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn nested_group() {
+        let cgroup = "0::/user.slice/user-1000.slice/session-2.scope\n";
+        assert_eq!(
+            parse_cgroup_dirs(MOUNTS, cgroup),
+            dirs(&[
+                "/sys/fs/cgroup/user.slice/user-1000.slice/session-2.scope",
+                "/sys/fs/cgroup/user.slice/user-1000.slice",
+                "/sys/fs/cgroup/user.slice",
+                "/sys/fs/cgroup",
+            ])
+        );
+    }
+
+    // Inside a container the group is the root of its own hierarchy
+    #[test]
+    fn root_group() {
+        assert_eq!(
+            parse_cgroup_dirs(MOUNTS, "0::/\n"),
+            dirs(&["/sys/fs/cgroup"])
+        );
+    }
+
+    #[test]
+    fn hybrid_layout() {
+        let cgroup = "\
+5:memory:/user.slice/user-1000.slice
+1:name=systemd:/user.slice/user-1000.slice/session-2.scope
+0::/user.slice/user-1000.slice/session-2.scope
+";
+        assert_eq!(
+            parse_cgroup_dirs(HYBRID_MOUNTS, cgroup)[..2],
+            dirs(&[
+                "/sys/fs/cgroup/unified/user.slice/user-1000.slice/session-2.scope",
+                "/sys/fs/cgroup/unified/user.slice/user-1000.slice",
+            ])
+        );
+    }
+
+    #[test]
+    fn v1_only_layout() {
+        let cgroup = "\
+4:memory:/docker/0123456789ab
+3:cpu,cpuacct:/docker/0123456789ab
+1:name=systemd:/docker/0123456789ab
+";
+        assert!(parse_cgroup_dirs(V1_MOUNTS, cgroup).is_empty());
+        // A v2 path is no use without a v2 mount to find it under
+        assert!(parse_cgroup_dirs(V1_MOUNTS, "0::/\n").is_empty());
+    }
+
+    #[test]
+    fn stat_values() {
+        let stat = "anon 1048576\ninactive_anon 4096\ninactive_file 8192\n";
+        assert_eq!(parse_stat_value(stat, "inactive_file"), Some(8192));
+        assert_eq!(parse_stat_value(stat, "anon"), Some(1048576));
+        // Only whole keys match
+        assert_eq!(parse_stat_value(stat, "inactive"), None);
+        assert_eq!(parse_stat_value(stat, "file"), None);
+    }
+
+    #[test]
+    fn cpu_max() {
+        assert_eq!(parse_cpu_max("max 100000\n"), None);
+        assert_eq!(parse_cpu_max("50000 100000\n"), Some(0.5));
+        assert_eq!(parse_cpu_max("200000 100000\n"), Some(2.0));
+        assert_eq!(parse_cpu_max("50000 0\n"), None);
+        assert_eq!(parse_cpu_max(""), None);
+    }
+}
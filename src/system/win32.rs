@@ -0,0 +1,105 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use windows::{
+    core::Result,
+    Win32::{
+        Foundation::{FILETIME, SYSTEMTIME},
+        System::{
+            SystemInformation::{GetSystemInfo, GlobalMemoryStatusEx, MEMORYSTATUSEX, SYSTEM_INFO},
+            Threading::GetSystemTimes,
+            Time::{FileTimeToSystemTime, SystemTimeToTzSpecificLocalTime},
+        },
+    },
+};
+
+use super::{CpuTimes, LocalTime, MemoryStatus};
+
+pub fn get_num_cpus() -> u32 {
+    let mut system_info = SYSTEM_INFO::default();
+    unsafe { GetSystemInfo(&mut system_info) };
+    system_info.dwNumberOfProcessors
+}
+
+// The page file figures are the commit limit and what's left of it. Swap is
+// whatever the page files add on top of physical memory.
+fn to_memory_status(memory_status: &MEMORYSTATUSEX) -> MemoryStatus {
+    let swap_total = memory_status
+        .ullTotalPageFile
+        .saturating_sub(memory_status.ullTotalPhys);
+    MemoryStatus {
+        total: memory_status.ullTotalPhys,
+        available: memory_status.ullAvailPhys,
+        used: memory_status
+            .ullTotalPhys
+            .saturating_sub(memory_status.ullAvailPhys),
+        load: memory_status.dwMemoryLoad,
+        commit_total: memory_status
+            .ullTotalPageFile
+            .saturating_sub(memory_status.ullAvailPageFile),
+        commit_limit: memory_status.ullTotalPageFile,
+        swap_total,
+        swap_free: memory_status
+            .ullAvailPageFile
+            .saturating_sub(memory_status.ullAvailPhys)
+            .min(swap_total),
+    }
+}
+
+pub fn get_memory_status() -> Result<MemoryStatus> {
+    let mut memory_status = MEMORYSTATUSEX {
+        dwLength: size_of::<MEMORYSTATUSEX>() as u32,
+        ..Default::default()
+    };
+    unsafe { GlobalMemoryStatusEx(&mut memory_status)? }
+    Ok(to_memory_status(&memory_status))
+}
+
+fn filetime_to_u64(time: &FILETIME) -> u64 {
+    ((time.dwHighDateTime as u64) << 32) | (time.dwLowDateTime as u64)
+}
+
+pub fn get_cpu_times() -> std::io::Result<CpuTimes> {
+    let mut idle_time = FILETIME::default();
+    let mut kernel_time = FILETIME::default();
+    let mut user_time = FILETIME::default();
+    unsafe {
+        GetSystemTimes(
+            Some(&mut idle_time),
+            Some(&mut kernel_time),
+            Some(&mut user_time),
+        )?
+    };
+
+    // Kernel time already includes the idle time
+    Ok(CpuTimes {
+        idle: filetime_to_u64(&idle_time),
+        total: filetime_to_u64(&kernel_time) + filetime_to_u64(&user_time),
+    })
+}
+
+// 100ns intervals from 1601 to 1970
+const UNIX_EPOCH_FILETIME: u64 = 116_444_736_000_000_000;
+
+// Converted with the current time zone's rules
+pub fn local_time(time: SystemTime) -> Option<LocalTime> {
+    let since_epoch = time.duration_since(UNIX_EPOCH).ok()?;
+    let file_time = UNIX_EPOCH_FILETIME + (since_epoch.as_nanos() / 100) as u64;
+    let file_time = FILETIME {
+        dwLowDateTime: file_time as u32,
+        dwHighDateTime: (file_time >> 32) as u32,
+    };
+    let mut universal = SYSTEMTIME::default();
+    let mut local = SYSTEMTIME::default();
+    unsafe {
+        FileTimeToSystemTime(&file_time, &mut universal).ok()?;
+        SystemTimeToTzSpecificLocalTime(None, &universal, &mut local).ok()?;
+    }
+    Some(LocalTime {
+        year: local.wYear as u32,
+        month: local.wMonth as u32,
+        day: local.wDay as u32,
+        hour: local.wHour as u32,
+        minute: local.wMinute as u32,
+        second: local.wSecond as u32,
+    })
+}
@@ -1,43 +1,33 @@
-use std::{
-    cmp::{min, Ordering},
-    ffi::c_void,
-    mem::transmute,
-    rc::Rc,
-};
+use std::{cmp::min, collections::HashMap, ffi::c_void, mem::transmute, rc::Rc};
 
 use crate::{
-    process::{self, Process},
-    resources::{to_pcwstr, IDM_TASK_CONTEXT_MENU},
-    state::{self, SortKey, SortState},
+    affinity_dialog,
+    column::{self, Column},
+    environment_dialog,
+    process::{self, MemoryMode, Priority, Process},
+    resources::{
+        to_pcwstr, IDM_MEMORY_PRIVATE, IDM_MEMORY_USS, IDM_ONLY_ELEVATED, IDM_ONLY_MY_PROCESSES,
+        IDM_PRIORITY_LOW, IDM_PRIORITY_REALTIME, IDM_TASK_CONTEXT_MENU, IDM_TREE_VIEW,
+    },
+    rows::{self, Filter},
+    search_box,
+    sort::{SortKey, SortState},
+    state, status_text,
 };
-use human_bytes::human_bytes;
 use widestring::U16CString;
 use windows::{
-    core::{w, Result, PWSTR},
+    core::{w, Result, PCWSTR, PWSTR},
     Win32::{
         Foundation::*,
-        Globalization::*,
         System::LibraryLoader::GetModuleHandleW,
-        UI::{Controls::*, WindowsAndMessaging::*},
+        UI::{
+            Controls::*,
+            Input::KeyboardAndMouse::{VK_ADD, VK_LEFT, VK_RIGHT, VK_SUBTRACT},
+            WindowsAndMessaging::*,
+        },
     },
 };
 
-const INDEX_NAME: i32 = 0;
-const INDEX_PID: i32 = 1;
-const INDEX_CPU: i32 = 2;
-const INDEX_MEMORY: i32 = 3;
-const NUM_TASK_LIST_COLUMNS: usize = 4;
-
-fn column_index_to_sort_key(sort_column_index: i32) -> SortKey {
-    match sort_column_index {
-        INDEX_NAME => SortKey::Name,
-        INDEX_PID => SortKey::Pid,
-        INDEX_CPU => SortKey::Cpu,
-        INDEX_MEMORY => SortKey::Memory,
-        _ => unreachable!(),
-    }
-}
-
 pub unsafe fn create_control(instance: &HINSTANCE, parent: HWND) -> Result<HWND> {
     let style = WS_TABSTOP | WS_CHILD | WS_BORDER | WS_VISIBLE;
     let lv_style = LVS_AUTOARRANGE | LVS_REPORT | LVS_OWNERDATA;
@@ -65,72 +55,86 @@ pub unsafe fn create_control(instance: &HINSTANCE, parent: HWND) -> Result<HWND>
         Some(LPARAM(extended_lv_style as isize)),
     );
 
-    add_column(hwnd, "Name", INDEX_NAME, 400, LVCFMT_LEFT);
-    add_column(hwnd, "PID", INDEX_PID, 50, LVCFMT_LEFT);
-    add_column(hwnd, "CPU", INDEX_CPU, 50, LVCFMT_RIGHT);
-    add_column(hwnd, "Memory", INDEX_MEMORY, 90, LVCFMT_RIGHT);
+    add_process_columns(hwnd, &column::default_columns(), MemoryMode::default());
 
     Ok(hwnd)
 }
 
-fn lexical_str_cmp(a: &U16CString, b: &U16CString) -> Ordering {
-    let result = unsafe {
-        CompareStringEx(
-            LOCALE_NAME_SYSTEM_DEFAULT,
-            COMPARE_STRING_FLAGS(0),
-            a.as_slice(),
-            b.as_slice(),
-            None,
-            None,
-            None,
-        )
-    };
-    match result {
-        CSTR_LESS_THAN => Ordering::Less,
-        CSTR_EQUAL => Ordering::Equal,
-        CSTR_GREATER_THAN => Ordering::Greater,
-        _ => unreachable!(),
+fn add_process_columns(task_list: HWND, columns: &[&Column], memory_mode: MemoryMode) {
+    for (index, column) in columns.iter().enumerate() {
+        let fmt = if column.align_right {
+            LVCFMT_RIGHT
+        } else {
+            LVCFMT_LEFT
+        };
+        let title = column::title(column, memory_mode);
+        add_column(task_list, &title, index as i32, column.window_width, fmt);
     }
 }
 
-fn sort_process_list(processes: &mut [std::rc::Rc<Process>], sort_key: SortKey) {
-    match sort_key {
-        SortKey::Name => processes.sort_by(|a, b| {
-            lexical_str_cmp(&a.image_name, &b.image_name).then_with(|| a.pid.cmp(&b.pid))
-        }),
-        SortKey::Pid => processes.sort_by_key(|k| k.pid),
-        SortKey::Cpu => processes.sort_by(|a, b| {
-            a.cpu_usage
-                .cmp(&b.cpu_usage)
-                .then_with(|| a.pid.cmp(&b.pid))
-        }),
-        SortKey::Memory => processes.sort_by(|a, b| {
-            a.private_working_set
-                .cmp(&b.private_working_set)
-                .then_with(|| a.pid.cmp(&b.pid))
-        }),
+// Swaps the list's columns for the ones picked in the Select columns dialog
+pub fn set_columns(hwnd: HWND, columns: Vec<&'static Column>) {
+    let state = unsafe { state::get(hwnd) };
+    for index in (0..state.columns.len()).rev() {
+        unsafe { SendMessageW(state.task_list, LVM_DELETECOLUMN, Some(WPARAM(index)), None) };
+    }
+    add_process_columns(state.task_list, &columns, state.memory_mode);
+    unsafe {
+        show_sort_arrow(state.task_list, &columns, state.sort_state);
+        state::set_columns(hwnd, columns);
     }
+    let _ = refresh_process_list(hwnd, true);
 }
 
-// invalidate_all = true does full refresh of list rather than just the items in view
-pub fn refresh_process_list(main_window: HWND, invalidate_all: bool) {
+// invalidate_all = true does full refresh of list rather than just the items in view.
+// On failure the list keeps showing the last refresh, like a finished replay
+// keeps showing its last tick.
+pub fn refresh_process_list(main_window: HWND, invalidate_all: bool) -> std::io::Result<()> {
     let state = unsafe { state::get(main_window) };
 
-    let new_pid_map = process::get_processes(&state).unwrap();
+    let new_pid_map = process::get_processes(
+        state.source.borrow_mut().as_mut(),
+        &state.pid_map,
+        state.num_cpus,
+    )?;
+    show_process_list(main_window, new_pid_map, invalidate_all);
+    Ok(())
+}
 
-    let mut new_process_list: Vec<Rc<Process>> = new_pid_map.values().cloned().collect();
-    let num_processes = new_process_list.len();
+// Re-sorts the processes from the last refresh without sampling again, after
+// the sort order, tree mode, the filter or a collapsed row changes
+pub fn resort_process_list(main_window: HWND) {
+    let state = unsafe { state::get(main_window) };
+    show_process_list(main_window, state.pid_map, true);
+}
 
-    match state.sort_state {
-        SortState::SortUp(sort_key) => sort_process_list(&mut new_process_list, sort_key),
-        SortState::SortDown(sort_key) => {
-            sort_process_list(&mut new_process_list, sort_key);
-            new_process_list.reverse();
-        }
-    }
+fn show_process_list(
+    main_window: HWND,
+    new_pid_map: HashMap<u32, Rc<Process>>,
+    invalidate_all: bool,
+) {
+    let state = unsafe { state::get(main_window) };
+
+    let new_rows = if state.tree_mode {
+        rows::tree_rows(
+            &new_pid_map,
+            state.sort_state,
+            &state.collapsed,
+            &state.filter,
+            state.memory_mode,
+        )
+    } else {
+        rows::flat_rows(
+            &new_pid_map,
+            state.sort_state,
+            &state.filter,
+            state.memory_mode,
+        )
+    };
+    let num_rows = new_rows.len();
 
     unsafe {
-        state::update_processes(main_window, new_process_list, new_pid_map);
+        state::update_processes(main_window, new_rows, new_pid_map);
     }
 
     let flags = if invalidate_all {
@@ -143,13 +147,13 @@ pub fn refresh_process_list(main_window: HWND, invalidate_all: bool) {
         SendMessageW(
             state.task_list,
             LVM_SETITEMCOUNT,
-            Some(WPARAM(num_processes)),
+            Some(WPARAM(num_rows)),
             Some(LPARAM(flags as isize)),
         );
     };
 }
 
-pub fn resize_to_parent(listview: HWND, parent: HWND, status_bar: HWND) {
+pub fn resize_to_parent(listview: HWND, parent: HWND, status_bar: HWND, search_box: HWND) {
     unsafe {
         // Let status bar size itself first
         SendMessageW(status_bar, WM_SIZE, None, None);
@@ -163,13 +167,17 @@ pub fn resize_to_parent(listview: HWND, parent: HWND, status_bar: HWND) {
         let _ = GetWindowRect(status_bar, &mut status_rect);
         let status_height = status_rect.bottom - status_rect.top;
 
-        // Size listview to fill client area minus status bar height
+        // Search box across the top
+        let search_height = search_box::SEARCH_BOX_HEIGHT;
+        let _ = MoveWindow(search_box, 0, 0, client_rect.right, search_height, true);
+
+        // Size listview to fill the rest of the client area above the status bar
         let _ = MoveWindow(
             listview,
             0,
-            0,
+            search_height,
             client_rect.right,
-            client_rect.bottom - status_height,
+            client_rect.bottom - status_height - search_height,
             true,
         );
     };
@@ -183,33 +191,21 @@ pub unsafe fn on_get_display_info(hwnd: HWND, lparam: LPARAM) {
     }
 
     let state = state::get(hwnd);
-    let process = &state.processes[lpdi.item.iItem as usize];
+    let row = &state.rows[lpdi.item.iItem as usize];
 
-    match lpdi.item.iSubItem {
-        INDEX_NAME => {
-            copy_wstring_to_buffer(&process.image_name, lpdi.item.pszText, lpdi.item.cchTextMax);
-        }
-        INDEX_PID => {
-            let pid_s = process.pid.to_string();
-            copy_string_to_buffer(&pid_s, lpdi.item.pszText, lpdi.item.cchTextMax);
-        }
-        INDEX_CPU => {
-            let cpu_s = process.cpu_usage.to_string();
-            copy_string_to_buffer(&cpu_s, lpdi.item.pszText, lpdi.item.cchTextMax);
-        }
-        INDEX_MEMORY => {
-            let ws_s = human_bytes(process.private_working_set as f64);
-            copy_string_to_buffer(&ws_s, lpdi.item.pszText, lpdi.item.cchTextMax);
-        }
-        _ => unreachable!(),
-    }
+    // The list can ask while its columns are being swapped
+    let Some(column) = state.columns.get(lpdi.item.iSubItem as usize) else {
+        return;
+    };
+    let text = (column.text)(row);
+    copy_string_to_buffer(&text, lpdi.item.pszText, lpdi.item.cchTextMax);
 }
 
 pub unsafe fn on_column_click(hwnd: HWND, lparam: LPARAM) {
     let lpdi = transmute::<LPARAM, *const NMLISTVIEW>(lparam);
     let lpdi = &(*lpdi);
     toggle_sort_order(hwnd, lpdi.iSubItem);
-    refresh_process_list(hwnd, true);
+    resort_process_list(hwnd);
 }
 
 pub fn on_show_contextmenu(hwnd: HWND, x: i32, y: i32) {
@@ -224,6 +220,20 @@ pub fn on_show_contextmenu(hwnd: HWND, x: i32, y: i32) {
         let menu_load =
             LoadMenuW(Some(instance), to_pcwstr(IDM_TASK_CONTEXT_MENU)).expect("shouldn't fail");
         let menu = GetSubMenu(menu_load, 0);
+        // Marks the current priority in the Set priority submenu
+        let process = &state.rows[selected_item as usize].process;
+        if let Some(index) = process
+            .priority
+            .and_then(|priority| Priority::ALL.iter().position(|&p| p == priority))
+        {
+            let _ = CheckMenuRadioItem(
+                menu,
+                IDM_PRIORITY_REALTIME as u32,
+                IDM_PRIORITY_LOW as u32,
+                IDM_PRIORITY_REALTIME as u32 + index as u32,
+                MF_BYCOMMAND.0,
+            );
+        }
         // FIXME: should call GetSystemMetrics to find the correct context menu alignment
         let _ = TrackPopupMenu(
             menu,
@@ -238,14 +248,36 @@ pub fn on_show_contextmenu(hwnd: HWND, x: i32, y: i32) {
     }
 }
 
+// Tells the user an action they asked for failed. The source has to be
+// borrowed and released by then, since the timer keeps refreshing the list
+// while the message box is up.
+pub fn show_error(hwnd: HWND, message: &str) {
+    let text = U16CString::from_str_truncate(message);
+    unsafe {
+        MessageBoxW(
+            Some(hwnd),
+            PCWSTR(text.as_ptr()),
+            w!("taskmanager--"),
+            MB_OK | MB_ICONERROR,
+        );
+    }
+}
+
 pub fn on_end_task_clicked(hwnd: HWND) -> LRESULT {
     unsafe {
         let state = state::get(hwnd);
         let selected_item = get_selected_task(state.task_list);
         if selected_item >= 0 {
-            let process = &state.processes[selected_item as usize];
-            if let Err(e) = process::kill_process(process.pid) {
-                println!("failed to kill process {}: {}", process.pid, e);
+            let process = &state.rows[selected_item as usize].process;
+            let result = state.source.borrow_mut().kill(process.pid);
+            if let Err(e) = result {
+                show_error(
+                    hwnd,
+                    &format!(
+                        "Couldn't end {} ({}): {}",
+                        process.image_name, process.pid, e
+                    ),
+                );
             }
         }
 
@@ -253,8 +285,285 @@ pub fn on_end_task_clicked(hwnd: HWND) -> LRESULT {
     }
 }
 
-fn add_column(task_list: HWND, title: &str, order: i32, width: i32, fmt: LVCOLUMNW_FORMAT) {
-    let mut title = widestring::U16CString::from_str(title).unwrap();
+pub fn on_open_file_location_clicked(hwnd: HWND) -> LRESULT {
+    let state = unsafe { state::get(hwnd) };
+    let selected_item = get_selected_task(state.task_list);
+    if selected_item >= 0 {
+        let process = &state.rows[selected_item as usize].process;
+        let result = state.source.borrow_mut().open_file_location(process);
+        if let Err(e) = result {
+            show_error(
+                hwnd,
+                &format!(
+                    "Couldn't open the location of {}: {}",
+                    process.image_name, e
+                ),
+            );
+        }
+    }
+    LRESULT(0)
+}
+
+pub fn on_set_affinity_clicked(hwnd: HWND) -> LRESULT {
+    let state = unsafe { state::get(hwnd) };
+    let selected_item = get_selected_task(state.task_list);
+    if selected_item >= 0 {
+        affinity_dialog::show(hwnd, &state.rows[selected_item as usize].process);
+    }
+    LRESULT(0)
+}
+
+pub fn on_environment_clicked(hwnd: HWND) -> LRESULT {
+    let state = unsafe { state::get(hwnd) };
+    let selected_item = get_selected_task(state.task_list);
+    if selected_item >= 0 {
+        environment_dialog::show(hwnd, &state.rows[selected_item as usize].process);
+    }
+    LRESULT(0)
+}
+
+pub fn on_security_details_clicked(hwnd: HWND) -> LRESULT {
+    let state = unsafe { state::get(hwnd) };
+    let selected_item = get_selected_task(state.task_list);
+    if selected_item < 0 {
+        return LRESULT(0);
+    }
+    let process = &state.rows[selected_item as usize].process;
+    let text = format!(
+        "{} ({})\r\n\r\n{}",
+        process.image_name,
+        process.pid,
+        status_text::security_details(&process.security, "\r\n")
+    );
+    let text = U16CString::from_str_truncate(text);
+    unsafe {
+        MessageBoxW(
+            Some(hwnd),
+            PCWSTR(text.as_ptr()),
+            w!("Security details"),
+            MB_OK | MB_ICONINFORMATION,
+        );
+    }
+    LRESULT(0)
+}
+
+pub fn on_set_priority_clicked(hwnd: HWND, priority: Priority) -> LRESULT {
+    let state = unsafe { state::get(hwnd) };
+    let selected_item = get_selected_task(state.task_list);
+    if selected_item >= 0 {
+        let process = &state.rows[selected_item as usize].process;
+        let result = state
+            .source
+            .borrow_mut()
+            .set_priority(process.pid, priority);
+        match result {
+            // Shows the new priority without waiting for the next refresh
+            Ok(()) => {
+                let _ = refresh_process_list(hwnd, false);
+            }
+            Err(e) => show_error(
+                hwnd,
+                &format!(
+                    "Couldn't set the priority of {} ({}) to {}: {}",
+                    process.image_name,
+                    process.pid,
+                    priority.name(),
+                    e
+                ),
+            ),
+        }
+    }
+    LRESULT(0)
+}
+
+pub fn on_tree_view_clicked(hwnd: HWND) -> LRESULT {
+    unsafe {
+        let state = state::get(hwnd);
+        let tree_mode = !state.tree_mode;
+        state::set_tree_mode(hwnd, tree_mode, state.collapsed);
+
+        let menu = GetMenu(hwnd);
+        let check = if tree_mode { MF_CHECKED } else { MF_UNCHECKED };
+        CheckMenuItem(menu, IDM_TREE_VIEW as u32, (MF_BYCOMMAND | check).0);
+    }
+    resort_process_list(hwnd);
+    LRESULT(0)
+}
+
+// Filters the list as the search box is typed in
+pub fn on_search_changed(hwnd: HWND) -> LRESULT {
+    unsafe {
+        let state = state::get(hwnd);
+        let filter = Filter {
+            text: search_box::get_text(state.search_box),
+            ..state.filter
+        };
+        state::set_filter(hwnd, filter);
+    }
+    resort_process_list(hwnd);
+    LRESULT(0)
+}
+
+pub fn on_only_my_processes_clicked(hwnd: HWND) -> LRESULT {
+    unsafe {
+        let state = state::get(hwnd);
+        let user = match state.filter.user {
+            Some(_) => None,
+            None => Some(process::sampling_user(state.source.borrow().as_ref())),
+        };
+        let check = if user.is_some() {
+            MF_CHECKED
+        } else {
+            MF_UNCHECKED
+        };
+        state::set_filter(
+            hwnd,
+            Filter {
+                user,
+                ..state.filter
+            },
+        );
+
+        let menu = GetMenu(hwnd);
+        CheckMenuItem(menu, IDM_ONLY_MY_PROCESSES as u32, (MF_BYCOMMAND | check).0);
+    }
+    resort_process_list(hwnd);
+    LRESULT(0)
+}
+
+pub fn on_only_elevated_clicked(hwnd: HWND) -> LRESULT {
+    unsafe {
+        let state = state::get(hwnd);
+        let elevated_only = !state.filter.elevated_only;
+        state::set_filter(
+            hwnd,
+            Filter {
+                elevated_only,
+                ..state.filter
+            },
+        );
+
+        let menu = GetMenu(hwnd);
+        let check = if elevated_only {
+            MF_CHECKED
+        } else {
+            MF_UNCHECKED
+        };
+        CheckMenuItem(menu, IDM_ONLY_ELEVATED as u32, (MF_BYCOMMAND | check).0);
+    }
+    resort_process_list(hwnd);
+    LRESULT(0)
+}
+
+// Marks the current mode in the View menu's Memory figure popup
+pub fn check_memory_mode(hwnd: HWND, memory_mode: MemoryMode) {
+    let index = MemoryMode::ALL
+        .iter()
+        .position(|&mode| mode == memory_mode)
+        .unwrap_or(0);
+    unsafe {
+        let _ = CheckMenuRadioItem(
+            GetMenu(hwnd),
+            IDM_MEMORY_PRIVATE as u32,
+            IDM_MEMORY_USS as u32,
+            IDM_MEMORY_PRIVATE as u32 + index as u32,
+            MF_BYCOMMAND.0,
+        );
+    }
+}
+
+// PSS and USS are only measured while shown, so this samples again rather
+// than waiting for the next refresh to fill them in
+pub fn on_memory_mode_clicked(hwnd: HWND, memory_mode: MemoryMode) -> LRESULT {
+    let state = unsafe { state::get(hwnd) };
+    unsafe { state::set_memory_mode(hwnd, memory_mode) };
+    state.source.borrow_mut().set_memory_mode(memory_mode);
+    check_memory_mode(hwnd, memory_mode);
+
+    if let Some(index) = state
+        .columns
+        .iter()
+        .position(|c| c.sort_key == SortKey::Memory)
+    {
+        set_column_title(
+            state.task_list,
+            index,
+            &column::title(state.columns[index], memory_mode),
+        );
+    }
+    let _ = refresh_process_list(hwnd, true);
+    LRESULT(0)
+}
+
+// CPU and memory added up for each user, as of the last refresh
+pub fn on_user_totals_clicked(hwnd: HWND) -> LRESULT {
+    let state = unsafe { state::get(hwnd) };
+    let text = rows::user_totals(&state.pid_map, state.memory_mode)
+        .iter()
+        .map(status_text::user_total)
+        .collect::<Vec<_>>()
+        .join("\r\n");
+    let text = U16CString::from_str_truncate(text);
+    unsafe {
+        MessageBoxW(
+            Some(hwnd),
+            PCWSTR(text.as_ptr()),
+            w!("User totals"),
+            MB_OK | MB_ICONINFORMATION,
+        );
+    }
+    LRESULT(0)
+}
+
+// Shows or hides the children of a row in tree mode. Returns false if the
+// row had none, or was already that way.
+fn set_expanded(hwnd: HWND, index: usize, expanded: bool) -> bool {
+    let state = unsafe { state::get(hwnd) };
+    let Some(row) = state.rows.get(index) else {
+        return false;
+    };
+    if row.expanded != Some(!expanded) {
+        return false;
+    }
+
+    let mut collapsed = state.collapsed.clone();
+    if expanded {
+        collapsed.remove(&row.process.pid);
+    } else {
+        collapsed.insert(row.process.pid);
+    }
+    unsafe { state::set_tree_mode(hwnd, state.tree_mode, collapsed) };
+    resort_process_list(hwnd);
+    true
+}
+
+// Left and Right (or - and +) collapse and expand the selected row, the way
+// they do in a tree view
+pub unsafe fn on_key_down(hwnd: HWND, lparam: LPARAM) {
+    let key_down = &*transmute::<LPARAM, *const NMLVKEYDOWN>(lparam);
+    let state = state::get(hwnd);
+    let selected_item = get_selected_task(state.task_list);
+    if !state.tree_mode || selected_item < 0 {
+        return;
+    }
+
+    let key = key_down.wVKey;
+    if key == VK_LEFT.0 || key == VK_SUBTRACT.0 {
+        set_expanded(hwnd, selected_item as usize, false);
+    } else if key == VK_RIGHT.0 || key == VK_ADD.0 {
+        set_expanded(hwnd, selected_item as usize, true);
+    }
+}
+
+pub unsafe fn on_double_click(hwnd: HWND, lparam: LPARAM) {
+    let item = &*transmute::<LPARAM, *const NMITEMACTIVATE>(lparam);
+    if item.iItem >= 0 && !set_expanded(hwnd, item.iItem as usize, false) {
+        set_expanded(hwnd, item.iItem as usize, true);
+    }
+}
+
+pub fn add_column(task_list: HWND, title: &str, order: i32, width: i32, fmt: LVCOLUMNW_FORMAT) {
+    let mut title = widestring::U16CString::from_str_truncate(title);
     let header = PWSTR::from_raw(title.as_mut_ptr());
     let mut column = LVCOLUMNW {
         mask: LVCF_FMT | LVCF_WIDTH | LVCF_TEXT | LVCF_SUBITEM,
@@ -273,18 +582,40 @@ fn add_column(task_list: HWND, title: &str, order: i32, width: i32, fmt: LVCOLUM
     };
 }
 
+fn set_column_title(task_list: HWND, index: usize, title: &str) {
+    let mut title = widestring::U16CString::from_str_truncate(title);
+    let mut column = LVCOLUMNW {
+        mask: LVCF_TEXT,
+        pszText: PWSTR::from_raw(title.as_mut_ptr()),
+        ..Default::default()
+    };
+    unsafe {
+        SendMessageW(
+            task_list,
+            LVM_SETCOLUMNW,
+            Some(WPARAM(index)),
+            Some(LPARAM(&raw mut column as isize)),
+        )
+    };
+}
+
+// Command lines can hold a NUL, which ends the text there
 unsafe fn copy_string_to_buffer(s: &str, buffer: PWSTR, buffer_size: i32) {
-    let wstr = U16CString::from_str(s).unwrap();
+    let wstr = U16CString::from_str_truncate(s);
     copy_wstring_to_buffer(&wstr, buffer, buffer_size);
 }
 
+// Text longer than the buffer is cut short, and always NUL-terminated
 unsafe fn copy_wstring_to_buffer(wstr: &U16CString, buffer: PWSTR, buffer_size: i32) {
-    let wstr_size_bytes = wstr.as_slice().len() + 1;
-    let len = min(buffer_size as usize, wstr_size_bytes);
+    if buffer_size <= 0 {
+        return;
+    }
+    let len = min(buffer_size as usize - 1, wstr.len());
     std::ptr::copy_nonoverlapping(wstr.as_ptr(), buffer.as_ptr(), len);
+    *buffer.as_ptr().add(len) = 0;
 }
 
-fn get_selected_task(list_hwnd: HWND) -> isize {
+pub fn get_selected_task(list_hwnd: HWND) -> isize {
     let result = unsafe {
         SendMessageW(
             list_hwnd,
@@ -303,22 +634,24 @@ const HEADER_NO_SORT_FORMAT: i32 = HDF_STRING.0;
 unsafe fn toggle_sort_order(hwnd: HWND, sort_column_index: i32) {
     let state = state::get(hwnd);
 
-    let new_sort = match state.sort_state {
-        SortState::SortUp(_) => SortState::SortDown(column_index_to_sort_key(sort_column_index)),
-        SortState::SortDown(_) => SortState::SortUp(column_index_to_sort_key(sort_column_index)),
-    };
+    let new_sort = state
+        .sort_state
+        .toggled(state.columns[sort_column_index as usize].sort_key);
 
     state::set_sort_state(hwnd, new_sort);
+    show_sort_arrow(state.task_list, &state.columns, new_sort);
+}
 
-    let header = SendMessageW(state.task_list, LVM_GETHEADER, None, None);
+// Marks the header of the column the list is sorted by, if it's shown
+unsafe fn show_sort_arrow(task_list: HWND, columns: &[&Column], sort_state: SortState) {
+    let header = SendMessageW(task_list, LVM_GETHEADER, None, None);
     if header.0 == INVALID_HANDLE_VALUE.0 as isize {
-        println!("LVM_GETHEADER failed");
         return;
     }
 
     let header = HWND(header.0 as _);
 
-    for column_index in 0..NUM_TASK_LIST_COLUMNS {
+    for (column_index, sort_column) in columns.iter().enumerate() {
         let mut column = HDITEMW {
             mask: HDI_FORMAT,
             ..Default::default()
@@ -330,12 +663,11 @@ unsafe fn toggle_sort_order(hwnd: HWND, sort_column_index: i32) {
             Some(LPARAM(&raw mut column as isize)),
         );
         if result.0 == 0 {
-            println!("HDM_GETITEM failed for column: {}", column_index);
             continue;
         }
 
-        if column_index == sort_column_index as usize {
-            column.fmt = match new_sort {
+        if sort_column.sort_key == sort_state.key() {
+            column.fmt = match sort_state {
                 SortState::SortDown(_) => HEADER_CONTROL_FORMAT_FLAGS(HEADER_SORT_DOWN_FORMAT),
                 SortState::SortUp(_) => HEADER_CONTROL_FORMAT_FLAGS(HEADER_SORT_UP_FORMAT),
             };
@@ -343,14 +675,11 @@ unsafe fn toggle_sort_order(hwnd: HWND, sort_column_index: i32) {
             column.fmt = HEADER_CONTROL_FORMAT_FLAGS(HEADER_NO_SORT_FORMAT);
         }
 
-        let result = SendMessageW(
+        SendMessageW(
             header,
             HDM_SETITEM,
             Some(WPARAM(column_index)),
             Some(LPARAM(&raw mut column as isize)),
         );
-        if result.0 == 0 {
-            println!("HDM_SETITEM failed for column: {}", column_index);
-        }
     }
 }
@@ -0,0 +1,1253 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{ErrorKind, Result},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use human_bytes::human_bytes;
+
+use crate::{
+    cli::SourceOptions,
+    column::{self, Column, COLUMNS},
+    connection::{self, ConnectionRow, CONNECTION_COLUMNS},
+    environment::{self, Variable},
+    process::{self, MemoryMode, Priority, Process, ProcessSource},
+    recording::Recorder,
+    rows::{self, Filter, Row},
+    sort::{SortKey, SortState},
+    status_text,
+    system::{self, CpuSampler, SystemStatus},
+    REFRESH_INTERVAL_MS,
+};
+
+mod terminal;
+
+use terminal::{Key, Terminal};
+
+const HEADER_ROWS: usize = 1;
+// The status bar line and the key help / prompt line
+const FOOTER_ROWS: usize = 2;
+const COLUMN_GAP: usize = 2;
+// User column of the per-user totals
+const USER_WIDTH: usize = 24;
+// Name column of the environment view
+const VARIABLE_NAME_WIDTH: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum View {
+    Processes,
+    // Per-user totals
+    Users,
+    // TCP and UDP sockets with their owners
+    Connections,
+    // One process's environment variables
+    Environment,
+    // Every process column, for picking the ones to show
+    Columns,
+}
+
+// The environment of one process, as of when the view was opened
+struct EnvironmentView {
+    pid: u32,
+    image_name: String,
+    variables: Vec<Variable>,
+    // Only variables whose name or value contains it are shown
+    filter: String,
+    // Set while '/' has the footer taking the filter text
+    editing_filter: bool,
+    // File name while 'x' has the footer asking where to export to
+    export_path: Option<String>,
+    selected: usize,
+    scroll: usize,
+}
+
+impl EnvironmentView {
+    fn shown(&self) -> Vec<&Variable> {
+        environment::filter(&self.variables, &self.filter)
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum Action {
+    Continue,
+    Quit,
+}
+
+struct App {
+    source: Box<dyn ProcessSource>,
+    recorder: Option<Recorder>,
+    num_cpus: u32,
+    cpu_sampler: CpuSampler,
+    system_status: SystemStatus,
+    // Set once a replayed recording runs out of ticks
+    replay_finished: bool,
+    sort_state: SortState,
+    // The process list's columns, in display order
+    columns: Vec<&'static Column>,
+    tree_mode: bool,
+    // PIDs whose children are hidden in tree mode
+    collapsed: HashSet<u32>,
+    rows: Vec<Row>,
+    pid_map: HashMap<u32, Rc<Process>>,
+    filter: Filter,
+    memory_mode: MemoryMode,
+    // Set while '/' has the footer taking the filter text
+    editing_filter: bool,
+    view: View,
+    // The sockets as of the last refresh, while the connections view shows
+    connections: Vec<ConnectionRow>,
+    // Set while the environment view shows
+    environment: Option<EnvironmentView>,
+
+    selected: usize,
+    scroll: usize,
+    selected_connection: usize,
+    connection_scroll: usize,
+    // The column picker's highlighted line, indexing COLUMNS
+    selected_column: usize,
+    column_scroll: usize,
+    list_height: usize,
+    // PID waiting for a y/n answer before it is ended
+    confirm_end_task: Option<u32>,
+    // PID whose new priority the footer is asking for
+    choosing_priority: Option<u32>,
+    // PID and CPU list while 'a' has the footer taking a new affinity
+    editing_affinity: Option<(u32, String)>,
+    message: Option<String>,
+    // Text waiting to be sent to the terminal's clipboard
+    clipboard: Option<String>,
+}
+
+// Start column and width of each column for a given screen width, given
+// their terminal widths. The columns without a fixed width share what's left
+// equally, with the last of them taking any remainder.
+fn column_layout(widths: &[Option<usize>], screen_width: usize) -> Vec<(usize, usize)> {
+    let fixed: usize = widths.iter().flatten().sum();
+    let gaps = COLUMN_GAP * (widths.len() - 1);
+    let num_flexible = widths.iter().filter(|w| w.is_none()).count();
+    let leftover = screen_width.saturating_sub(fixed + gaps);
+    let flexible = (leftover / num_flexible.max(1)).max(4);
+    let last_flexible = widths.iter().rposition(|w| w.is_none());
+
+    let mut start = 0;
+    widths
+        .iter()
+        .enumerate()
+        .map(|(index, terminal_width)| {
+            let width = match *terminal_width {
+                Some(width) => width,
+                None if Some(index) == last_flexible => {
+                    flexible.max(leftover.saturating_sub(flexible * (num_flexible - 1)))
+                }
+                None => flexible,
+            };
+            let layout = (start, width);
+            start += width + COLUMN_GAP;
+            layout
+        })
+        .collect()
+}
+
+// Process names and command lines are set by the process itself, so control
+// characters are replaced before they can reach the terminal as escape
+// sequences
+fn fit(text: &str, width: usize, align_right: bool) -> String {
+    let text: String = text
+        .chars()
+        .map(|c| if c.is_control() { '?' } else { c })
+        .take(width)
+        .collect();
+    if align_right {
+        format!("{:>width$}", text)
+    } else {
+        format!("{:<width$}", text)
+    }
+}
+
+fn process_layout(columns: &[&Column], screen_width: usize) -> Vec<(usize, usize)> {
+    let widths: Vec<Option<usize>> = columns.iter().map(|c| c.terminal_width).collect();
+    column_layout(&widths, screen_width)
+}
+
+fn connection_layout(screen_width: usize) -> Vec<(usize, usize)> {
+    let widths: Vec<Option<usize>> = CONNECTION_COLUMNS
+        .iter()
+        .map(|c| c.terminal_width)
+        .collect();
+    column_layout(&widths, screen_width)
+}
+
+// `align_right` says for each cell whether it's a number
+fn format_row(cells: &[String], align_right: &[bool], layout: &[(usize, usize)]) -> String {
+    let mut row = String::new();
+    for ((cell, &align_right), (_, width)) in cells.iter().zip(align_right).zip(layout) {
+        if !row.is_empty() {
+            row.push_str(&" ".repeat(COLUMN_GAP));
+        }
+        row.push_str(&fit(cell, *width, align_right));
+    }
+    row
+}
+
+// CPU numbers written the way taskset and /proc do, like "0-3,6"
+fn format_cpu_list(cpus: &[u32]) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for &cpu in cpus {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == cpu => *last = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|&(first, last)| match last - first {
+            0 => first.to_string(),
+            _ => format!("{}-{}", first, last),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn parse_cpu_list(text: &str) -> Option<Vec<u32>> {
+    let mut cpus = Vec::new();
+    for part in text
+        .split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+    {
+        let (first, last) = part.split_once('-').unwrap_or((part, part));
+        let (first, last): (u32, u32) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+        if first > last {
+            return None;
+        }
+        cpus.extend(first..=last);
+    }
+    cpus.sort_unstable();
+    cpus.dedup();
+    Some(cpus)
+}
+
+// Moves a list's selection by `delta` rows, scrolling to keep it in view
+fn move_within(
+    selected: &mut usize,
+    scroll: &mut usize,
+    len: usize,
+    list_height: usize,
+    delta: isize,
+) {
+    *selected = selected
+        .saturating_add_signed(delta)
+        .min(len.saturating_sub(1));
+    if *selected < *scroll {
+        *scroll = *selected;
+    } else if list_height > 0 && *selected >= *scroll + list_height {
+        *scroll = *selected + 1 - list_height;
+    }
+}
+
+impl App {
+    fn new(options: &SourceOptions) -> Result<App> {
+        let source = options.open_source()?;
+        let num_cpus = source.num_cpus().unwrap_or_else(system::get_num_cpus);
+        Ok(App {
+            recorder: options.open_recorder(num_cpus)?,
+            source,
+            num_cpus,
+            cpu_sampler: CpuSampler::new(),
+            system_status: SystemStatus::default(),
+            replay_finished: false,
+            sort_state: SortState::SortUp(SortKey::Name),
+            columns: column::default_columns(),
+            tree_mode: false,
+            collapsed: HashSet::new(),
+            rows: Vec::new(),
+            pid_map: HashMap::new(),
+            filter: Filter::default(),
+            memory_mode: MemoryMode::default(),
+            editing_filter: false,
+            view: View::Processes,
+            connections: Vec::new(),
+            environment: None,
+            selected: 0,
+            scroll: 0,
+            selected_connection: 0,
+            connection_scroll: 0,
+            selected_column: 0,
+            column_scroll: 0,
+            list_height: 0,
+            confirm_end_task: None,
+            choosing_priority: None,
+            editing_affinity: None,
+            message: None,
+            clipboard: None,
+        })
+    }
+
+    fn selected_process(&self) -> Option<&Rc<Process>> {
+        self.rows.get(self.selected).map(|row| &row.process)
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        if self.replay_finished {
+            return Ok(());
+        }
+        let selected_pid = self.selected_process().map(|p| p.pid);
+
+        let result = process::get_processes(self.source.as_mut(), &self.pid_map, self.num_cpus);
+        let new_pid_map = match result {
+            Ok(new_pid_map) => new_pid_map,
+            // Keep showing the last tick of a finished replay
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                self.replay_finished = true;
+                return Ok(());
+            }
+            // Like a stalled or lost agent, which leaves the last tick up
+            Err(e) => {
+                self.message = Some(format!("Couldn't refresh: {}", e));
+                return Ok(());
+            }
+        };
+        self.system_status = self
+            .source
+            .system_status()
+            .unwrap_or_else(|| system::get_system_status(&mut self.cpu_sampler));
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(new_pid_map.values().map(Rc::as_ref), &self.system_status)?;
+        }
+
+        self.pid_map = new_pid_map;
+        self.rebuild_rows_from(selected_pid);
+        if self.view == View::Connections {
+            self.refresh_connections();
+        }
+        Ok(())
+    }
+
+    // Falls back to the process list if the source can't list connections
+    fn refresh_connections(&mut self) {
+        match self.source.connections() {
+            Ok(connections) => {
+                self.connections = connection::connection_rows(connections, &self.pid_map);
+                self.move_connection_selection(0);
+            }
+            Err(e) => {
+                self.view = View::Processes;
+                self.message = Some(format!("Failed to list connections: {}", e));
+            }
+        }
+    }
+
+    fn selected_connection_pid(&self) -> Option<u32> {
+        self.connections
+            .get(self.selected_connection)
+            .and_then(|row| row.connection.pid)
+    }
+
+    // After the sort order, tree mode, the filter or a collapsed row changes
+    fn rebuild_rows(&mut self) {
+        let selected_pid = self.selected_process().map(|p| p.pid);
+        self.rebuild_rows_from(selected_pid);
+    }
+
+    fn rebuild_rows_from(&mut self, selected_pid: Option<u32>) {
+        self.rows = if self.tree_mode {
+            rows::tree_rows(
+                &self.pid_map,
+                self.sort_state,
+                &self.collapsed,
+                &self.filter,
+                self.memory_mode,
+            )
+        } else {
+            rows::flat_rows(
+                &self.pid_map,
+                self.sort_state,
+                &self.filter,
+                self.memory_mode,
+            )
+        };
+        self.restore_selection(selected_pid);
+    }
+
+    // Right expands the selected row and Left collapses it, or on a row
+    // that's already collapsed, moves up to its parent like a tree control
+    fn set_expanded(&mut self, expanded: bool) {
+        let Some(row) = self.rows.get(self.selected) else {
+            return;
+        };
+        let pid = row.process.pid;
+        let parent_pid = row.process.parent_pid;
+        if row.expanded == Some(!expanded) {
+            if expanded {
+                self.collapsed.remove(&pid);
+            } else {
+                self.collapsed.insert(pid);
+            }
+            self.rebuild_rows();
+        } else if !expanded && row.depth.is_some_and(|depth| depth > 0) {
+            self.restore_selection(Some(parent_pid));
+        }
+    }
+
+    // Keeps the same process selected as rows move around, or stays at the
+    // same position if it has exited
+    fn restore_selection(&mut self, selected_pid: Option<u32>) {
+        if let Some(index) =
+            selected_pid.and_then(|pid| self.rows.iter().position(|r| r.process.pid == pid))
+        {
+            self.selected = index;
+        }
+        self.move_selection(0);
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        move_within(
+            &mut self.selected,
+            &mut self.scroll,
+            self.rows.len(),
+            self.list_height,
+            delta,
+        );
+    }
+
+    fn move_connection_selection(&mut self, delta: isize) {
+        move_within(
+            &mut self.selected_connection,
+            &mut self.connection_scroll,
+            self.connections.len(),
+            self.list_height,
+            delta,
+        );
+    }
+
+    fn move_column_selection(&mut self, delta: isize) {
+        move_within(
+            &mut self.selected_column,
+            &mut self.column_scroll,
+            COLUMNS.len(),
+            self.list_height,
+            delta,
+        );
+    }
+
+    // Shows or hides the highlighted column, keeping display order and at
+    // least one column
+    fn toggle_column(&mut self) {
+        let toggled = &COLUMNS[self.selected_column];
+        let is_shown = |column: &Column| self.columns.iter().any(|c| c.key == column.key);
+        let showing = !is_shown(toggled);
+        if !showing && self.columns.len() == 1 {
+            self.message = Some("The process list needs at least one column".to_string());
+            return;
+        }
+        let columns = COLUMNS
+            .iter()
+            .filter(|&column| {
+                if column.key == toggled.key {
+                    showing
+                } else {
+                    is_shown(column)
+                }
+            })
+            .collect();
+        self.columns = columns;
+    }
+
+    // '<' and '>' sort by the shown column left or right of the current one,
+    // which reaches the columns past the ninth
+    fn sort_by_neighbour(&mut self, delta: isize) {
+        let len = self.columns.len() as isize;
+        let index = match self
+            .columns
+            .iter()
+            .position(|c| c.sort_key == self.sort_state.key())
+        {
+            Some(index) => (index as isize + delta).rem_euclid(len) as usize,
+            None => 0,
+        };
+        self.sort_state = self.sort_state.with_key(self.columns[index].sort_key);
+        self.rebuild_rows();
+    }
+
+    fn end_task(&mut self, pid: u32) {
+        // Failures are shown in the footer rather than lost behind the screen
+        self.message = match self.source.kill(pid) {
+            Ok(()) => Some(format!("Ended process {}", pid)),
+            Err(e) => Some(format!("Failed to end process {}: {}", pid, e)),
+        };
+    }
+
+    fn set_priority(&mut self, pid: u32, priority: Priority) {
+        let name = self.pid_map.get(&pid).map_or("", |p| p.image_name.as_str());
+        self.message = match self.source.set_priority(pid, priority) {
+            Ok(()) => Some(format!(
+                "Set the priority of {} ({}) to {}",
+                name,
+                pid,
+                priority.name()
+            )),
+            Err(e) => Some(format!(
+                "Failed to set the priority of {} ({}): {}",
+                name, pid, e
+            )),
+        };
+    }
+
+    fn start_editing_affinity(&mut self) {
+        let Some(pid) = self.selected_process().map(|p| p.pid) else {
+            return;
+        };
+        match self.source.affinity(pid) {
+            Ok(cpus) => self.editing_affinity = Some((pid, format_cpu_list(&cpus))),
+            Err(e) => self.message = Some(format!("Failed to get the CPU affinity: {}", e)),
+        }
+    }
+
+    // Enter sets the typed CPUs, Escape leaves them as they were
+    fn edit_affinity(&mut self, key: Key) {
+        let Some((pid, text)) = self.editing_affinity.as_mut() else {
+            return;
+        };
+        match key {
+            Key::Char('\r' | '\n') => {
+                let (pid, text) = (*pid, text.clone());
+                self.editing_affinity = None;
+                self.set_affinity(pid, &text);
+            }
+            Key::Escape | Key::Char('\x03') => self.editing_affinity = None,
+            Key::Char('\x7f' | '\x08') => {
+                text.pop();
+            }
+            Key::Char(c @ ('0'..='9' | ',' | '-')) => text.push(c),
+            _ => {}
+        }
+    }
+
+    fn set_affinity(&mut self, pid: u32, text: &str) {
+        let cpus = match parse_cpu_list(text) {
+            Some(cpus) if cpus.is_empty() => {
+                self.message = Some("Pick at least one CPU".to_string());
+                return;
+            }
+            Some(cpus) => cpus,
+            None => {
+                self.message = Some(format!("Not a list of CPUs: {}", text));
+                return;
+            }
+        };
+        let name = self.pid_map.get(&pid).map_or("", |p| p.image_name.as_str());
+        self.message = match self.source.set_affinity(pid, &cpus) {
+            Ok(()) => Some(format!(
+                "{} ({}) may now run on CPUs {}",
+                name,
+                pid,
+                format_cpu_list(&cpus)
+            )),
+            Err(e) => Some(format!(
+                "Failed to set the CPU affinity of {} ({}): {}",
+                name, pid, e
+            )),
+        };
+    }
+
+    fn open_file_location(&mut self) {
+        let Some(process) = self.selected_process().cloned() else {
+            return;
+        };
+        self.message = match self.source.open_file_location(&process) {
+            Ok(()) => Some(format!("Opened the folder of {}", process.image_path)),
+            Err(e) => Some(format!("Failed to open file location: {}", e)),
+        };
+    }
+
+    fn handle_columns_key(&mut self, key: Key) {
+        let page = self.list_height.max(1) as isize;
+        match key {
+            Key::Up => self.move_column_selection(-1),
+            Key::Down => self.move_column_selection(1),
+            Key::PageUp => self.move_column_selection(-page),
+            Key::PageDown => self.move_column_selection(page),
+            Key::Home => self.move_column_selection(isize::MIN),
+            Key::End => self.move_column_selection(isize::MAX),
+            Key::WheelUp => self.move_column_selection(-3),
+            Key::WheelDown => self.move_column_selection(3),
+            Key::Click { row, .. } if row >= HEADER_ROWS => {
+                let index = self.column_scroll + row - HEADER_ROWS;
+                if row < HEADER_ROWS + self.list_height && index < COLUMNS.len() {
+                    self.selected_column = index;
+                    self.toggle_column();
+                }
+            }
+            Key::Char(' ' | '\r' | '\n') => self.toggle_column(),
+            Key::Char('f' | 'q') | Key::Escape | Key::Char('\x03') => self.view = View::Processes,
+            _ => {}
+        }
+    }
+
+    fn show_security_details(&mut self) {
+        let Some(process) = self.selected_process() else {
+            return;
+        };
+        self.message = Some(format!(
+            "{} ({}): {}",
+            process.image_name,
+            process.pid,
+            status_text::security_details(&process.security, "  ")
+        ));
+    }
+
+    fn open_environment(&mut self) {
+        let Some(process) = self.selected_process().cloned() else {
+            return;
+        };
+        match self.source.environment(process.pid) {
+            Ok(variables) => {
+                self.environment = Some(EnvironmentView {
+                    pid: process.pid,
+                    image_name: process.image_name.clone(),
+                    variables,
+                    filter: String::new(),
+                    editing_filter: false,
+                    export_path: None,
+                    selected: 0,
+                    scroll: 0,
+                });
+                self.view = View::Environment;
+            }
+            Err(e) => {
+                self.message = Some(format!(
+                    "Failed to read the environment of {} ({}): {}",
+                    process.image_name, process.pid, e
+                ))
+            }
+        }
+    }
+
+    fn move_variable_selection(&mut self, delta: isize) {
+        let list_height = self.list_height;
+        if let Some(view) = &mut self.environment {
+            let len = view.shown().len();
+            move_within(
+                &mut view.selected,
+                &mut view.scroll,
+                len,
+                list_height,
+                delta,
+            );
+        }
+    }
+
+    // 'y' copies the selected variable and 'Y' all the shown ones
+    fn copy_variables(&mut self, all: bool) {
+        let Some(view) = &self.environment else {
+            return;
+        };
+        let shown = view.shown();
+        let variables = if all {
+            shown
+        } else {
+            shown.get(view.selected).into_iter().copied().collect()
+        };
+        if variables.is_empty() {
+            return;
+        }
+        self.message = Some(match variables.as_slice() {
+            [variable] => format!("Copied {}", variable.name),
+            _ => format!("Copied {} variables", variables.len()),
+        });
+        self.clipboard = Some(environment::to_text(&variables, "\n"));
+    }
+
+    fn export_variables(&mut self, path: &str) {
+        let Some(view) = &self.environment else {
+            return;
+        };
+        let shown = view.shown();
+        self.message = match fs::write(path, environment::to_text(&shown, "\n")) {
+            Ok(()) => Some(format!("Exported {} variables to {}", shown.len(), path)),
+            Err(e) => Some(format!("Failed to export to {}: {}", path, e)),
+        };
+    }
+
+    // Enter keeps the search or exports, Escape clears or cancels. Returns
+    // false when neither is being typed.
+    fn edit_environment_footer(&mut self, key: Key) -> bool {
+        let Some(view) = &mut self.environment else {
+            return false;
+        };
+        if let Some(path) = &mut view.export_path {
+            match key {
+                Key::Char('\r' | '\n') => {
+                    let path = path.clone();
+                    view.export_path = None;
+                    self.export_variables(&path);
+                }
+                Key::Escape | Key::Char('\x03') => view.export_path = None,
+                Key::Char('\x7f' | '\x08') => {
+                    path.pop();
+                }
+                Key::Char(c) if !c.is_control() => path.push(c),
+                _ => {}
+            }
+            return true;
+        }
+        if !view.editing_filter {
+            return false;
+        }
+        match key {
+            Key::Char('\r' | '\n') => view.editing_filter = false,
+            Key::Escape | Key::Char('\x03') => {
+                view.editing_filter = false;
+                view.filter.clear();
+            }
+            Key::Char('\x7f' | '\x08') => {
+                view.filter.pop();
+            }
+            Key::Char(c) if !c.is_control() => view.filter.push(c),
+            _ => {}
+        }
+        self.move_variable_selection(0);
+        true
+    }
+
+    fn handle_environment_key(&mut self, key: Key) {
+        if self.edit_environment_footer(key) {
+            return;
+        }
+        self.message = None;
+        let page = self.list_height.max(1) as isize;
+        match key {
+            Key::Up => self.move_variable_selection(-1),
+            Key::Down => self.move_variable_selection(1),
+            Key::PageUp => self.move_variable_selection(-page),
+            Key::PageDown => self.move_variable_selection(page),
+            Key::Home => self.move_variable_selection(isize::MIN),
+            Key::End => self.move_variable_selection(isize::MAX),
+            Key::WheelUp => self.move_variable_selection(-3),
+            Key::WheelDown => self.move_variable_selection(3),
+            Key::Click { row, .. }
+                if row >= HEADER_ROWS && row < HEADER_ROWS + self.list_height =>
+            {
+                if let Some(view) = &mut self.environment {
+                    let index = view.scroll + row - HEADER_ROWS;
+                    if index < view.shown().len() {
+                        view.selected = index;
+                    }
+                }
+            }
+            Key::Char('/') => {
+                if let Some(view) = &mut self.environment {
+                    view.editing_filter = true;
+                }
+            }
+            Key::Char('y') => self.copy_variables(false),
+            Key::Char('Y') => self.copy_variables(true),
+            Key::Char('x') => {
+                if let Some(view) = &mut self.environment {
+                    view.export_path = Some(format!("{}-{}.env", view.image_name, view.pid));
+                }
+            }
+            Key::Char('v' | 'q') | Key::Escape | Key::Char('\x03') => {
+                self.view = View::Processes;
+                self.environment = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn on_click(&mut self, column: usize, row: usize, screen_width: usize) {
+        if row < HEADER_ROWS {
+            let clicked = process_layout(&self.columns, screen_width)
+                .iter()
+                .position(|&(start, width)| column >= start && column < start + width);
+            if let Some(index) = clicked {
+                self.sort_state = self.sort_state.toggled(self.columns[index].sort_key);
+                self.rebuild_rows();
+            }
+        } else if row < HEADER_ROWS + self.list_height {
+            let index = self.scroll + row - HEADER_ROWS;
+            if index < self.rows.len() {
+                self.selected = index;
+            }
+        }
+    }
+
+    // The list follows the filter as it's typed. Enter keeps it, Escape
+    // clears it.
+    fn edit_filter(&mut self, key: Key) {
+        match key {
+            Key::Char('\r' | '\n') => self.editing_filter = false,
+            Key::Escape | Key::Char('\x03') => {
+                self.editing_filter = false;
+                self.filter.text.clear();
+            }
+            Key::Char('\x7f' | '\x08') => {
+                self.filter.text.pop();
+            }
+            Key::Char(c) if !c.is_control() => self.filter.text.push(c),
+            _ => return,
+        }
+        self.rebuild_rows();
+    }
+
+    // Moving through the connections and ending their owners. Returns false
+    // for keys that mean the same as in the process list.
+    fn handle_connections_key(&mut self, key: Key) -> bool {
+        let page = self.list_height.max(1) as isize;
+        match key {
+            Key::Up => self.move_connection_selection(-1),
+            Key::Down => self.move_connection_selection(1),
+            Key::PageUp => self.move_connection_selection(-page),
+            Key::PageDown => self.move_connection_selection(page),
+            Key::Home => self.move_connection_selection(isize::MIN),
+            Key::End => self.move_connection_selection(isize::MAX),
+            Key::WheelUp => self.move_connection_selection(-3),
+            Key::WheelDown => self.move_connection_selection(3),
+            Key::Click { row, .. } if row >= HEADER_ROWS => {
+                let index = self.connection_scroll + row - HEADER_ROWS;
+                if row < HEADER_ROWS + self.list_height && index < self.connections.len() {
+                    self.selected_connection = index;
+                }
+            }
+            Key::Delete | Key::Char('k') => self.confirm_end_task = self.selected_connection_pid(),
+            Key::Char('c') => self.view = View::Processes,
+            Key::Char('q') | Key::Escape | Key::Char('\x03') => return false,
+            _ => {}
+        }
+        true
+    }
+
+    fn handle_key(&mut self, key: Key, screen_width: usize) -> Action {
+        if let Some(pid) = self.confirm_end_task.take() {
+            if key == Key::Char('y') || key == Key::Char('Y') {
+                self.end_task(pid);
+            }
+            return Action::Continue;
+        }
+        if let Some(pid) = self.choosing_priority.take() {
+            if let Key::Char(c @ '1'..='6') = key {
+                self.set_priority(pid, Priority::ALL[c as usize - '1' as usize]);
+            }
+            return Action::Continue;
+        }
+        if self.editing_filter {
+            self.edit_filter(key);
+            return Action::Continue;
+        }
+        if self.editing_affinity.is_some() {
+            self.edit_affinity(key);
+            return Action::Continue;
+        }
+        if self.view == View::Environment {
+            self.handle_environment_key(key);
+            return Action::Continue;
+        }
+        self.message = None;
+        if self.view == View::Columns {
+            self.handle_columns_key(key);
+            return Action::Continue;
+        }
+        if self.view == View::Connections && self.handle_connections_key(key) {
+            return Action::Continue;
+        }
+
+        let page = self.list_height.max(1) as isize;
+        match key {
+            Key::Char('q') | Key::Escape | Key::Char('\x03') => return Action::Quit,
+            Key::Up => self.move_selection(-1),
+            Key::Down => self.move_selection(1),
+            Key::Left | Key::Char('-') => self.set_expanded(false),
+            Key::Right | Key::Char('+') => self.set_expanded(true),
+            Key::Char('t') => {
+                self.tree_mode = !self.tree_mode;
+                self.rebuild_rows();
+            }
+            Key::Char('/') => self.editing_filter = true,
+            Key::Char('m') => {
+                self.filter.user = match self.filter.user {
+                    Some(_) => None,
+                    None => Some(process::sampling_user(self.source.as_ref())),
+                };
+                self.rebuild_rows();
+            }
+            Key::Char('e') => {
+                self.filter.elevated_only = !self.filter.elevated_only;
+                self.rebuild_rows();
+            }
+            // PSS and USS show up from the next refresh, which measures them
+            Key::Char('M') => {
+                self.memory_mode = self.memory_mode.next();
+                self.source.set_memory_mode(self.memory_mode);
+                self.rebuild_rows();
+            }
+            Key::Char('u') => {
+                self.view = match self.view {
+                    View::Users => View::Processes,
+                    _ => View::Users,
+                }
+            }
+            Key::Char('c') => {
+                self.view = View::Connections;
+                self.refresh_connections();
+            }
+            Key::Char('f') => self.view = View::Columns,
+            Key::PageUp => self.move_selection(-page),
+            Key::PageDown => self.move_selection(page),
+            Key::Home => self.move_selection(isize::MIN),
+            Key::End => self.move_selection(isize::MAX),
+            Key::WheelUp => self.move_selection(-3),
+            Key::WheelDown => self.move_selection(3),
+            Key::Delete | Key::Char('k') => {
+                self.confirm_end_task = self.selected_process().map(|p| p.pid);
+            }
+            Key::Char('o') => self.open_file_location(),
+            Key::Char('p') => self.choosing_priority = self.selected_process().map(|p| p.pid),
+            Key::Char('a') => self.start_editing_affinity(),
+            Key::Char('i') => self.show_security_details(),
+            Key::Char('v') => self.open_environment(),
+            Key::Char(c @ '1'..='9') => {
+                let index = c as usize - '1' as usize;
+                if let Some(column) = self.columns.get(index) {
+                    self.sort_state = self.sort_state.toggled(column.sort_key);
+                    self.rebuild_rows();
+                }
+            }
+            Key::Char('<') => self.sort_by_neighbour(-1),
+            Key::Char('>') => self.sort_by_neighbour(1),
+            Key::Click { column, row } => self.on_click(column, row, screen_width),
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    fn header(&self, layout: &[(usize, usize)]) -> String {
+        let titles: Vec<String> = self
+            .columns
+            .iter()
+            .map(|column| {
+                let title = column::title(column, self.memory_mode);
+                if column.sort_key != self.sort_state.key() {
+                    return title;
+                }
+                match self.sort_state {
+                    SortState::SortUp(_) => format!("{} \u{25b2}", title),
+                    SortState::SortDown(_) => format!("{} \u{25bc}", title),
+                }
+            })
+            .collect();
+        let align_right: Vec<bool> = self.columns.iter().map(|c| c.align_right).collect();
+        format_row(&titles, &align_right, layout)
+    }
+
+    fn status_line(&self) -> String {
+        [
+            status_text::process_count(self.pid_map.len()),
+            status_text::cpu_usage(&self.system_status),
+            status_text::memory_usage(&self.system_status),
+            status_text::process_memory(
+                self.memory_mode,
+                rows::memory_total(&self.pid_map, self.memory_mode),
+            ),
+        ]
+        .join("    ")
+    }
+
+    fn footer(&self) -> String {
+        if let Some(pid) = self.confirm_end_task {
+            let name = self.pid_map.get(&pid).map_or("", |p| p.image_name.as_str());
+            if self.view == View::Connections {
+                return format!("End owning process {} ({})? [y/N]", name, pid);
+            }
+            return format!("End task {} ({})? [y/N]", name, pid);
+        }
+        if let Some(pid) = self.choosing_priority {
+            let name = self.pid_map.get(&pid).map_or("", |p| p.image_name.as_str());
+            let choices: Vec<String> = Priority::ALL
+                .iter()
+                .enumerate()
+                .map(|(index, priority)| format!("{}: {}", index + 1, priority.name()))
+                .collect();
+            return format!(
+                "Priority of {} ({})?  {}  Esc: cancel",
+                name,
+                pid,
+                choices.join("  ")
+            );
+        }
+        if let Some((pid, text)) = &self.editing_affinity {
+            let name = self.pid_map.get(pid).map_or("", |p| p.image_name.as_str());
+            return format!(
+                "CPUs for {} ({}), of 0-{}: {}_    Enter: set   Esc: cancel",
+                name,
+                pid,
+                self.num_cpus.saturating_sub(1),
+                text
+            );
+        }
+        if self.editing_filter {
+            return format!("Filter: {}_    Enter: keep   Esc: clear", self.filter.text);
+        }
+        if let Some(view) = &self.environment {
+            if let Some(path) = &view.export_path {
+                return format!("Export to: {}_    Enter: save   Esc: cancel", path);
+            }
+            if view.editing_filter {
+                return format!("Search: {}_    Enter: keep   Esc: clear", view.filter);
+            }
+        }
+        if let Some(message) = &self.message {
+            return message.clone();
+        }
+        if self.view == View::Connections {
+            return "k/Del: end owning process  c: back to processes  q: quit".to_string();
+        }
+        if self.view == View::Columns {
+            return "Space/Enter/click: show or hide  f/Esc: back to processes".to_string();
+        }
+        if let Some(view) = &self.environment {
+            let mut help = format!(
+                "Environment of {} ({})    /: search  y: copy  Y: copy all shown  \
+                 x: export shown  Esc: back to processes",
+                view.image_name, view.pid
+            );
+            if !view.filter.is_empty() {
+                help = format!("Search: {}    {}", view.filter, help);
+            }
+            return help;
+        }
+        let mut help = format!(
+            "1-{}/</>/click header: sort  f: columns  t: tree  /: filter  m: mine  e: elevated  \
+             M: memory mode  u: users  c: connections  k/Del: end task  p: priority  \
+             a: affinity  i: security details  v: environment  o: open location  q: quit",
+            self.columns.len().min(9)
+        );
+        if !self.filter.text.is_empty() {
+            help = format!("Filter: {}    {}", self.filter.text, help);
+        }
+        if self.filter.elevated_only {
+            help = format!("Only elevated    {}", help);
+        }
+        if let Some(user) = &self.filter.user {
+            help = format!("Only {}    {}", user, help);
+        }
+        if self.replay_finished {
+            format!("End of recording    {}", help)
+        } else {
+            help
+        }
+    }
+
+    fn render(&mut self, width: usize, height: usize) -> String {
+        self.list_height = height.saturating_sub(HEADER_ROWS + FOOTER_ROWS);
+        self.move_selection(0);
+        self.move_connection_selection(0);
+        self.move_variable_selection(0);
+        self.move_column_selection(0);
+
+        let mut lines = Vec::with_capacity(height);
+        match self.view {
+            View::Processes => self.render_processes(width, &mut lines),
+            View::Users => self.render_users(width, &mut lines),
+            View::Connections => self.render_connections(width, &mut lines),
+            View::Environment => self.render_environment(width, &mut lines),
+            View::Columns => self.render_columns(width, &mut lines),
+        }
+
+        lines.push(format!(
+            "\x1b[7m{}\x1b[0m",
+            fit(&self.status_line(), width, false)
+        ));
+        lines.push(fit(&self.footer(), width, false));
+
+        // Redraw in place, clearing whatever a shorter line leaves behind
+        let mut frame = String::from("\x1b[H");
+        frame.push_str(&lines.join("\x1b[K\r\n"));
+        frame.push_str("\x1b[K");
+        frame
+    }
+
+    fn render_processes(&self, width: usize, lines: &mut Vec<String>) {
+        let layout = process_layout(&self.columns, width);
+        let align_right: Vec<bool> = self.columns.iter().map(|c| c.align_right).collect();
+        lines.push(format!(
+            "\x1b[1;7m{}\x1b[0m",
+            fit(&self.header(&layout), width, false)
+        ));
+
+        for index in self.scroll..self.scroll + self.list_height {
+            let Some(row) = self.rows.get(index) else {
+                lines.push(String::new());
+                continue;
+            };
+            let cells: Vec<String> = self.columns.iter().map(|c| (c.text)(row)).collect();
+            let row = fit(&format_row(&cells, &align_right, &layout), width, false);
+            if index == self.selected {
+                lines.push(format!("\x1b[7m{}\x1b[0m", row));
+            } else {
+                lines.push(row);
+            }
+        }
+    }
+
+    fn render_connections(&self, width: usize, lines: &mut Vec<String>) {
+        let layout = connection_layout(width);
+        let align_right: Vec<bool> = CONNECTION_COLUMNS.iter().map(|c| c.align_right).collect();
+        let titles: Vec<String> = CONNECTION_COLUMNS
+            .iter()
+            .map(|c| c.title.to_string())
+            .collect();
+        let header = format_row(&titles, &align_right, &layout);
+        lines.push(format!("\x1b[1;7m{}\x1b[0m", fit(&header, width, false)));
+
+        for index in self.connection_scroll..self.connection_scroll + self.list_height {
+            let Some(row) = self.connections.get(index) else {
+                lines.push(String::new());
+                continue;
+            };
+            let cells: Vec<String> = CONNECTION_COLUMNS.iter().map(|c| (c.text)(row)).collect();
+            let row = fit(&format_row(&cells, &align_right, &layout), width, false);
+            if index == self.selected_connection {
+                lines.push(format!("\x1b[7m{}\x1b[0m", row));
+            } else {
+                lines.push(row);
+            }
+        }
+    }
+
+    fn render_environment(&self, width: usize, lines: &mut Vec<String>) {
+        let Some(view) = &self.environment else {
+            return;
+        };
+        let value_width = width.saturating_sub(VARIABLE_NAME_WIDTH + COLUMN_GAP);
+        let format_line = |name: &str, value: &str| {
+            format!(
+                "{}{}{}",
+                fit(name, VARIABLE_NAME_WIDTH, false),
+                " ".repeat(COLUMN_GAP),
+                fit(value, value_width, false)
+            )
+        };
+        lines.push(format!(
+            "\x1b[1;7m{}\x1b[0m",
+            fit(&format_line("Name", "Value"), width, false)
+        ));
+
+        let shown = view.shown();
+        for index in view.scroll..view.scroll + self.list_height {
+            let Some(variable) = shown.get(index) else {
+                lines.push(String::new());
+                continue;
+            };
+            let line = fit(&format_line(&variable.name, &variable.value), width, false);
+            if index == view.selected {
+                lines.push(format!("\x1b[7m{}\x1b[0m", line));
+            } else {
+                lines.push(line);
+            }
+        }
+    }
+
+    // Every column with whether the process list shows it, ticked like a
+    // checkbox
+    fn render_columns(&self, width: usize, lines: &mut Vec<String>) {
+        lines.push(format!(
+            "\x1b[1;7m{}\x1b[0m",
+            fit("Process list columns", width, false)
+        ));
+        for index in self.column_scroll..self.column_scroll + self.list_height {
+            let Some(column) = COLUMNS.get(index) else {
+                lines.push(String::new());
+                continue;
+            };
+            let shown = self.columns.iter().any(|c| c.key == column.key);
+            let line = format!(
+                "[{}] {}",
+                if shown { 'x' } else { ' ' },
+                column::title(column, self.memory_mode)
+            );
+            let line = fit(&line, width, false);
+            if index == self.selected_column {
+                lines.push(format!("\x1b[7m{}\x1b[0m", line));
+            } else {
+                lines.push(line);
+            }
+        }
+    }
+
+    // CPU and memory added up for each user, in place of the process list
+    fn render_users(&self, width: usize, lines: &mut Vec<String>) {
+        let format_line = |user: &str, processes: &str, cpu: &str, memory: &str| {
+            format!(
+                "{}  {}  {}  {}",
+                fit(user, USER_WIDTH, false),
+                fit(processes, 9, true),
+                fit(cpu, 5, true),
+                fit(memory, 11, true)
+            )
+        };
+        let header = format_line("User", "Processes", "CPU", "Memory");
+        lines.push(format!("\x1b[1;7m{}\x1b[0m", fit(&header, width, false)));
+
+        let totals = rows::user_totals(&self.pid_map, self.memory_mode);
+        for index in 0..self.list_height {
+            let line = totals.get(index).map_or_else(String::new, |total| {
+                format_line(
+                    &total.user,
+                    &total.processes.to_string(),
+                    &total.cpu_usage.to_string(),
+                    &human_bytes(total.memory as f64),
+                )
+            });
+            lines.push(fit(&line, width, false));
+        }
+    }
+}
+
+pub fn run(options: &SourceOptions) -> Result<()> {
+    let mut app = App::new(options)?;
+    let mut terminal = Terminal::enter()?;
+    app.refresh()?;
+
+    let refresh_interval = Duration::from_millis(REFRESH_INTERVAL_MS as u64);
+    let mut next_refresh = Instant::now() + refresh_interval;
+    loop {
+        let (width, height) = terminal.size();
+        terminal.draw(&app.render(width, height))?;
+
+        let timeout = next_refresh.saturating_duration_since(Instant::now());
+        for key in terminal.read_keys(timeout)? {
+            if app.handle_key(key, width) == Action::Quit {
+                return Ok(());
+            }
+        }
+        if let Some(text) = app.clipboard.take() {
+            terminal.copy(&text)?;
+        }
+
+        if Instant::now() >= next_refresh {
+            app.refresh()?;
+            next_refresh = Instant::now() + refresh_interval;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_pads_and_truncates() {
+        assert_eq!(fit("abc", 5, false), "abc  ");
+        assert_eq!(fit("abc", 5, true), "  abc");
+        assert_eq!(fit("abcdef", 4, false), "abcd");
+    }
+
+    #[test]
+    fn fit_replaces_control_characters() {
+        assert_eq!(fit("a\x1b[2Jb", 10, false), "a?[2Jb    ");
+        assert_eq!(fit("\x1b]52;c;eA==\x07", 14, false), "?]52;c;eA==?  ");
+        assert_eq!(fit("two\nlines\u{85}", 10, false), "two?lines?");
+    }
+}
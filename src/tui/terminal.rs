@@ -0,0 +1,229 @@
+use std::{
+    io::{self, Error, Result, Write},
+    mem::MaybeUninit,
+    time::Duration,
+};
+
+// Alternate screen, hidden cursor and SGR mouse reporting for header clicks
+const ENTER_SEQUENCE: &str = "\x1b[?1049h\x1b[?25l\x1b[?1000h\x1b[?1006h";
+const LEAVE_SEQUENCE: &str = "\x1b[?1006l\x1b[?1000l\x1b[?25h\x1b[?1049l";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Delete,
+    Escape,
+    Char(char),
+    // Zero-based cell of a left button press
+    Click { column: usize, row: usize },
+    WheelUp,
+    WheelDown,
+}
+
+pub struct Terminal {
+    original_mode: libc::termios,
+}
+
+impl Terminal {
+    // Puts the terminal into raw mode until the returned value is dropped
+    pub fn enter() -> Result<Terminal> {
+        let mut original_mode = MaybeUninit::<libc::termios>::uninit();
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, original_mode.as_mut_ptr()) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        let original_mode = unsafe { original_mode.assume_init() };
+
+        let mut raw_mode = original_mode;
+        unsafe { libc::cfmakeraw(&mut raw_mode) };
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &raw_mode) } != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let terminal = Terminal { original_mode };
+        let mut out = io::stdout().lock();
+        out.write_all(ENTER_SEQUENCE.as_bytes())?;
+        out.flush()?;
+        Ok(terminal)
+    }
+
+    // (columns, rows), falling back on the classic 80x24
+    pub fn size(&self) -> (usize, usize) {
+        let mut size = MaybeUninit::<libc::winsize>::zeroed();
+        let result =
+            unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, size.as_mut_ptr()) };
+        let size = unsafe { size.assume_init() };
+        if result != 0 || size.ws_col == 0 || size.ws_row == 0 {
+            (80, 24)
+        } else {
+            (size.ws_col as usize, size.ws_row as usize)
+        }
+    }
+
+    pub fn draw(&mut self, frame: &str) -> Result<()> {
+        let mut out = io::stdout().lock();
+        out.write_all(frame.as_bytes())?;
+        out.flush()
+    }
+
+    // Asks the terminal to put `text` on the clipboard with OSC 52. Terminals
+    // that don't support it ignore the sequence.
+    pub fn copy(&mut self, text: &str) -> Result<()> {
+        let mut out = io::stdout().lock();
+        write!(out, "\x1b]52;c;{}\x07", base64(text.as_bytes()))?;
+        out.flush()
+    }
+
+    // Waits up to `timeout` for input and returns whatever keys arrived
+    pub fn read_keys(&mut self, timeout: Duration) -> Result<Vec<Key>> {
+        let mut poll_fd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+        let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) };
+        if ready < 0 {
+            let err = Error::last_os_error();
+            // A resize (SIGWINCH) interrupts the wait, the caller redraws anyway
+            return if err.kind() == io::ErrorKind::Interrupted {
+                Ok(Vec::new())
+            } else {
+                Err(err)
+            };
+        }
+        if ready == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut buffer = [0u8; 256];
+        let read = unsafe {
+            libc::read(
+                libc::STDIN_FILENO,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        };
+        if read < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(parse_keys(&buffer[..read as usize]))
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let mut out = io::stdout().lock();
+        let _ = out.write_all(LEAVE_SEQUENCE.as_bytes());
+        let _ = out.flush();
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &self.original_mode) };
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |group, (index, &byte)| {
+                group | ((byte as u32) << (16 - 8 * index))
+            });
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(ALPHABET[((group >> (18 - 6 * index)) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// Parses "\x1b[<button;column;row" followed by M (press) or m (release)
+fn parse_mouse(params: &[u8], pressed: bool) -> Option<Key> {
+    let params = std::str::from_utf8(params).ok()?;
+    let mut fields = params.split(';').map(|f| f.parse::<usize>().ok());
+    let button = fields.next()??;
+    let column = fields.next()??.checked_sub(1)?;
+    let row = fields.next()??.checked_sub(1)?;
+    match button {
+        0 if pressed => Some(Key::Click { column, row }),
+        64 => Some(Key::WheelUp),
+        65 => Some(Key::WheelDown),
+        _ => None,
+    }
+}
+
+// Maps the final part of a CSI ("\x1b[") sequence to a key
+fn parse_csi(params: &[u8], terminator: u8) -> Option<Key> {
+    match (params, terminator) {
+        ([], b'A') => Some(Key::Up),
+        ([], b'B') => Some(Key::Down),
+        ([], b'C') => Some(Key::Right),
+        ([], b'D') => Some(Key::Left),
+        ([], b'H') | (b"1" | b"7", b'~') => Some(Key::Home),
+        ([], b'F') | (b"4" | b"8", b'~') => Some(Key::End),
+        (b"3", b'~') => Some(Key::Delete),
+        (b"5", b'~') => Some(Key::PageUp),
+        (b"6", b'~') => Some(Key::PageDown),
+        ([b'<', mouse @ ..], b'M') => parse_mouse(mouse, true),
+        ([b'<', mouse @ ..], b'm') => parse_mouse(mouse, false),
+        _ => None,
+    }
+}
+
+fn parse_keys(input: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            0x1b if input.get(i + 1) == Some(&b'[') => {
+                // Parameters run until the first byte in the final range
+                let start = i + 2;
+                let end = (start..input.len()).find(|&j| (0x40..=0x7e).contains(&input[j]));
+                let Some(end) = end else {
+                    break;
+                };
+                keys.extend(parse_csi(&input[start..end], input[end]));
+                i = end + 1;
+            }
+            0x1b if input.get(i + 1) == Some(&b'O') && i + 2 < input.len() => {
+                keys.extend(match input[i + 2] {
+                    b'H' => Some(Key::Home),
+                    b'F' => Some(Key::End),
+                    _ => None,
+                });
+                i += 3;
+            }
+            0x1b => {
+                keys.push(Key::Escape);
+                i += 1;
+            }
+            _ => {
+                // Typed into the filter, so multi-byte UTF-8 input is decoded
+                // whole. Anything malformed is dropped.
+                let len = match input[i] {
+                    0xc0..=0xdf => 2,
+                    0xe0..=0xef => 3,
+                    0xf0..=0xf7 => 4,
+                    _ => 1,
+                };
+                let end = (i + len).min(input.len());
+                let c = std::str::from_utf8(&input[i..end])
+                    .ok()
+                    .and_then(|s| s.chars().next());
+                keys.extend(c.map(Key::Char));
+                i = end;
+            }
+        }
+    }
+    keys
+}
@@ -1,3 +1,5 @@
+use std::ffi::c_void;
+
 use windows::{
     core::{w, Result, PCWSTR},
     Win32::{
@@ -31,7 +33,11 @@ pub unsafe fn register_class(instance: &HINSTANCE, name: &PCWSTR, wndproc: WndPr
     Ok(())
 }
 
-pub unsafe fn create_window(instance: &HINSTANCE, name: &PCWSTR) -> Result<()> {
+pub unsafe fn create_window(
+    instance: &HINSTANCE,
+    name: &PCWSTR,
+    create_params: *const c_void,
+) -> Result<()> {
     let hwnd = CreateWindowExW(
         WINDOW_EX_STYLE::default(),
         *name,
@@ -44,7 +50,7 @@ pub unsafe fn create_window(instance: &HINSTANCE, name: &PCWSTR) -> Result<()> {
         None,
         None,
         Some(*instance),
-        None,
+        Some(create_params),
     )?;
     let _ = ShowWindow(hwnd, SW_SHOW);
     let _ = UpdateWindow(hwnd);